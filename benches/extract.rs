@@ -0,0 +1,64 @@
+//! Extraction of the built-in tasks from a parsed Solidity contract with many commented functions
+//! calling each other, the per-file work of `sparser extract`
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use sparser::analysis::{find_function_calls, find_function_comments, LanguageSpec};
+use sparser::extract::{Extractor, FileCtx, FuncCallComm, FuncComm};
+use tree_sitter::Parser;
+
+const NUM_FUNCTIONS: usize = 200;
+
+/// A contract whose functions each call the next two and have a body of a few hundred bytes
+fn contract() -> String {
+    let mut src = String::from("pragma solidity ^0.8.0;\n\ncontract Bench {\n");
+    for idx in 0..NUM_FUNCTIONS {
+        src.push_str(&format!(
+            "    /// @notice Step {} of the computation\n    /// @param x the input\n    \
+             function step{}(uint256 x) public pure returns (uint256) {{\n",
+            idx, idx
+        ));
+        for line in 0..8 {
+            src.push_str(&format!("        x = x * {} + {};\n", line, idx));
+        }
+        for callee in [idx + 1, idx + 2].iter().filter(|&&c| c < NUM_FUNCTIONS) {
+            src.push_str(&format!("        x = step{}(x);\n", callee));
+        }
+        src.push_str("        return x;\n    }\n\n");
+    }
+    src.push_str("}\n");
+    src
+}
+
+fn bench_extract(c: &mut Criterion) {
+    let spec = LanguageSpec::solidity();
+    let src = contract();
+    let mut parser = Parser::new();
+    parser.set_language(spec.language).unwrap();
+    let tree = parser.parse(&src, None).unwrap();
+    let root = tree.root_node();
+    let ctx = FileCtx {
+        path: "Bench.sol",
+        tree: &tree,
+        spec,
+        layout: None,
+        include_recursive: false,
+        max_file_lines: None,
+        subtokenizer: None,
+        seed: Some(0),
+    };
+    c.bench_function("find_function_comments", |b| {
+        b.iter(|| find_function_comments(&spec, &src, root).unwrap())
+    });
+    c.bench_function("find_function_calls", |b| {
+        b.iter(|| find_function_calls(&spec, &src, root, |_| true).unwrap())
+    });
+    c.bench_function("func_call_comm", |b| {
+        b.iter(|| FuncCallComm.extract(&src, &ctx).unwrap())
+    });
+    c.bench_function("func_comm", |b| {
+        b.iter(|| FuncComm.extract(&src, &ctx).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_extract);
+criterion_main!(benches);
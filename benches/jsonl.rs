@@ -0,0 +1,71 @@
+//! Parsing of a pair-calls jsonl corpus, lines read into a new `String` each and parsed as text
+//! against lines read into one reused buffer and parsed as bytes, as `pair-calls` reads them
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use sparser::JsonSample;
+use std::io::{BufRead, BufReader, Cursor};
+
+const NUM_SAMPLES: usize = 10_000;
+
+/// A jsonl corpus of python functions with their tokens and docstrings, ten per repo
+fn corpus() -> Vec<u8> {
+    let mut corpus = Vec::new();
+    for idx in 0..NUM_SAMPLES {
+        let code = format!(
+            "def step{}(x):\n    x = x * {} + 1\n    return step{}(x)\n",
+            idx,
+            idx,
+            idx + 1
+        );
+        let sample = JsonSample {
+            func_name: format!("step{}", idx),
+            repo: Some(format!("repo{}", idx / 10)),
+            path: Some(format!("src/step{}.py", idx)),
+            original_string: Some(code.clone()),
+            code_tokens: Some(code.split_whitespace().map(str::to_string).collect()),
+            code,
+            docstring: Some(format!("Step {} of the computation", idx)),
+            docstring_tokens: Some(vec!["Step".to_string(), idx.to_string()]),
+            language: None,
+        };
+        serde_json::to_writer(&mut corpus, &sample).unwrap();
+        corpus.push(b'\n');
+    }
+    corpus
+}
+
+/// The samples of `corpus` read line by line as text
+fn parse_lines(corpus: &[u8]) -> Vec<JsonSample> {
+    BufReader::new(Cursor::new(corpus))
+        .lines()
+        .filter_map(|line| serde_json::from_str(&line.unwrap()).ok())
+        .collect()
+}
+
+/// The samples of `corpus` read into a reused buffer and parsed as bytes
+fn parse_buffered(corpus: &[u8]) -> Vec<JsonSample> {
+    let mut reader = BufReader::with_capacity(1 << 20, Cursor::new(corpus));
+    let mut line = Vec::new();
+    let mut samples = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).unwrap() == 0 {
+            break;
+        }
+        if let Ok(sample) = serde_json::from_slice(line.trim_ascii_end()) {
+            samples.push(sample);
+        }
+    }
+    samples
+}
+
+fn bench_jsonl(c: &mut Criterion) {
+    let corpus = corpus();
+    let mut group = c.benchmark_group("jsonl");
+    group.bench_function("lines_from_str", |b| b.iter(|| parse_lines(&corpus)));
+    group.bench_function("buffer_from_slice", |b| b.iter(|| parse_buffered(&corpus)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_jsonl);
+criterion_main!(benches);
@@ -0,0 +1,880 @@
+//! Analyses of the functions of a syntax tree which the extraction tasks and `pair-calls` are
+//! built on. A `LanguageSpec` holds everything which differs between the supported languages.
+
+use crate::error::Result;
+use crate::layout::CodeLayout;
+use crate::node_text;
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use tree_sitter::{Language, Node, Query, QueryCursor};
+
+extern "C" {
+    fn tree_sitter_solidity() -> Language;
+    fn tree_sitter_php() -> Language;
+}
+
+/// The grammar of a language and the queries run by the analyses
+#[derive(Debug, Clone, Copy)]
+pub struct LanguageSpec {
+    pub name: &'static str,
+    pub language: Language,
+    /// Query capturing the names of called functions as `@function` or `@function.method`
+    pub call_query: &'static str,
+    /// Query capturing functions as `@func_src`, their names as `@name` and their preceding
+    /// comments as `@comment`, `None` if comments are not supported for the language
+    pub comment_query: Option<&'static str>,
+    /// Query capturing class-like declarations as `@class` and their names as `@name`, `None` for
+    /// languages without classes
+    pub class_query: Option<&'static str>,
+    /// Node kind of function definitions, the caller of a call is the innermost one around it
+    pub function_kind: &'static str,
+    /// Field of `function_kind` nodes holding the function name
+    pub name_field: &'static str,
+    /// Kinds of call nodes and their field holding the called function. Only a captured name in
+    /// such a field, or naming the member of a member access in it as the method of
+    /// `obj.method()`, is a call site to mask. Any captured name is for an empty list.
+    pub call_positions: &'static [(&'static str, &'static str)],
+    /// Kinds of nodes adding a path through a function, each a branch, loop, case or handler,
+    /// which the cyclomatic complexity counts besides the `&&` and `||` operators
+    pub branch_kinds: &'static [&'static str],
+    /// Kinds of nodes holding the statements of a block, the nesting depth of a function is how
+    /// deeply they nest in its body
+    pub block_kinds: &'static [&'static str],
+    /// Node kinds of the methods of the classes of `class_query`
+    pub method_kinds: &'static [&'static str],
+    /// Layout of the code of `func_call` samples by default. The built-in languages all keep
+    /// their line breaks, as code collapsed onto one line runs into its line comments and loses
+    /// the indentation which opens blocks.
+    pub default_layout: CodeLayout,
+}
+
+const SOLIDITY_CALL_QUERY: &str = "(
+  (call_expression
+    . (identifier) @function
+  )
+)";
+
+const SOLIDITY_COMMENT_QUERY: &str = "(
+  (comment)+ @comment
+  .
+  (function_definition
+    function_name: ((identifier) @name)
+    body: (
+      (function_body) @func_body
+    )
+  ) @func_src
+)";
+
+const SOLIDITY_CLASS_QUERY: &str = "(contract_declaration (identifier) @name) @class";
+
+const PYTHON_CALL_QUERY: &str = "
+(call
+  function: (attribute attribute: (identifier) @function.method))
+(call
+  function: (identifier) @function)";
+
+const PYTHON_CLASS_QUERY: &str = "(class_definition name: (identifier) @name) @class";
+
+const JAVASCRIPT_CALL_QUERY: &str = "
+(call_expression
+  function: (identifier) @function)
+(call_expression
+  function: (member_expression
+    property: (property_identifier) @function.method))
+";
+const JAVASCRIPT_CLASS_QUERY: &str = "(class_declaration name: (identifier) @name) @class";
+const JAVA_CALL_QUERY: &str = "(method_declaration
+  name: (identifier) @function.method)
+(method_invocation
+  name: (identifier) @function.method)
+";
+const JAVA_CLASS_QUERY: &str = "
+(class_declaration name: (identifier) @name) @class
+(interface_declaration name: (identifier) @name) @class";
+const GO_CALL_QUERY: &str = "
+(call_expression
+  function: (identifier) @function)
+(call_expression
+  function: (selector_expression
+    field: (field_identifier) @function.method))";
+
+const RUBY_CALL_QUERY: &str = "
+(call
+  method: [(identifier) (constant)] @function.method)";
+const RUBY_CLASS_QUERY: &str = "(class name: (constant) @name) @class";
+const PHP_CALL_QUERY: &str = "
+(member_call_expression
+  name: (name) @function.method)
+(function_call_expression
+  function: (qualified_name (name)) @function)
+";
+
+const PHP_CLASS_QUERY: &str = "(class_declaration name: (name) @name) @class";
+
+/// Files of a query directory which override the call, comment and class queries of the language
+/// of the subdirectory they are in, e.g. `python/func_call.scm`
+const CALL_QUERY_FILE: &str = "func_call.scm";
+const COMMENT_QUERY_FILE: &str = "func_comm.scm";
+const CLASS_QUERY_FILE: &str = "class_comm.scm";
+
+impl LanguageSpec {
+    /// The spec with the queries of `query_dir` in place of the built-in ones. The queries are
+    /// checked against the grammar and kept for the rest of the process.
+    pub fn with_query_dir(mut self, query_dir: &Path) -> Result<Self> {
+        let lang_dir = query_dir.join(self.name);
+        if let Some(query) = read_query(&lang_dir.join(CALL_QUERY_FILE))? {
+            self.call_query = query;
+            self.compiled_query(query)?;
+        }
+        if let Some(query) = read_query(&lang_dir.join(COMMENT_QUERY_FILE))? {
+            self.comment_query = Some(query);
+            self.compiled_query(query)?;
+        }
+        if let Some(query) = read_query(&lang_dir.join(CLASS_QUERY_FILE))? {
+            self.class_query = Some(query);
+            self.compiled_query(query)?;
+        }
+        Ok(self)
+    }
+
+    /// `source` compiled for the language, once per process as compiling a query takes longer
+    /// than running it on a file
+    pub fn compiled_query(&self, source: &'static str) -> Result<&'static Query> {
+        let mut queries = QUERIES.lock().unwrap();
+        if let Some(query) = queries.get(&(self.name, source)) {
+            return Ok(query);
+        }
+        let query: &'static Query = Box::leak(Box::new(Query::new(self.language, source)?));
+        queries.insert((self.name, source), query);
+        Ok(query)
+    }
+
+    /// The spec of a language by its name, e.g. `solidity` or `python`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "solidity" => Some(Self::solidity()),
+            "python" => Some(Self::python()),
+            "javascript" => Some(Self::javascript()),
+            "java" => Some(Self::java()),
+            "go" => Some(Self::go()),
+            "ruby" => Some(Self::ruby()),
+            "php" => Some(Self::php()),
+            _ => None,
+        }
+    }
+
+    pub fn solidity() -> Self {
+        LanguageSpec {
+            name: "solidity",
+            language: unsafe { tree_sitter_solidity() },
+            call_query: SOLIDITY_CALL_QUERY,
+            comment_query: Some(SOLIDITY_COMMENT_QUERY),
+            class_query: Some(SOLIDITY_CLASS_QUERY),
+            function_kind: "function_definition",
+            name_field: "function_name",
+            call_positions: &[],
+            branch_kinds: &[
+                "if_statement",
+                "for_statement",
+                "while_statement",
+                "do_while_statement",
+                "catch_clause",
+                "ternary_expression",
+            ],
+            block_kinds: &["function_body", "block_statement"],
+            method_kinds: &["function_definition"],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+
+    pub fn python() -> Self {
+        LanguageSpec {
+            name: "python",
+            language: tree_sitter_python::language(),
+            call_query: PYTHON_CALL_QUERY,
+            comment_query: None,
+            class_query: Some(PYTHON_CLASS_QUERY),
+            function_kind: "function_definition",
+            name_field: "name",
+            call_positions: &[("call", "function")],
+            branch_kinds: &[
+                "if_statement",
+                "elif_clause",
+                "for_statement",
+                "while_statement",
+                "except_clause",
+                "conditional_expression",
+                "for_in_clause",
+                "if_clause",
+            ],
+            block_kinds: &["block"],
+            method_kinds: &["function_definition"],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+
+    pub fn javascript() -> Self {
+        LanguageSpec {
+            name: "javascript",
+            language: tree_sitter_javascript::language(),
+            call_query: JAVASCRIPT_CALL_QUERY,
+            comment_query: None,
+            class_query: Some(JAVASCRIPT_CLASS_QUERY),
+            function_kind: "function_declaration",
+            name_field: "name",
+            call_positions: &[("call_expression", "function")],
+            branch_kinds: &[
+                "if_statement",
+                "for_statement",
+                "for_in_statement",
+                "while_statement",
+                "do_statement",
+                "switch_case",
+                "catch_clause",
+                "ternary_expression",
+            ],
+            block_kinds: &["statement_block", "switch_body"],
+            method_kinds: &["method_definition"],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+
+    pub fn java() -> Self {
+        LanguageSpec {
+            name: "java",
+            language: tree_sitter_java::language(),
+            call_query: JAVA_CALL_QUERY,
+            comment_query: None,
+            class_query: Some(JAVA_CLASS_QUERY),
+            function_kind: "method_declaration",
+            name_field: "name",
+            call_positions: &[("method_invocation", "name")],
+            branch_kinds: &[
+                "if_statement",
+                "for_statement",
+                "enhanced_for_statement",
+                "while_statement",
+                "do_statement",
+                "switch_label",
+                "catch_clause",
+                "ternary_expression",
+            ],
+            block_kinds: &["block", "switch_block"],
+            method_kinds: &["method_declaration", "constructor_declaration"],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+
+    pub fn go() -> Self {
+        LanguageSpec {
+            name: "go",
+            language: tree_sitter_go::language(),
+            call_query: GO_CALL_QUERY,
+            comment_query: None,
+            class_query: None,
+            function_kind: "function_declaration",
+            name_field: "name",
+            call_positions: &[("call_expression", "function")],
+            branch_kinds: &[
+                "if_statement",
+                "for_statement",
+                "expression_case",
+                "type_case",
+                "communication_case",
+            ],
+            block_kinds: &["block"],
+            method_kinds: &[],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+
+    pub fn ruby() -> Self {
+        LanguageSpec {
+            name: "ruby",
+            language: tree_sitter_ruby::language(),
+            call_query: RUBY_CALL_QUERY,
+            comment_query: None,
+            class_query: Some(RUBY_CLASS_QUERY),
+            function_kind: "method",
+            name_field: "name",
+            call_positions: &[("call", "method")],
+            branch_kinds: &[
+                "if",
+                "elsif",
+                "unless",
+                "while",
+                "until",
+                "for",
+                "when",
+                "rescue",
+                "conditional",
+                "if_modifier",
+                "unless_modifier",
+                "while_modifier",
+                "until_modifier",
+                "rescue_modifier",
+            ],
+            block_kinds: &["then", "else", "do", "do_block", "block"],
+            method_kinds: &["method", "singleton_method"],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+
+    pub fn php() -> Self {
+        LanguageSpec {
+            name: "php",
+            language: unsafe { tree_sitter_php() },
+            call_query: PHP_CALL_QUERY,
+            comment_query: None,
+            class_query: Some(PHP_CLASS_QUERY),
+            function_kind: "function_definition",
+            name_field: "name",
+            call_positions: &[
+                ("function_call_expression", "function"),
+                ("member_call_expression", "name"),
+                ("scoped_call_expression", "name"),
+            ],
+            branch_kinds: &[
+                "if_statement",
+                "else_if_clause",
+                "for_statement",
+                "foreach_statement",
+                "while_statement",
+                "do_statement",
+                "case_statement",
+                "catch_clause",
+                "conditional_expression",
+            ],
+            block_kinds: &["compound_statement"],
+            method_kinds: &["method_declaration"],
+            default_layout: CodeLayout::NormalizedIndent,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Compiled queries by language name and query source, shared by all workers
+    static ref QUERIES: Mutex<HashMap<(&'static str, &'static str), &'static Query>> =
+        Default::default();
+}
+
+/// The query of a file of a query directory, `None` if there is no such file
+fn read_query(path: &Path) -> Result<Option<&'static str>> {
+    match fs::read_to_string(path) {
+        Ok(query) => {
+            info!("Using the query of {}", path.display());
+            Ok(Some(Box::leak(query.into_boxed_str())))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Nodes of the called function names below `root` accepted by `func_validate_fn`
+fn called_function_nodes<'tree, 'a, F>(
+    spec: &LanguageSpec,
+    code: &'a str,
+    root: Node<'tree>,
+    func_validate_fn: F,
+) -> Result<Vec<(Node<'tree>, &'a str)>>
+where
+    F: Fn(&str) -> bool,
+{
+    let query = spec.compiled_query(spec.call_query)?;
+    let mut query_cursor = QueryCursor::new();
+    let matches = query_cursor.matches(query, root, |_| code.as_bytes());
+    let mut calls = Vec::new();
+    for m in matches {
+        for capture in m.captures {
+            let capture_name = &query.capture_names()[capture.index as usize];
+            match capture_name.as_str() {
+                "function" | "function.method" => {
+                    let func_name = node_text(capture.node, code);
+                    if func_validate_fn(func_name) {
+                        calls.push((capture.node, func_name));
+                    }
+                }
+                _ => {
+                    debug!("unknown capture_name: {}", capture_name);
+                }
+            }
+        }
+    }
+    Ok(calls)
+}
+
+/// Names of the functions called below `root` which are accepted by `func_validate_fn`
+pub fn find_called_functions<'a, F>(
+    spec: &LanguageSpec,
+    code: &'a str,
+    root: Node,
+    func_validate_fn: F,
+) -> Result<HashSet<&'a str>>
+where
+    F: Fn(&str) -> bool,
+{
+    Ok(called_function_nodes(spec, code, root, func_validate_fn)?
+        .into_iter()
+        .map(|(_, func_name)| func_name)
+        .collect())
+}
+
+/// Byte ranges of the names of the calls below `root` whose callee is accepted by
+/// `func_validate_fn`, in source order
+pub fn find_call_sites<F>(
+    spec: &LanguageSpec,
+    code: &str,
+    root: Node,
+    func_validate_fn: F,
+) -> Result<Vec<Range<usize>>>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut sites: Vec<Range<usize>> = called_function_nodes(spec, code, root, func_validate_fn)?
+        .into_iter()
+        .filter(|(node, _)| is_call_position(spec, *node))
+        .map(|(node, _)| node.byte_range())
+        .collect();
+    sites.sort_by_key(|site| site.start);
+    sites.dedup();
+    Ok(sites)
+}
+
+/// Whether a captured name is in one of the `call_positions` of the language, directly or as the
+/// last part of a member access, rather than e.g. the name of a method declaration
+fn is_call_position(spec: &LanguageSpec, node: Node) -> bool {
+    if spec.call_positions.is_empty() {
+        return true;
+    }
+    let is_called = |node: Node| match node.parent() {
+        Some(call) => spec.call_positions.iter().any(|(kind, field)| {
+            call.kind() == *kind && call.child_by_field_name(field) == Some(node)
+        }),
+        None => false,
+    };
+    if is_called(node) {
+        return true;
+    }
+    match node.parent() {
+        Some(access) => {
+            let num_children = access.named_child_count();
+            num_children > 0
+                && access.named_child(num_children - 1) == Some(node)
+                && is_called(access)
+        }
+        None => false,
+    }
+}
+
+/// The first function or method below `root` in pre-order, `root` itself if it is none
+pub fn function_node<'tree>(root: Node<'tree>, spec: &LanguageSpec) -> Node<'tree> {
+    let mut cursor = root.walk();
+    loop {
+        let kind = cursor.node().kind();
+        if kind == spec.function_kind || spec.method_kinds.contains(&kind) {
+            return cursor.node();
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return root;
+            }
+        }
+    }
+}
+
+/// Whether a top-level node may precede the header comment of a file, such as `pragma` and
+/// `import` directives and `package` clauses
+fn is_preamble(kind: &str) -> bool {
+    ["pragma", "import", "package"]
+        .iter()
+        .any(|part| kind.contains(part))
+}
+
+/// Whether a comment is a license identifier or an interpreter or encoding line rather than a
+/// description of the file
+fn is_boilerplate_comment(text: &str) -> bool {
+    text.contains("SPDX-License-Identifier") || text.starts_with("#!") || text.contains("-*-")
+}
+
+/// The header comment of a file and the byte ranges it is made of: the comments before the first
+/// top-level node other than directives and imports, and in Python the module docstring.
+/// License identifiers and interpreter lines are left out. `None` if the file has no header.
+pub fn find_header_comment(
+    spec: &LanguageSpec,
+    code: &str,
+    root: Node,
+) -> Option<(String, Vec<Range<usize>>)> {
+    let (mut comment, mut ranges) = (String::new(), Vec::new());
+    let mut cursor = root.walk();
+    let mut more = cursor.goto_first_child();
+    while more {
+        let node = cursor.node();
+        let is_docstring = spec.name == "python"
+            && node.kind() == "expression_statement"
+            && node.named_child(0).map(|child| child.kind()) == Some("string");
+        if node.kind().contains("comment") || is_docstring {
+            let text = node_text(node, code).trim();
+            if !is_boilerplate_comment(text) {
+                comment.push_str(text);
+                comment.push('\n');
+                ranges.push(node.byte_range());
+            }
+        } else if !is_preamble(node.kind()) {
+            break;
+        }
+        // the docstring is the first statement of a module, the header ends with it
+        more = !is_docstring && cursor.goto_next_sibling();
+    }
+    match ranges.is_empty() {
+        true => None,
+        false => Some((comment, ranges)),
+    }
+}
+
+/// Comments right before `node`, or before the export or decorated definition it is in, in source
+/// order
+fn preceding_comments(node: Node, code: &str) -> Vec<String> {
+    let mut node = node;
+    while let Some(parent) = node.parent() {
+        match parent.kind().contains("export") || parent.kind().contains("decorated") {
+            true => node = parent,
+            false => break,
+        }
+    }
+    let mut comments = Vec::new();
+    let mut sibling = node.prev_sibling();
+    while let Some(comment) = sibling.filter(|sibling| sibling.kind().contains("comment")) {
+        comments.push(node_text(comment, code).trim().to_string());
+        sibling = comment.prev_sibling();
+    }
+    comments.reverse();
+    comments
+}
+
+/// The documentation of a class or function, the docstring opening its body in Python or else
+/// the comments before it
+pub(crate) fn documentation(spec: &LanguageSpec, node: Node, code: &str) -> String {
+    let first = match spec.name {
+        "python" => node
+            .child_by_field_name("body")
+            .and_then(|body| body.named_child(0)),
+        _ => None,
+    };
+    match first {
+        Some(first)
+            if first.kind() == "expression_statement"
+                && first.named_child(0).map(|child| child.kind()) == Some("string") =>
+        {
+            node_text(first, code).trim().to_string()
+        }
+        _ => preceding_comments(node, code).join("\n"),
+    }
+}
+
+/// The text of `node` before its body, the declaration of a class or signature of a method, or its
+/// first line if it has no body field, with runs of whitespace collapsed
+pub(crate) fn declaration(node: Node, code: &str) -> String {
+    let text = node_text(node, code);
+    let end = match node.child_by_field_name("body") {
+        Some(body) => body.start_byte() - node.start_byte(),
+        None => text.find('\n').unwrap_or(text.len()),
+    };
+    text[..end]
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// The functions and methods below `root` with their names
+pub(crate) fn named_functions<'a, 'tree>(
+    spec: &LanguageSpec,
+    root: Node<'tree>,
+    code: &'a str,
+) -> Vec<(&'a str, Node<'tree>)> {
+    let mut functions = Vec::new();
+    let mut cursor = root.walk();
+    loop {
+        let node = cursor.node();
+        if node.kind() == spec.function_kind || spec.method_kinds.contains(&node.kind()) {
+            let name = node
+                .child_by_field_name(spec.name_field)
+                .or_else(|| node.child_by_field_name("name"));
+            if let Some(name) = name {
+                functions.push((node_text(name, code), node));
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return functions;
+            }
+        }
+    }
+}
+
+/// The methods of `class` in source order, without those of the nested classes among `classes`
+fn class_methods<'tree>(
+    spec: &LanguageSpec,
+    class: Node<'tree>,
+    classes: &[Node<'tree>],
+) -> Vec<Node<'tree>> {
+    let mut methods = Vec::new();
+    let mut cursor = class.walk();
+    loop {
+        let node = cursor.node();
+        let is_method = spec.method_kinds.contains(&node.kind());
+        if is_method {
+            methods.push(node);
+        }
+        let is_nested = node != class && classes.contains(&node);
+        if !is_method && !is_nested && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() || cursor.node() == class {
+                return methods;
+            }
+        }
+    }
+}
+
+/// The documented classes below `root` as their declaration followed by the signature and the
+/// documentation collapsed onto a line of each of their methods, with the documentation of the
+/// class. Empty for languages without a `class_query`.
+pub fn find_class_summaries(
+    spec: &LanguageSpec,
+    code: &str,
+    root: Node,
+) -> Result<Vec<(String, String)>> {
+    let query_string = match spec.class_query {
+        Some(query_string) => query_string,
+        None => return Ok(Vec::new()),
+    };
+    let query = spec.compiled_query(query_string)?;
+    let mut query_cursor = QueryCursor::new();
+    let classes: Vec<Node> = query_cursor
+        .matches(query, root, |_| code.as_bytes())
+        .flat_map(|m| {
+            m.captures
+                .iter()
+                .filter(|capture| query.capture_names()[capture.index as usize] == "class")
+                .map(|capture| capture.node)
+                .collect::<Vec<Node>>()
+        })
+        .collect();
+    let mut summaries = Vec::new();
+    for class in &classes {
+        let doc = documentation(spec, *class, code);
+        if doc.is_empty() {
+            continue;
+        }
+        let mut skeleton = declaration(*class, code);
+        for method in class_methods(spec, *class, &classes) {
+            let method_doc = documentation(spec, method, code);
+            let method_doc: Vec<&str> = method_doc.split_whitespace().collect();
+            if !method_doc.is_empty() {
+                skeleton.push_str(&format!("\n    {}", method_doc.join(" ")));
+            }
+            skeleton.push_str(&format!("\n    {}", declaration(method, code)));
+        }
+        summaries.push((skeleton, doc));
+    }
+    Ok(summaries)
+}
+
+/// Byte ranges of the comments below `root`, in source order
+pub fn find_comments(root: Node) -> Vec<Range<usize>> {
+    let mut comments = Vec::new();
+    let mut cursor = root.walk();
+    loop {
+        let node = cursor.node();
+        if node.kind().contains("comment") {
+            comments.push(node.byte_range());
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() || cursor.node() == root {
+                return comments;
+            }
+        }
+    }
+}
+
+/// (caller, callee) names of the calls below `root` whose callee is accepted by
+/// `func_validate_fn`. Calls in nested functions are attributed to every enclosing function, and
+/// recursive calls pair a function with itself.
+pub fn find_function_calls<'a, F>(
+    spec: &LanguageSpec,
+    code: &'a str,
+    root: Node,
+    func_validate_fn: F,
+) -> Result<HashSet<(&'a str, &'a str)>>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut calling_pairs = HashSet::new();
+    for (call, func_name) in called_function_nodes(spec, code, root, func_validate_fn)? {
+        // find caller
+        let mut node = call;
+        while let Some(parent) = node.parent() {
+            if parent.kind() == spec.function_kind {
+                if let Some(identifier_node) = parent.child_by_field_name(spec.name_field) {
+                    calling_pairs.insert((node_text(identifier_node, code), func_name));
+                }
+            }
+            node = parent;
+        }
+    }
+    Ok(calling_pairs)
+}
+
+/// Source and comment of functions, both by function name
+pub type FunctionComments<'a> = (HashMap<&'a str, &'a str>, HashMap<&'a str, String>);
+
+/// Source and comment of the commented functions below `root`, by function name. Functions
+/// defined more than once are left out. Both maps are empty for languages without a
+/// `comment_query`.
+pub fn find_function_comments<'a>(
+    spec: &LanguageSpec,
+    code: &'a str,
+    root: Node,
+) -> Result<FunctionComments<'a>> {
+    let mut func_comments: HashMap<&str, String> = HashMap::new();
+    let mut func_code: HashMap<&str, &str> = HashMap::new();
+    let query_string = match spec.comment_query {
+        Some(query_string) => query_string,
+        None => return Ok((func_code, func_comments)),
+    };
+    let fc_query = spec.compiled_query(query_string)?;
+    let mut fc_qc = QueryCursor::new();
+    let matches = fc_qc.matches(fc_query, root, |_| code.as_bytes());
+    // names of functions defined more than once, which are left out for simplicity
+    let mut dup_funcs = HashSet::new();
+    for m in matches {
+        // match a function name with its comment
+        let mut comment = "".to_string();
+        let mut name = "";
+        let mut src = "";
+        for capture in m.captures {
+            let capture_name = &fc_query.capture_names()[capture.index as usize];
+            match capture_name.as_str() {
+                "name" => {
+                    name = node_text(capture.node, code);
+                }
+                "comment" => {
+                    let com = node_text(capture.node, code).trim();
+                    match com.contains("\r\n") {
+                        true => comment.push_str(&com.replace("\r\n", "\n")),
+                        false => comment.push_str(com),
+                    }
+                    comment.push('\n');
+                }
+                "func_src" => {
+                    src = node_text(capture.node, code);
+                }
+                _unhandled => {}
+            }
+        }
+        if dup_funcs.contains(name) {
+            continue;
+        }
+        if func_code.get(name).is_some_and(|other| *other != src) {
+            dup_funcs.insert(name);
+            func_code.remove(name);
+            func_comments.remove(name);
+            continue;
+        }
+        func_comments.insert(name, comment);
+        func_code.insert(name, src);
+    }
+    Ok((func_code, func_comments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::{Parser, Tree};
+
+    /// Python with a comment query like the one of Solidity, whose grammar is not needed
+    fn commented_python() -> LanguageSpec {
+        LanguageSpec {
+            comment_query: Some(
+                "((comment)+ @comment . (function_definition name: (identifier) @name) @func_src)",
+            ),
+            ..LanguageSpec::python()
+        }
+    }
+
+    fn parse(spec: &LanguageSpec, code: &str) -> Tree {
+        let mut parser = Parser::new();
+        parser.set_language(spec.language).unwrap();
+        parser.parse(code, None).unwrap()
+    }
+
+    #[test]
+    fn calls_of_functions() {
+        let spec = LanguageSpec::python();
+        let code = "def f(x):\n    return g(x) + print(x)\n\ndef g(x):\n    return g(x - 1)\n";
+        let tree = parse(&spec, code);
+        let calls = find_function_calls(&spec, code, tree.root_node(), |name| name != "print");
+        let expected: HashSet<(&str, &str)> = [("f", "g"), ("g", "g")].iter().copied().collect();
+        assert_eq!(calls.unwrap(), expected);
+    }
+
+    #[test]
+    fn calls_in_nested_functions() {
+        let spec = LanguageSpec::python();
+        let code = "def outer():\n    def inner():\n        return helper()\n    return inner()\n";
+        let tree = parse(&spec, code);
+        let calls = find_function_calls(&spec, code, tree.root_node(), |_| true).unwrap();
+        assert!(calls.contains(&("inner", "helper")));
+        assert!(calls.contains(&("outer", "helper")));
+        assert!(calls.contains(&("outer", "inner")));
+    }
+
+    #[test]
+    fn comments_of_functions() {
+        let spec = commented_python();
+        let code = "# Adds one\n# to x\ndef f(x):\n    return x + 1\n\ndef g(x):\n    return x\n";
+        let tree = parse(&spec, code);
+        let (func_code, func_comments) =
+            find_function_comments(&spec, code, tree.root_node()).unwrap();
+        assert_eq!(func_comments.len(), 1);
+        assert_eq!(func_comments["f"], "# Adds one\n# to x\n");
+        assert_eq!(func_code["f"], "def f(x):\n    return x + 1");
+    }
+
+    #[test]
+    fn comments_of_functions_defined_twice() {
+        let spec = commented_python();
+        let code = "# First\ndef f(x):\n    return x\n\n# Second\ndef f(y):\n    return y\n\n\
+                    # Third\ndef f(z):\n    return z\n\n# Once\ndef g():\n    pass\n";
+        let tree = parse(&spec, code);
+        let (func_code, func_comments) =
+            find_function_comments(&spec, code, tree.root_node()).unwrap();
+        assert!(!func_code.contains_key("f"));
+        assert!(!func_comments.contains_key("f"));
+        assert_eq!(func_comments["g"], "# Once\n");
+    }
+
+    #[test]
+    fn no_comments_without_query() {
+        let spec = LanguageSpec::python();
+        let code = "# Adds one\ndef f(x):\n    return x + 1\n";
+        let tree = parse(&spec, code);
+        let (func_code, func_comments) =
+            find_function_comments(&spec, code, tree.root_node()).unwrap();
+        assert!(func_code.is_empty() && func_comments.is_empty());
+    }
+}
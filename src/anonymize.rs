@@ -0,0 +1,149 @@
+//! Anonymization of the identifiers of functions, the obfuscated variant of a dataset written by
+//! `pair-calls --anonymize` for robustness and memorization studies.
+//!
+//! The functions of a group are named `FUNC_1`, `FUNC_2`, … in the order they are read, wherever
+//! they are defined or called, so that calls between them are still found. The other
+//! identifiers of each function are named `VAR_1`, `VAR_2`, … in the order they first appear in
+//! it. Called functions which are not in the group, such as those of libraries, type names, the
+//! members accessed on objects, as `length` in `obj.length`, and the names the language defines,
+//! such as `self` or `console`, are not defined by the functions and keep their names, as do
+//! comments and strings.
+
+use crate::analysis::{find_call_sites, LanguageSpec};
+use crate::error::{Error, Result};
+use crate::metrics;
+use crate::{get_node_token_ranges, JsonSample};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use tree_sitter::Node;
+
+/// Kinds of nodes accessing a member and their field holding its name
+const MEMBER_POSITIONS: &[(&str, &str)] = &[
+    ("member_expression", "property"),
+    ("attribute", "attribute"),
+    ("selector_expression", "field"),
+    ("field_access", "field"),
+    ("method_invocation", "name"),
+    ("call", "method"),
+    ("member_access_expression", "name"),
+    ("member_call_expression", "name"),
+    ("scoped_call_expression", "name"),
+    ("pair", "key"),
+    ("keyword_argument", "name"),
+];
+
+/// The samples of a group with their identifiers renamed, their code tokens are derived from the
+/// renamed code
+pub fn anonymize_group(samples: Vec<JsonSample>, spec: LanguageSpec) -> Result<Vec<JsonSample>> {
+    let mut funcs: HashMap<String, String> = HashMap::new();
+    for sample in &samples {
+        let next = format!("FUNC_{}", funcs.len() + 1);
+        funcs.entry(sample.func_name.clone()).or_insert(next);
+    }
+    samples
+        .into_par_iter()
+        .map(|sample| anonymize(sample, spec, &funcs))
+        .collect()
+}
+
+/// Whether a leaf node of `kind` is an identifier which is renamed
+fn is_identifier(kind: &str) -> bool {
+    let identifier = kind.contains("identifier") || kind == "name" || kind == "constant";
+    identifier && !kind.contains("type") && !kind.contains("package")
+}
+
+/// Whether `node` names the member accessed on an object or the key of an object literal or
+/// keyword argument, which are not defined by the function
+fn is_member(node: Node) -> bool {
+    match node.parent() {
+        Some(access) => MEMBER_POSITIONS.iter().any(|(kind, field)| {
+            access.kind() == *kind && access.child_by_field_name(field) == Some(node)
+        }),
+        None => false,
+    }
+}
+
+/// Names defined by `language` which the grammar parses as identifiers, such as its receivers
+/// and global objects
+fn builtins(language: &str) -> &'static [&'static str] {
+    match language {
+        "python" => &["self", "cls", "super", "print", "len", "range", "object"],
+        "javascript" => &[
+            "console",
+            "window",
+            "document",
+            "globalThis",
+            "module",
+            "exports",
+            "require",
+            "arguments",
+            "undefined",
+            "NaN",
+            "Infinity",
+        ],
+        "java" => &["System", "String", "Object", "Math"],
+        "go" => &[
+            "len", "cap", "make", "new", "append", "panic", "iota", "fmt",
+        ],
+        "ruby" => &["puts", "require", "raise", "attr_accessor", "attr_reader"],
+        "php" => &["this", "self", "parent"],
+        "solidity" => &["msg", "block", "tx", "now", "require", "assert", "revert"],
+        _ => &[],
+    }
+}
+
+fn anonymize(
+    mut sample: JsonSample,
+    spec: LanguageSpec,
+    funcs: &HashMap<String, String>,
+) -> Result<JsonSample> {
+    let mut parser = tree_sitter::Parser::new();
+    parser.set_language(spec.language)?;
+    let tree = parser.parse(&sample.code, None).ok_or_else(|| {
+        metrics::add_parse_failure();
+        Error::Parse {
+            path: sample.func_name.clone(),
+        }
+    })?;
+    let root = tree.root_node();
+    let library_calls: HashSet<_> =
+        find_call_sites(&spec, &sample.code, root, |name| !funcs.contains_key(name))?
+            .into_iter()
+            .collect();
+    let builtins = builtins(spec.name);
+    let mut vars: HashMap<&str, String> = HashMap::new();
+    let (mut code, mut code_tokens, mut end) = (String::new(), Vec::new(), 0);
+    for range in get_node_token_ranges(root) {
+        let text = &sample.code[range.clone()];
+        let node = root.descendant_for_byte_range(range.start, range.end);
+        let kind = node.map_or("", |node| node.kind());
+        let kept = !is_identifier(kind)
+            || library_calls.contains(&range)
+            || node.is_some_and(is_member)
+            || builtins.contains(&text);
+        let renamed = match funcs.get(text) {
+            Some(func) => Some(func.clone()),
+            None if kept => None,
+            None => {
+                let next = format!("VAR_{}", vars.len() + 1);
+                Some(vars.entry(text).or_insert(next).clone())
+            }
+        };
+        match renamed {
+            Some(renamed) => {
+                code.push_str(&sample.code[end..range.start]);
+                code.push_str(&renamed);
+                end = range.end;
+                code_tokens.push(renamed);
+            }
+            None => code_tokens.push(text.to_string()),
+        }
+    }
+    code.push_str(&sample.code[end..]);
+    sample.func_name = funcs[&sample.func_name].clone();
+    sample.code = code;
+    sample.code_tokens = Some(code_tokens);
+    // the source with the comment is not renamed
+    sample.original_string = None;
+    Ok(sample)
+}
@@ -0,0 +1,81 @@
+//! Syntax trees of functions written alongside their samples by `--with-ast`, for structure-aware
+//! models which would otherwise parse the dataset again. A tree is the S-expression tree-sitter
+//! prints, or a compact JSON tree of the named nodes whose leaves hold their text.
+
+use crate::analysis::{function_node, LanguageSpec};
+use crate::node_text;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+use tree_sitter::{Parser, TreeCursor};
+
+/// How syntax trees are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AstFormat {
+    /// the S-expression of the named nodes with their field names
+    Sexp,
+    /// `{"type", "field", "children"}` objects of the named nodes as a JSON string, leaves have
+    /// their `text` instead of children
+    Json,
+}
+
+impl FromStr for AstFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sexp" => Ok(AstFormat::Sexp),
+            "json" => Ok(AstFormat::Json),
+            _ => Err(format!("Unknown AST format: {}", s)),
+        }
+    }
+}
+
+/// The syntax tree of the function defined by `code` in `format`, or of all of `code` if the
+/// grammar finds no function in it. Empty if the code cannot be parsed.
+pub fn function_ast(code: &str, spec: &LanguageSpec, format: AstFormat) -> String {
+    let mut parser = Parser::new();
+    // the code was parsed with the same grammar when its calls were found
+    let tree = match parser.set_language(spec.language) {
+        Ok(()) => parser.parse(code, None),
+        Err(_) => None,
+    };
+    let tree = match tree {
+        Some(tree) => tree,
+        None => return String::new(),
+    };
+    let root = tree.root_node();
+    let node = function_node(root, spec);
+    match format {
+        AstFormat::Sexp => node.to_sexp(),
+        AstFormat::Json => json_tree(&mut node.walk(), code).to_string(),
+    }
+}
+
+/// The JSON tree of the node of `cursor` and its named descendants
+fn json_tree(cursor: &mut TreeCursor, code: &str) -> Value {
+    let node = cursor.node();
+    let mut tree = Map::new();
+    tree.insert("type".to_string(), node.kind().into());
+    if let Some(field) = cursor.field_name() {
+        tree.insert("field".to_string(), field.into());
+    }
+    let mut children = Vec::new();
+    if cursor.goto_first_child() {
+        loop {
+            if cursor.node().is_named() {
+                children.push(json_tree(cursor, code));
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+    match children.is_empty() {
+        true => tree.insert("text".to_string(), node_text(node, code).into()),
+        false => tree.insert("children".to_string(), children.into()),
+    };
+    Value::Object(tree)
+}
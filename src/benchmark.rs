@@ -0,0 +1,296 @@
+//! `sparser bench`, timings of the stages of `pair-calls` over a synthetic corpus, so that
+//! performance changes between releases can be measured without a private corpus. The corpus has
+//! a repo per file whose functions each call the next two functions of the repo and a library
+//! function. Each stage is timed on its own over the whole corpus, then the complete pipeline is
+//! timed as `pair-calls` runs it.
+
+use crate::analysis::{find_called_functions, LanguageSpec};
+use crate::error::{Error, Result};
+use crate::pair_calls::{
+    self, pair_samples, LangSelection, PairCallsArgs, PairOptions, SeenPairs, TargetLanguage,
+};
+use crate::{get_node_tokens, JsonSample, OutputFileWriter, OutputOptions};
+use log::warn;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tree_sitter::Parser;
+
+const NUM_FILES: usize = 1000;
+const NUM_FUNCTIONS: usize = 20;
+
+/// Time the stages of pair-calls over a synthetic corpus
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Language of the corpus: python, javascript, java, go, php or ruby [default: python]
+    #[clap(short = 'l', long)]
+    pub lang: Option<TargetLanguage>,
+    /// Number of files of the corpus, each a repo of its own [default: 1000]
+    #[clap(long)]
+    pub files: Option<usize>,
+    /// Number of functions per file [default: 20]
+    #[clap(long)]
+    pub functions: Option<usize>,
+    /// Directory the corpus and the outputs are written into, and removed from afterwards
+    /// [default: the system temporary directory]
+    #[clap(long)]
+    pub tmp_dir: Option<String>,
+    /// Also write the timings as JSON to this file
+    #[clap(long)]
+    pub report: Option<String>,
+}
+
+/// The timing of a stage
+#[derive(Debug, Serialize)]
+struct StageTiming {
+    stage: &'static str,
+    seconds: f64,
+    /// functions, or pairs for the stages writing pairs
+    items: usize,
+    items_per_second: f64,
+}
+
+impl StageTiming {
+    fn new(stage: &'static str, start: Instant, items: usize) -> Self {
+        let seconds = start.elapsed().as_secs_f64();
+        StageTiming {
+            stage,
+            seconds,
+            items,
+            items_per_second: items as f64 / seconds.max(f64::EPSILON),
+        }
+    }
+}
+
+/// Source of a function of the corpus in `lang`, `{name}` calls `{callee1}` and `{callee2}`
+fn function_template(lang: TargetLanguage) -> &'static str {
+    match lang {
+        TargetLanguage::Python => {
+            "def {name}(x):\n    y = x * {idx} + 1\n    y = {callee1}(y)\n    \
+             print(y)\n    return {callee2}(y)\n"
+        }
+        TargetLanguage::Javascript => {
+            "function {name}(x) {\n  let y = x * {idx} + 1;\n  y = {callee1}(y);\n  \
+             console.log(y);\n  return {callee2}(y);\n}\n"
+        }
+        TargetLanguage::Java => {
+            "public int {name}(int x) {\n    int y = x * {idx} + 1;\n    y = {callee1}(y);\n    \
+             System.out.println(y);\n    return {callee2}(y);\n}\n"
+        }
+        TargetLanguage::Go => {
+            "func {name}(x int) int {\n\ty := x*{idx} + 1\n\ty = {callee1}(y)\n\t\
+             fmt.Println(y)\n\treturn {callee2}(y)\n}\n"
+        }
+        TargetLanguage::Php => {
+            "function {name}($x) {\n    $y = $x * {idx} + 1;\n    $y = {callee1}($y);\n    \
+             echo($y);\n    return {callee2}($y);\n}\n"
+        }
+        TargetLanguage::Ruby => {
+            "def {name}(x)\n  y = x * {idx} + 1\n  y = {callee1}(y)\n  puts(y)\n  \
+             {callee2}(y)\nend\n"
+        }
+    }
+}
+
+/// Source of the function `name` in `lang` which calls `callee1` and `callee2`, `idx` varies
+/// the body of functions of the same callees
+pub(crate) fn function_code(
+    lang: TargetLanguage,
+    name: &str,
+    idx: usize,
+    callee1: &str,
+    callee2: &str,
+) -> String {
+    function_template(lang)
+        .replace("{name}", name)
+        .replace("{idx}", &idx.to_string())
+        .replace("{callee1}", callee1)
+        .replace("{callee2}", callee2)
+}
+
+/// The sample of the function `name` of `repo` as CodeSearchNet has it, commented as step `idx`
+pub(crate) fn function_sample(
+    parser: &mut Parser,
+    lang: TargetLanguage,
+    repo: &str,
+    name: &str,
+    idx: usize,
+    code: String,
+) -> Result<JsonSample> {
+    let tree = parser.parse(&code, None).ok_or_else(|| Error::Parse {
+        path: name.to_string(),
+    })?;
+    let docstring = format!("Step {} of the computation", idx);
+    Ok(JsonSample {
+        func_name: name.to_string(),
+        repo: Some(repo.to_string()),
+        path: Some(format!("{}/steps.{}", repo, lang.name())),
+        original_string: Some(code.clone()),
+        code_tokens: Some(get_node_tokens(tree.root_node(), &code)),
+        docstring_tokens: Some(docstring.split(' ').map(str::to_string).collect()),
+        docstring: Some(docstring),
+        code,
+        language: Some(lang.name().to_string()),
+    })
+}
+
+/// Write the corpus, a jsonl file per repo, returning the number of functions
+fn generate_corpus(
+    dir: &Path,
+    lang: TargetLanguage,
+    num_files: usize,
+    num_functions: usize,
+) -> Result<usize> {
+    let spec = lang.spec();
+    let mut parser = Parser::new();
+    parser.set_language(spec.language)?;
+    for file_idx in 0..num_files {
+        let repo = format!("bench/repo{}", file_idx);
+        let path = dir.join(format!("{}_{}.jsonl", lang.name(), file_idx));
+        let mut file = BufWriter::new(File::create(path)?);
+        for idx in 0..num_functions {
+            let name = |idx: usize| format!("step{}_{}", file_idx, idx % num_functions);
+            let code = function_code(lang, &name(idx), idx, &name(idx + 1), &name(idx + 2));
+            let sample = function_sample(&mut parser, lang, &repo, &name(idx), idx, code)?;
+            serde_json::to_writer(&mut file, &sample)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+    }
+    Ok(num_files * num_functions)
+}
+
+/// The samples of the corpus, a group per file
+fn read_corpus(dir: &Path) -> Result<Vec<Vec<JsonSample>>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.sort();
+    let mut groups = Vec::new();
+    for path in paths {
+        let mut group = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            group.push(serde_json::from_str(&line?)?);
+        }
+        groups.push(group);
+    }
+    Ok(groups)
+}
+
+/// Number of calls between the functions of a group, the call graph `pair-calls` pairs on
+fn call_graph(spec: &LanguageSpec, group: &[JsonSample]) -> Result<usize> {
+    let names: HashSet<&str> = group.iter().map(|s| s.func_name.as_str()).collect();
+    let mut parser = Parser::new();
+    parser.set_language(spec.language)?;
+    let mut num_calls = 0;
+    for sample in group {
+        let tree = parser
+            .parse(&sample.code, None)
+            .ok_or_else(|| Error::Parse {
+                path: sample.func_name.clone(),
+            })?;
+        let called = find_called_functions(spec, &sample.code, tree.root_node(), |name| {
+            names.contains(name)
+        })?;
+        num_calls += called.len();
+    }
+    Ok(num_calls)
+}
+
+pub fn run(args: BenchArgs) -> Result<()> {
+    let lang = args.lang.unwrap_or(TargetLanguage::Python);
+    let spec = lang.spec();
+    let num_files = args.files.unwrap_or(NUM_FILES);
+    let num_functions = args.functions.unwrap_or(NUM_FUNCTIONS);
+    let parent = args
+        .tmp_dir
+        .as_deref()
+        .map_or_else(std::env::temp_dir, PathBuf::from);
+    let dir = parent.join(format!("sparser-bench-{}", std::process::id()));
+    let corpus_dir = dir.join("corpus");
+    fs::create_dir_all(&corpus_dir)?;
+    let result = run_stages(lang, spec, num_files, num_functions, &dir, &corpus_dir);
+    // the error of a stage is the one reported, a directory left behind only warrants a warning
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("Failed to remove {}: {}", dir.display(), e);
+    }
+    let timings = result?;
+    println!(
+        "{:<10} {:>10} {:>10} {:>12}",
+        "stage", "seconds", "items", "items/s"
+    );
+    for timing in &timings {
+        println!(
+            "{:<10} {:>10.3} {:>10} {:>12.1}",
+            timing.stage, timing.seconds, timing.items, timing.items_per_second
+        );
+    }
+    if let Some(report) = &args.report {
+        fs::write(report, serde_json::to_vec_pretty(&timings)?)?;
+    }
+    Ok(())
+}
+
+fn run_stages(
+    lang: TargetLanguage,
+    spec: LanguageSpec,
+    num_files: usize,
+    num_functions: usize,
+    dir: &Path,
+    corpus_dir: &Path,
+) -> Result<Vec<StageTiming>> {
+    let mut timings = Vec::new();
+    let start = Instant::now();
+    let num_samples = generate_corpus(corpus_dir, lang, num_files, num_functions)?;
+    timings.push(StageTiming::new("generate", start, num_samples));
+
+    let start = Instant::now();
+    let groups = read_corpus(corpus_dir)?;
+    timings.push(StageTiming::new("read", start, num_samples));
+
+    let start = Instant::now();
+    let mut parser = Parser::new();
+    parser.set_language(spec.language)?;
+    for sample in groups.iter().flatten() {
+        parser.parse(&sample.code, None);
+    }
+    timings.push(StageTiming::new("parse", start, num_samples));
+
+    let start = Instant::now();
+    for group in &groups {
+        call_graph(&spec, group)?;
+    }
+    timings.push(StageTiming::new("calls", start, num_samples));
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let start = Instant::now();
+    let mut pairs = Vec::new();
+    let seen = SeenPairs::default();
+    for group in groups {
+        pairs.extend(runtime.block_on(pair_samples(group, spec, PairOptions::default(), &seen))?);
+    }
+    timings.push(StageTiming::new("pair", start, pairs.len()));
+
+    let start = Instant::now();
+    let out = dir.join("pairs.jsonl").to_string_lossy().into_owned();
+    let mut file = OutputFileWriter::create(&out, &OutputOptions::default())?;
+    file.append(&pairs)?;
+    file.finish()?;
+    timings.push(StageTiming::new("write", start, pairs.len()));
+
+    let out = dir.join("pipeline.jsonl").to_string_lossy().into_owned();
+    let mut args = PairCallsArgs {
+        data: Some(corpus_dir.to_string_lossy().into_owned()),
+        out: Some(out),
+        lang: Some(LangSelection::List(vec![lang])),
+        ..Default::default()
+    };
+    args.output.force = true;
+    let start = Instant::now();
+    pair_calls::run_sync(args)?;
+    timings.push(StageTiming::new("pipeline", start, pairs.len()));
+    Ok(timings)
+}
@@ -0,0 +1,119 @@
+//! Structural metrics of functions computed from their syntax tree, written alongside pairs by
+//! `--complexity` so that datasets can be stratified or filtered by how hard their functions are
+//! to follow. Which nodes branch and which hold blocks is up to the `LanguageSpec` of the
+//! language.
+
+use crate::analysis::{function_node, LanguageSpec};
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, Parser};
+
+/// Operators of short-circuit evaluation, each a path of the cyclomatic complexity
+const BOOLEAN_OPERATORS: [&str; 4] = ["&&", "||", "and", "or"];
+
+/// The structural metrics of a function
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Complexity {
+    /// the branches, loops, cases, handlers and short-circuit operators plus one
+    pub cyclomatic: usize,
+    /// how deeply blocks nest in the body, 0 for code without nested blocks
+    pub nesting_depth: usize,
+    pub num_params: usize,
+}
+
+/// The metrics of the function defined by `code`, or of all of `code` if the grammar finds no
+/// function in it. `None` if the code cannot be parsed.
+pub fn function_complexity(code: &str, spec: &LanguageSpec) -> Option<Complexity> {
+    let mut parser = Parser::new();
+    parser.set_language(spec.language).ok()?;
+    let tree = parser.parse(code, None)?;
+    let function = function_node(tree.root_node(), spec);
+    let mut complexity = Complexity {
+        cyclomatic: 1,
+        nesting_depth: 0,
+        num_params: num_params(function),
+    };
+    let mut cursor = function.walk();
+    if cursor.goto_first_child() {
+        loop {
+            let child = cursor.node();
+            // the body of the function is not nested
+            match spec.block_kinds.contains(&child.kind()) {
+                true => visit_children(child, 0, spec, &mut complexity),
+                false => visit(child, 0, spec, &mut complexity),
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+    Some(complexity)
+}
+
+/// Count the paths of `node` and the nodes below it, which is inside `depth` blocks
+fn visit(node: Node, depth: usize, spec: &LanguageSpec, complexity: &mut Complexity) {
+    let kind = node.kind();
+    let is_branch = match node.is_named() {
+        true => spec.branch_kinds.contains(&kind),
+        false => BOOLEAN_OPERATORS.contains(&kind),
+    };
+    complexity.cyclomatic += is_branch as usize;
+    let depth = match spec.block_kinds.contains(&kind) {
+        true => depth + 1,
+        false => depth,
+    };
+    complexity.nesting_depth = complexity.nesting_depth.max(depth);
+    visit_children(node, depth, spec, complexity);
+}
+
+fn visit_children(node: Node, depth: usize, spec: &LanguageSpec, complexity: &mut Complexity) {
+    let mut cursor = node.walk();
+    if cursor.goto_first_child() {
+        loop {
+            visit(cursor.node(), depth, spec, complexity);
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+}
+
+/// Parameters of `function`, those of its `parameters` field or its children of a parameter
+/// kind. A declaration of several names, such as `a, b int` in Go, counts each name.
+fn num_params(function: Node) -> usize {
+    let (list, is_param): (Node, fn(&Node) -> bool) =
+        match function.child_by_field_name("parameters") {
+            Some(list) => (list, |node| {
+                node.is_named() && !node.kind().contains("comment")
+            }),
+            None => (function, |node| node.kind().contains("parameter")),
+        };
+    let mut cursor = list.walk();
+    let mut num_params = 0;
+    if cursor.goto_first_child() {
+        loop {
+            let node = cursor.node();
+            if is_param(&node) {
+                num_params += num_names(node).max(1);
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+    num_params
+}
+
+/// Children of `node` in its `name` field
+fn num_names(node: Node) -> usize {
+    let mut cursor = node.walk();
+    let mut num_names = 0;
+    if cursor.goto_first_child() {
+        loop {
+            num_names += (cursor.field_name() == Some("name")) as usize;
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+    num_names
+}
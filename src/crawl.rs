@@ -0,0 +1,178 @@
+//! Harvest source files from the GitHub code search API
+
+use crate::error::{Error, Result};
+use crate::required_arg;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const GITHUB_API: &str = "https://api.github.com";
+/// The code search API returns at most 1000 results per query
+const MAX_SEARCH_RESULTS: usize = 1000;
+const PER_PAGE: usize = 100;
+
+/// Download files matching a GitHub code search into a data directory
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Search terms, e.g. `pragma solidity`
+    #[clap(short = 'q', long)]
+    pub query: Option<String>,
+    /// Only files of this GitHub language, e.g. `Solidity`
+    #[clap(long)]
+    pub language: Option<String>,
+    /// Only files with this extension, e.g. `sol`
+    #[clap(long)]
+    pub extension: Option<String>,
+    /// Data directory the files are written into as `owner/repo/path`
+    #[clap(short = 'o', long)]
+    pub out_dir: Option<String>,
+    /// Maximum number of files to download [default: 1000]
+    #[clap(long)]
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    items: Vec<SearchItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchItem {
+    path: String,
+    /// contents API URL of the file
+    url: String,
+    repository: SearchRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchRepository {
+    full_name: String,
+}
+
+/// Seconds to wait before retrying a rate limited request, `None` if it was not rate limited
+fn rate_limit_wait(status: u16, response: &ureq::Response) -> Option<u64> {
+    if status != 403 && status != 429 {
+        return None;
+    }
+    if let Some(retry_after) = response.header("retry-after") {
+        return retry_after.parse().ok();
+    }
+    if response.header("x-ratelimit-remaining") == Some("0") {
+        let reset: u64 = response.header("x-ratelimit-reset")?.parse().ok()?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        return Some(reset.saturating_sub(now) + 1);
+    }
+    // secondary rate limits are reported without headers
+    Some(60)
+}
+
+/// GET a GitHub API URL, waiting out rate limits
+fn github_get(
+    url: &str,
+    query: &[(&str, &str)],
+    accept: &str,
+    token: &str,
+) -> std::result::Result<ureq::Response, String> {
+    loop {
+        let mut request = ureq::get(url)
+            .set("Accept", accept)
+            .set("Authorization", &format!("Bearer {}", token))
+            .set("User-Agent", "sparser")
+            .set("X-GitHub-Api-Version", "2022-11-28");
+        for (key, value) in query {
+            request = request.query(key, value);
+        }
+        match request.call() {
+            Ok(response) => return Ok(response),
+            Err(ureq::Error::Status(status, response)) => {
+                match rate_limit_wait(status, &response) {
+                    Some(wait) => {
+                        eprintln!("Rate limited, retrying in {}s", wait);
+                        thread::sleep(Duration::from_secs(wait));
+                    }
+                    None => return Err(format!("{} returned {}", url, status)),
+                }
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// GitHub code search qualifiers for the crawl options
+fn search_query(args: &CrawlArgs) -> String {
+    let mut terms: Vec<String> = args.query.iter().cloned().collect();
+    if let Some(language) = &args.language {
+        terms.push(format!("language:{}", language));
+    }
+    if let Some(extension) = &args.extension {
+        terms.push(format!("extension:{}", extension.trim_start_matches('.')));
+    }
+    terms.join(" ")
+}
+
+pub fn run(mut args: CrawlArgs) -> Result<()> {
+    let token = std::env::var("GITHUB_TOKEN").map_err(|_| {
+        Error::Usage("the GITHUB_TOKEN environment variable is required for code search".into())
+    })?;
+    let out_dir = required_arg(args.out_dir.clone(), "--out-dir");
+    let max_files = *args.max_files.get_or_insert(MAX_SEARCH_RESULTS);
+    let query = search_query(&args);
+    if query.is_empty() {
+        required_arg(None::<String>, "--query, --language or --extension");
+    }
+    let search_url = format!("{}/search/code", GITHUB_API);
+    let mut downloaded = 0;
+    let pages = max_files.min(MAX_SEARCH_RESULTS).div_ceil(PER_PAGE);
+    'pages: for page in 1..=pages {
+        let page = page.to_string();
+        let per_page = PER_PAGE.to_string();
+        let params = [
+            ("q", query.as_str()),
+            ("per_page", &per_page),
+            ("page", &page),
+        ];
+        let response: SearchResponse =
+            github_get(&search_url, &params, "application/vnd.github+json", &token)
+                .map_err(io::Error::other)?
+                .into_json()?;
+        if response.items.is_empty() {
+            break;
+        }
+        for item in response.items {
+            if downloaded >= max_files {
+                break 'pages;
+            }
+            let path = Path::new(&out_dir)
+                .join(&item.repository.full_name)
+                .join(&item.path);
+            downloaded += 1;
+            print!("\x1b[K\r{}/{} {}", downloaded, max_files, path.display());
+            // files of earlier runs are kept so interrupted crawls can be resumed
+            if path.exists() {
+                continue;
+            }
+            match github_get(&item.url, &[], "application/vnd.github.raw", &token)
+                .and_then(|response| response.into_string().map_err(|e| e.to_string()))
+            {
+                Ok(content) => {
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    fs::write(&path, content)?;
+                }
+                Err(e) => eprintln!("\nSkipping {}: {}", item.url, e),
+            }
+        }
+    }
+    println!();
+    Ok(())
+}
@@ -0,0 +1,652 @@
+//! Subcommands operating on already generated jsonl datasets
+
+use crate::error::{Error, Result};
+use crate::predicate::Expr;
+use crate::shuffle::ExternalShuffle;
+use crate::split::{plain_split_of, SplitStrategyArgs};
+use crate::{
+    check_overwrite, required_arg, save_data_gen, split_paths, write_hf_metadata, write_samples,
+    OutputArgs, OutputFileWriter, OutputFormat, OutputOptions, Record,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Iterate over the records of a jsonl file, skipping empty lines
+pub fn jsonl_records(path: &str) -> io::Result<impl Iterator<Item = io::Result<Value>>> {
+    let file =
+        File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    let reader = BufReader::new(file);
+    let path = path.to_string();
+    Ok(reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(move |(idx, line)| {
+            serde_json::from_str(&line?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path, idx + 1, e),
+                )
+            })
+        }))
+}
+
+/// Read all records of a jsonl file
+pub fn read_jsonl_records(path: &str) -> io::Result<Vec<Value>> {
+    jsonl_records(path)?.collect()
+}
+
+/// Split an existing jsonl dataset into train/val/test
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Jsonl file to split
+    pub input: Option<String>,
+    #[clap(short = 'o', long)]
+    pub out_dir: Option<String>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+    /// Write a HuggingFace datasets compatible directory layout
+    #[clap(long)]
+    pub hf_layout: bool,
+    /// Also write a dataset card stub in the README of the HuggingFace layout
+    #[clap(long)]
+    pub hf_readme: bool,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub split: SplitStrategyArgs,
+    /// Shuffle the records before splitting, through temporary shards on disk so that datasets
+    /// larger than memory can be shuffled
+    #[clap(long)]
+    pub shuffle: bool,
+    /// Seed of the shuffle
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Directory of the temporary shards of the shuffle [default: the system temporary directory]
+    #[clap(long)]
+    pub tmp_dir: Option<String>,
+}
+
+pub fn run_split(args: SplitArgs) -> Result<()> {
+    let input = required_arg(args.input, "<INPUT>");
+    let out_dir = required_arg(args.out_dir, "--out-dir");
+    let out_dir = out_dir.strip_suffix('/').unwrap_or(&out_dir);
+    let output_opts = OutputOptions {
+        hf_layout: args.hf_layout,
+        hf_readme: args.hf_readme,
+        split: args.split.to_options(),
+        ..args.output.to_options()
+    };
+    if args.shuffle {
+        let size = fs::metadata(&input)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", input, e)))?
+            .len();
+        let mut shuffle =
+            ExternalShuffle::new(args.tmp_dir.as_deref(), size, seeded_rng(args.seed))?;
+        for line in BufReader::new(File::open(&input)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                shuffle.push(line.trim_end())?;
+            }
+        }
+        save_shuffled(out_dir, shuffle, &output_opts)?;
+        return Ok(());
+    }
+    let records = read_jsonl_records(&input)?;
+    save_data_gen(out_dir, &records, &output_opts)?;
+    Ok(())
+}
+
+/// Split and write the shuffled records. The plain 8:1:1 split is written while the records are
+/// read back, other split strategies need all records in memory.
+fn save_shuffled(out_dir: &str, shuffle: ExternalShuffle, opts: &OutputOptions) -> io::Result<()> {
+    let total = shuffle.len();
+    let records = shuffle
+        .finish()?
+        .map(|line| Ok(serde_json::from_str::<Value>(&line?)?));
+    if opts.split.is_custom() {
+        let records = records.collect::<io::Result<Vec<Value>>>()?;
+        return save_data_gen(out_dir, &records, opts);
+    }
+    let paths = split_paths(out_dir, opts);
+    let files: Vec<String> = paths.iter().map(|(_, path)| path.clone()).collect();
+    check_overwrite(&files, opts)?;
+    let mut writers = Vec::new();
+    for (split, path) in &paths {
+        fs::create_dir_all(Path::new(path).parent().unwrap())?;
+        writers.push((*split, OutputFileWriter::create(path, opts)?, 0));
+    }
+    let mut first = None;
+    for (idx, record) in records.enumerate() {
+        let record = record?;
+        let split = ["train", "val", "test"][plain_split_of(idx, total)];
+        for (name, writer, count) in &mut writers {
+            if *name == "all" || *name == split || (*name == "validation" && split == "val") {
+                writer.append_one(&record)?;
+                *count += 1;
+            }
+        }
+        first.get_or_insert(record);
+    }
+    let mut counts = Vec::new();
+    for (split, writer, count) in writers {
+        writer.finish()?;
+        counts.push((split, count));
+    }
+    if opts.hf_layout {
+        let record = first.map(|record| Record::new(&record, None)).transpose()?;
+        write_hf_metadata(out_dir, record, &counts, opts)?;
+    }
+    Ok(())
+}
+
+/// Print statistics of jsonl datasets
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Jsonl files
+    #[clap(required = true)]
+    pub inputs: Vec<String>,
+}
+
+/// Accumulated statistics of one field
+#[derive(Debug, Default, Serialize)]
+struct FieldStats {
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_len: Option<f64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    values: BTreeMap<String, usize>,
+    #[serde(skip)]
+    total_len: usize,
+    #[serde(skip)]
+    num_sized: usize,
+}
+
+/// Number of records, and per field the average length of strings and lists and the
+/// distribution of booleans
+pub fn dataset_stats<I: IntoIterator<Item = Value>>(records: I) -> Value {
+    let mut num_records = 0;
+    let mut fields: BTreeMap<String, FieldStats> = BTreeMap::new();
+    for record in records {
+        num_records += 1;
+        let entries: Vec<(String, Value)> = match record {
+            Value::Object(map) => map.into_iter().collect(),
+            Value::Array(values) => values
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| (format!("field{}", i), v))
+                .collect(),
+            other => vec![("value".to_string(), other)],
+        };
+        for (name, value) in entries {
+            let stats = fields.entry(name).or_default();
+            stats.count += 1;
+            let len = match &value {
+                Value::String(s) => s.chars().count(),
+                Value::Array(items) => items.len(),
+                Value::Bool(b) => {
+                    *stats.values.entry(b.to_string()).or_default() += 1;
+                    continue;
+                }
+                _ => continue,
+            };
+            stats.total_len += len;
+            stats.num_sized += 1;
+            stats.avg_len = Some(stats.total_len as f64 / stats.num_sized as f64);
+        }
+    }
+    serde_json::json!({ "records": num_records, "fields": fields })
+}
+
+pub fn run_stats(args: StatsArgs) -> Result<()> {
+    let mut all_stats = serde_json::Map::new();
+    for input in &args.inputs {
+        // the records are streamed, reading stops at the first which cannot be read
+        let mut error = None;
+        let records =
+            jsonl_records(input)?.map_while(|record| record.map_err(|e| error = Some(e)).ok());
+        let stats = dataset_stats(records);
+        if let Some(e) = error {
+            return Err(e.into());
+        }
+        all_stats.insert(input.clone(), stats);
+    }
+    println!("{}", serde_json::to_string_pretty(&all_stats)?);
+    Ok(())
+}
+
+/// Check that jsonl datasets are well formed and their records share the same fields
+#[derive(clap::Args, Debug)]
+pub struct ValidateArgs {
+    /// Jsonl files
+    #[clap(required = true)]
+    pub inputs: Vec<String>,
+    /// Maximum number of problems reported per file
+    #[clap(long, default_value_t = 20)]
+    pub max_errors: usize,
+}
+
+/// Field names and json types of a record
+fn record_schema(record: &Value) -> Vec<(String, &'static str)> {
+    let type_name = |v: &Value| match v {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+    match record {
+        Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), type_name(v))).collect(),
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (format!("field{}", i), type_name(v)))
+            .collect(),
+        other => vec![("value".to_string(), type_name(other))],
+    }
+}
+
+/// Problems found in a jsonl file, at most `max_errors` are returned
+pub fn validate_file(path: &str, max_errors: usize) -> io::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    let mut schema = None;
+    for (idx, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        if errors.len() >= max_errors {
+            break;
+        }
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(&line) {
+            Ok(record) => {
+                let record_schema = record_schema(&record);
+                match &schema {
+                    None => schema = Some(record_schema),
+                    Some(expected) if *expected != record_schema => errors.push(format!(
+                        "{}:{}: fields {:?} differ from the first record {:?}",
+                        path,
+                        idx + 1,
+                        record_schema,
+                        expected
+                    )),
+                    _ => {}
+                }
+            }
+            Err(e) => errors.push(format!("{}:{}: {}", path, idx + 1, e)),
+        }
+    }
+    Ok(errors)
+}
+
+/// Validate all inputs, an `Invalid` error if any of them has problems
+pub fn run_validate(args: ValidateArgs) -> Result<()> {
+    let mut invalid = 0;
+    for input in &args.inputs {
+        let errors = validate_file(input, args.max_errors)?;
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        if !errors.is_empty() {
+            invalid += 1;
+        }
+    }
+    match invalid {
+        0 => Ok(()),
+        invalid => Err(Error::Invalid {
+            invalid,
+            total: args.inputs.len(),
+        }),
+    }
+}
+
+/// Re-encode a jsonl dataset in another output format
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConvertArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Jsonl file to convert
+    pub input: Option<String>,
+    /// Target format, same as --format
+    #[clap(long)]
+    pub to: Option<OutputFormat>,
+    /// Output file [default: the input path with the extension of the target format]
+    #[clap(short = 'o', long)]
+    pub out: Option<String>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+}
+
+pub fn run_convert(mut args: ConvertArgs) -> Result<()> {
+    let input = required_arg(args.input, "<INPUT>");
+    if let Some(format) = args.to {
+        args.output.format = Some(format);
+    }
+    let opts = args.output.to_options();
+    let out = args.out.unwrap_or_else(|| {
+        let stem = input.strip_suffix(".jsonl").unwrap_or(&input);
+        format!("{}.{}", stem, opts.format.extension())
+    });
+    let mut writer = OutputFileWriter::create(&out, &opts)?;
+    for record in jsonl_records(&input)? {
+        writer.append_one(&record?)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Concatenate generated datasets
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MergeArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Output dataset directory
+    pub out_dir: Option<String>,
+    /// Datasets to merge, either jsonl files or dataset directories with train/val/test splits
+    pub inputs: Vec<String>,
+    /// Drop records whose content is identical to an earlier record
+    #[clap(long)]
+    pub dedup: bool,
+    /// Shuffle the merged records and split them again instead of keeping the input splits,
+    /// implied by the split strategy options
+    #[clap(long)]
+    pub rebalance: bool,
+    /// Seed of the shuffle before rebalancing
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Directory of the temporary shards of the shuffle before rebalancing, which is done on
+    /// disk so that datasets larger than memory can be merged [default: the system temporary
+    /// directory]
+    #[clap(long)]
+    pub tmp_dir: Option<String>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub split: SplitStrategyArgs,
+}
+
+/// Random generator seeded with `seed`, or from entropy if no seed is given
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Hash of the serialized content of a record
+pub fn content_hash(record: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    record.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Jsonl files of a generated dataset, tagged with their split if `input` is a dataset directory
+fn dataset_files(input: &str) -> Vec<(Option<&'static str>, String)> {
+    if !Path::new(input).is_dir() {
+        return vec![(None, input.to_string())];
+    }
+    let opts = OutputOptions {
+        hf_layout: Path::new(&format!("{}/train", input)).is_dir(),
+        ..Default::default()
+    };
+    split_paths(input.trim_end_matches('/'), &opts)
+        .into_iter()
+        .filter_map(|(split, path)| match split {
+            "all" => None,
+            "validation" => Some((Some("val"), path)),
+            split => Some((Some(split), path)),
+        })
+        .collect()
+}
+
+/// Records of a generated dataset, tagged with their split if `input` is a dataset directory
+pub fn dataset_records(input: &str) -> io::Result<Vec<(Option<&'static str>, Value)>> {
+    let mut records = Vec::new();
+    for (split, path) in dataset_files(input) {
+        for record in jsonl_records(&path)? {
+            records.push((split, record?));
+        }
+    }
+    Ok(records)
+}
+
+/// Shuffle the records of the inputs on disk, dropping duplicates if `dedup`
+fn shuffle_inputs(args: &MergeArgs) -> io::Result<ExternalShuffle> {
+    let files: Vec<String> = args
+        .inputs
+        .iter()
+        .flat_map(|input| dataset_files(input))
+        .map(|(_, path)| path)
+        .collect();
+    let size = files
+        .iter()
+        .map(|path| fs::metadata(path).map(|m| m.len()))
+        .sum::<io::Result<u64>>()?;
+    let mut shuffle = ExternalShuffle::new(args.tmp_dir.as_deref(), size, seeded_rng(args.seed))?;
+    let mut seen = HashSet::new();
+    for path in &files {
+        for record in jsonl_records(path)? {
+            let record = record?;
+            if args.dedup && !seen.insert(content_hash(&record)) {
+                continue;
+            }
+            shuffle.push(&record.to_string())?;
+        }
+    }
+    Ok(shuffle)
+}
+
+pub fn run_merge(mut args: MergeArgs) -> Result<()> {
+    let out_dir = required_arg(args.out_dir.take(), "<OUT_DIR>");
+    let out_dir = out_dir.trim_end_matches('/');
+    let opts = OutputOptions {
+        split: args.split.to_options(),
+        ..args.output.to_options()
+    };
+    if args.rebalance {
+        let shuffle = shuffle_inputs(&args)?;
+        save_shuffled(out_dir, shuffle, &opts)?;
+        return Ok(());
+    }
+    let mut seen = HashSet::new();
+    let mut records = Vec::new();
+    for input in &args.inputs {
+        for (split, record) in dataset_records(input)? {
+            if args.dedup && !seen.insert(content_hash(&record)) {
+                continue;
+            }
+            records.push((split, record));
+        }
+    }
+    // plain jsonl inputs carry no split, so the merged records have to be split again
+    if opts.split.is_custom() || records.iter().any(|(split, _)| split.is_none()) {
+        let records: Vec<Value> = records.into_iter().map(|(_, record)| record).collect();
+        save_data_gen(out_dir, &records, &opts)?;
+        return Ok(());
+    }
+    let paths = split_paths(out_dir, &opts);
+    let files: Vec<String> = paths.iter().map(|(_, path)| path.clone()).collect();
+    check_overwrite(&files, &opts)?;
+    fs::create_dir_all(out_dir)?;
+    for (split, path) in &paths {
+        let split_records: Vec<&Value> = records
+            .iter()
+            .filter(|(record_split, _)| *split == "all" || *record_split == Some(*split))
+            .map(|(_, record)| record)
+            .collect();
+        write_samples(&split_records, path, &opts)?;
+    }
+    Ok(())
+}
+
+/// Draw a uniform random sample of the records of a jsonl dataset
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SampleArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Jsonl file to sample from
+    pub input: Option<String>,
+    /// Output file
+    pub out: Option<String>,
+    /// Number of records to keep
+    #[clap(short, long)]
+    pub n: Option<usize>,
+    /// Seed of the random generator
+    #[clap(long)]
+    pub seed: Option<u64>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+}
+
+/// Uniform sample of at most `capacity` of the items pushed, for streams of unknown length
+pub struct Reservoir<T, R> {
+    capacity: usize,
+    rng: R,
+    num_seen: usize,
+    items: Vec<(usize, T)>,
+}
+
+impl<T, R: Rng> Reservoir<T, R> {
+    pub fn new(capacity: usize, rng: R) -> Self {
+        Reservoir {
+            capacity,
+            rng,
+            num_seen: 0,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        let idx = self.num_seen;
+        self.num_seen += 1;
+        if idx < self.capacity {
+            self.items.push((idx, item));
+        } else {
+            let slot = self.rng.gen_range(0..=idx);
+            if slot < self.capacity {
+                self.items[slot] = (idx, item);
+            }
+        }
+    }
+
+    /// Number of items pushed, kept or not
+    pub fn num_seen(&self) -> usize {
+        self.num_seen
+    }
+
+    /// The kept items in the order they were pushed
+    pub fn into_vec(mut self) -> Vec<T> {
+        self.items.sort_by_key(|(idx, _)| *idx);
+        self.items.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+/// Uniformly sample `n` items in a single pass, keeping their original order
+pub fn reservoir_sample<T, I, R>(items: I, n: usize, rng: &mut R) -> Vec<T>
+where
+    I: IntoIterator<Item = T>,
+    R: Rng,
+{
+    let mut reservoir = Reservoir::new(n, rng);
+    items.into_iter().for_each(|item| reservoir.push(item));
+    reservoir.into_vec()
+}
+
+pub fn run_sample(args: SampleArgs) -> Result<()> {
+    let input = required_arg(args.input, "<INPUT>");
+    let out = required_arg(args.out, "<OUT>");
+    let n = required_arg(args.n, "--n");
+    let mut reservoir = Reservoir::new(n, seeded_rng(args.seed));
+    for record in jsonl_records(&input)? {
+        reservoir.push(record?);
+    }
+    let mut writer = OutputFileWriter::create(&out, &args.output.to_options())?;
+    writer.append(&reservoir.into_vec())?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Copy the records of a jsonl dataset which satisfy a predicate
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Jsonl file to filter
+    pub input: Option<String>,
+    /// Output file
+    pub out: Option<String>,
+    /// Predicate on the fields of a record, e.g. `label == true && len(callee_code_tokens) < 256`
+    #[clap(short = 'w', long = "where")]
+    #[serde(rename = "where")]
+    pub predicate: Option<String>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+}
+
+pub fn run_filter(args: FilterArgs) -> Result<()> {
+    let input = required_arg(args.input, "<INPUT>");
+    let out = required_arg(args.out, "<OUT>");
+    let predicate: Expr = required_arg(args.predicate, "--where")
+        .parse()
+        .unwrap_or_else(|e| clap::Error::raw(clap::ErrorKind::InvalidValue, e + "\n").exit());
+    let mut writer = OutputFileWriter::create(&out, &args.output.to_options())?;
+    let (mut total, mut kept) = (0, 0);
+    // records the predicate cannot be evaluated on, such as records without a field it compares,
+    // are left out
+    let (mut num_failed, mut first_error) = (0, None);
+    for record in jsonl_records(&input)? {
+        let record = record?;
+        total += 1;
+        match predicate.matches(&record) {
+            Ok(true) => {
+                writer.append_one(&record)?;
+                kept += 1;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                num_failed += 1;
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    writer.finish()?;
+    println!("Kept {} of {} records", kept, total);
+    if let Some(e) = first_error {
+        eprintln!(
+            "Left out {} records the predicate could not be evaluated on, the first: {}",
+            num_failed, e
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+//! Detection of the language of a source file or function from its file extension, its shebang
+//! line and markers of its content, used where the language of an input is not given, such as
+//! the files of cloned repositories and the samples of `pair-calls --lang auto`.
+//!
+//! The extension, the shebang and unambiguous markers such as `<?php` or `pragma solidity` are
+//! trusted, a source on which they disagree is logged and left undetected. Only without any of
+//! them the language is inferred from the lines typical of each language, and only if one
+//! language clearly dominates.
+
+use log::{debug, warn};
+use std::path::Path;
+
+/// Languages by file extension
+const EXTENSIONS: [(&str, &str); 13] = [
+    ("sol", "solidity"),
+    ("py", "python"),
+    ("pyi", "python"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("jsx", "javascript"),
+    ("java", "java"),
+    ("go", "go"),
+    ("php", "php"),
+    ("phtml", "php"),
+    ("rb", "ruby"),
+    ("rake", "ruby"),
+];
+
+/// Languages of files named without an extension
+const FILE_NAMES: [(&str, &str); 3] = [
+    ("Rakefile", "ruby"),
+    ("Gemfile", "ruby"),
+    ("SConstruct", "python"),
+];
+
+/// Languages by shebang interpreter, version suffixes such as `python3.11` are ignored
+const INTERPRETERS: [(&str, &str); 5] = [
+    ("python", "python"),
+    ("node", "javascript"),
+    ("nodejs", "javascript"),
+    ("ruby", "ruby"),
+    ("php", "php"),
+];
+
+/// Markers which only appear in sources of one language
+const MARKERS: [(&str, &str); 2] = [("<?php", "php"), ("pragma solidity", "solidity")];
+
+/// The language of a source, named like `LanguageSpec::name`, `None` if it cannot be told or its
+/// signals conflict. `path` is the file the source was read from, if any.
+pub fn detect_language(path: Option<&Path>, src: &str) -> Option<&'static str> {
+    let signals = [
+        ("extension", path.and_then(language_of_extension)),
+        ("shebang", language_of_shebang(src)),
+        ("content", language_of_markers(src)),
+    ];
+    let mut found = signals
+        .iter()
+        .filter_map(|(signal, lang)| lang.map(|lang| (*signal, lang)));
+    let (first_signal, language) = match found.next() {
+        Some(first) => first,
+        None => return language_of_lines(src),
+    };
+    if let Some((signal, other)) = found.find(|(_, lang)| *lang != language) {
+        let name = path.map_or("source".into(), |path| path.to_string_lossy());
+        warn!(
+            "{}: the {} is {} but the {} is {}, the language is left undetected",
+            name, first_signal, language, signal, other
+        );
+        return None;
+    }
+    Some(language)
+}
+
+fn language_of_extension(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    if let Some((_, lang)) = FILE_NAMES.iter().find(|(name, _)| *name == file_name) {
+        return Some(lang);
+    }
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, lang)| *lang)
+}
+
+/// The language of the interpreter of a `#!/usr/bin/python3` or `#!/usr/bin/env node` line
+fn language_of_shebang(src: &str) -> Option<&'static str> {
+    let line = src.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // `env -S python3 -u` runs the first word which is not an option
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(interpreter, _)| *interpreter == program)
+        .map(|(_, lang)| *lang)
+}
+
+fn language_of_markers(src: &str) -> Option<&'static str> {
+    MARKERS
+        .iter()
+        .find(|(marker, _)| src.contains(marker))
+        .map(|(_, lang)| *lang)
+}
+
+/// A language and whether a trimmed line is typical of it
+type LinePattern = (&'static str, fn(&str) -> bool);
+
+const LINE_PATTERNS: [LinePattern; 7] = [
+    ("python", |line| {
+        (line.starts_with("def ") && line.ends_with(':'))
+            || line.starts_with("elif ")
+            || (line.starts_with("from ") && line.contains(" import "))
+    }),
+    ("go", |line| {
+        line.starts_with("func ") || (line.starts_with("package ") && !line.ends_with(';'))
+    }),
+    ("java", |line| {
+        let modifier = ["public ", "private ", "protected "]
+            .iter()
+            .any(|modifier| line.starts_with(modifier));
+        (modifier && !line.contains("function")) || line.starts_with("import java")
+    }),
+    ("php", |line| {
+        line.contains("$this->") || (line.contains("function ") && line.contains("($"))
+    }),
+    ("javascript", |line| {
+        (line.starts_with("function ") && !line.contains('$'))
+            || line.starts_with("const ")
+            || line.starts_with("module.exports")
+            || line.contains(") => {")
+    }),
+    ("ruby", |line| {
+        line == "end"
+            || (line.starts_with("def ") && !line.ends_with(':'))
+            || line.starts_with("require '")
+    }),
+    ("solidity", |line| {
+        line.starts_with("contract ") || line.starts_with("modifier ")
+    }),
+];
+
+/// The language most lines are typical of, if it has more than twice as many such lines as all
+/// other languages together
+fn language_of_lines(src: &str) -> Option<&'static str> {
+    let mut counts = [0usize; LINE_PATTERNS.len()];
+    for line in src.lines().map(str::trim) {
+        for (count, (_, is_typical)) in counts.iter_mut().zip(&LINE_PATTERNS) {
+            *count += is_typical(line) as usize;
+        }
+    }
+    let total: usize = counts.iter().sum();
+    let (idx, &best) = counts.iter().enumerate().max_by_key(|(_, count)| **count)?;
+    if best == 0 {
+        return None;
+    }
+    if best <= 2 * (total - best) {
+        debug!(
+            "the lines of a source are typical of several languages: {:?}",
+            counts
+        );
+        return None;
+    }
+    Some(LINE_PATTERNS[idx].0)
+}
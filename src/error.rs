@@ -0,0 +1,113 @@
+//! Errors of the library. Errors of a single input file (`Io`, `Parse` and `Serde`) are
+//! recoverable, a run can skip the file and carry on; the others are fatal.
+
+use std::io;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// options which cannot be used together or are out of range
+    #[error("{0}")]
+    Usage(String),
+    /// tree-sitter could not produce a syntax tree, e.g. because parsing was cancelled
+    #[error("cannot parse {path}")]
+    Parse { path: String },
+    #[error("invalid query: {0}")]
+    Query(#[from] tree_sitter::QueryError),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    /// a grammar built for an incompatible version of tree-sitter
+    #[error(transparent)]
+    Language(#[from] tree_sitter::LanguageError),
+    /// a task file which cannot be loaded, or a task the registry does not have
+    #[error("{0}")]
+    Task(String),
+    /// a `--config` file which cannot be read or parsed
+    #[error("{0}")]
+    Config(String),
+    /// an option of a `--config` file whose value does not fit the option
+    #[error("invalid value of {key} in the config: {message}")]
+    ConfigValue { key: String, message: String },
+    /// the data directory cannot be watched
+    #[error(transparent)]
+    Watch(#[from] notify::Error),
+    /// a stage of a pipeline stopped before the others
+    #[error("pipeline stage stopped: {0}")]
+    Channel(String),
+    /// more inputs of a run failed than --max-failure-rate allows, the output is written anyway
+    #[error("{failed} of {total} {inputs} failed, more than --max-failure-rate {max_rate}")]
+    FailureRate {
+        failed: usize,
+        total: usize,
+        inputs: &'static str,
+        max_rate: f64,
+    },
+    /// a signal stopped the run, the output of the inputs read before is written
+    #[error("interrupted by {name}")]
+    Interrupted { name: &'static str, number: i32 },
+    /// checks of `sparser selftest` failed, the build does not extract what it should
+    #[error("{failed} of {total} selftest checks failed")]
+    Selftest { failed: usize, total: usize },
+    /// datasets checked by `sparser validate` have problems, which are reported as found
+    #[error("{invalid} of {total} datasets are invalid")]
+    Invalid { invalid: usize, total: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Default of --max-failure-rate
+pub const MAX_FAILURE_RATE: f64 = 0.1;
+
+/// A `FailureRate` error if more than `max_rate` of the `total` inputs failed
+pub fn check_failure_rate(
+    failed: usize,
+    total: usize,
+    inputs: &'static str,
+    max_rate: f64,
+) -> Result<()> {
+    match total > 0 && failed as f64 > max_rate * total as f64 {
+        true => Err(Error::FailureRate {
+            failed,
+            total,
+            inputs,
+            max_rate,
+        }),
+        false => Ok(()),
+    }
+}
+
+/// A task of the pipeline panicked or was cancelled
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Error::Channel(e.to_string())
+    }
+}
+
+impl Error {
+    /// Whether the error concerns a single input, which can be skipped
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Error::Io(_) | Error::Parse { .. } | Error::Serde(_))
+    }
+
+    /// Process exit code of the error, following the BSD `sysexits.h` conventions
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // EX_USAGE
+            Error::Usage(_) => 64,
+            // EX_IOERR
+            Error::Io(_) | Error::Watch(_) => 74,
+            // EX_DATAERR
+            Error::Parse { .. }
+            | Error::Serde(_)
+            | Error::FailureRate { .. }
+            | Error::Invalid { .. } => 65,
+            // EX_CONFIG
+            Error::Task(_) | Error::Config(_) | Error::ConfigValue { .. } => 78,
+            // EX_SOFTWARE
+            Error::Query(_) | Error::Language(_) | Error::Channel(_) | Error::Selftest { .. } => 70,
+            // the status a shell reports for a process killed by the signal
+            Error::Interrupted { number, .. } => 128 + number,
+        }
+    }
+}
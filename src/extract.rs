@@ -0,0 +1,1265 @@
+use crate::analysis::{
+    find_class_summaries, find_function_calls, find_function_comments, find_header_comment,
+    LanguageSpec,
+};
+use crate::dataset::{reservoir_sample, seeded_rng};
+use crate::detect::detect_language;
+use crate::error::{check_failure_rate, Error, Result, MAX_FAILURE_RATE};
+use crate::history::{FuncHistory, StaleComment};
+use crate::input::{
+    clone_repos, decode_source, expand_glob, fetch_verified_sources, is_generated, is_glob,
+    normalize_source, visit_input_file, walk_source_files, PartitionArgs, SkipReason, SkipReport,
+    WalkArgs, MAX_FILE_SIZE, STDIN_PATH,
+};
+use crate::layout::CodeLayout;
+use crate::metrics;
+use crate::preview;
+use crate::readme::ReadmeApi;
+use crate::remote::{self, REMOTE_CACHE};
+use crate::shutdown;
+use crate::split::{stable_hash, SplitStrategyArgs};
+use crate::subtoken::{IdentifierStyle, Subtokenizer};
+use crate::task::QueryTask;
+use crate::{
+    data_sample_record, error_node_ratio, get_node_tokens, node_text, required_arg, save_config,
+    save_dataset, write_data_samples, DataSample, OutputArgs, OutputFormat, OutputOptions,
+    FUNC_CALL_ID_MASK, STDOUT_PATH,
+};
+use futures::Stream;
+use log::debug;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tree_sitter::{Parser, QueryCursor, Tree};
+
+/// Extract datasets from a directory of Solidity sources
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtractArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Directory of source files, glob pattern such as `'contracts/**/*.sol'`, `s3://`/`gs://`
+    /// prefix or `http(s)://` URL of a file or archive, `-` extracts a single file read from stdin
+    /// to stdout
+    #[clap(short = 'd', long)]
+    pub data: Option<String>,
+    /// File listing git repository URLs, one per line, which are shallow-cloned and extracted
+    #[clap(long)]
+    pub repos: Option<String>,
+    /// Directory the repositories of --repos are cloned into [default: .sparser/repos]
+    #[clap(long)]
+    pub repo_cache: Option<String>,
+    /// File listing contract addresses, one per line, whose verified sources are downloaded from
+    /// Etherscan (API key in ETHERSCAN_API_KEY) and extracted
+    #[clap(long)]
+    pub addresses: Option<String>,
+    /// Chain of the --addresses contracts [default: 1]
+    #[clap(long)]
+    pub chain_id: Option<u64>,
+    /// Directory the sources of --addresses are downloaded into [default: .sparser/etherscan]
+    #[clap(long)]
+    pub source_cache: Option<String>,
+    /// Comma separated tasks to run: func_call, func_call_comm, func_comm, file_comm, class_comm,
+    /// readme_api, stale_comment, func_history or tasks registered by an embedding crate. Several
+    /// tasks are run on the same syntax trees and each writes its dataset into a subdirectory of
+    /// --out-dir named after it [default: func_call]
+    #[clap(short = 't', long)]
+    pub task: Option<String>,
+    /// TOML file defining a task by a tree-sitter query per language and the capture of each
+    /// output field, which is run instead of --task
+    #[clap(long)]
+    pub task_file: Option<String>,
+    /// Directory of queries overriding the built-in ones, as `<language>/func_call.scm`,
+    /// `<language>/func_comm.scm` and `<language>/class_comm.scm`
+    #[clap(long)]
+    pub query_dir: Option<String>,
+    /// Skip files whose syntax tree has a larger share of ERROR nodes, which are mostly in a
+    /// dialect the grammar cannot handle [default: 0.1]
+    #[clap(long)]
+    pub max_error_ratio: Option<f64>,
+    /// Exit with an error once the dataset is written when a larger share of the files could not
+    /// be read, parsed or extracted [default: 0.1]
+    #[clap(long)]
+    pub max_failure_rate: Option<f64>,
+    /// Layout of the code of samples: raw, collapsed onto one line or normalized-indent, dedented
+    /// and indented by levels of four spaces. Task files lay out their fields with their
+    /// post-processors instead. [default: that of the language for func_call, normalized-indent
+    /// for the built-in languages, raw for the other tasks]
+    #[clap(long)]
+    pub code_layout: Option<CodeLayout>,
+    /// Write the code of samples of every task with its whitespace as in the source, so that
+    /// the line breaks and indentation the code needs to parse are kept
+    #[clap(long, conflicts_with = "code-layout")]
+    pub preserve_layout: bool,
+    /// Also pair functions with themselves for their recursive calls in func_call and
+    /// func_call_comm, which are skipped by default. The samples of the run then have a
+    /// `recursive` field telling them apart.
+    #[clap(long)]
+    pub include_recursive: bool,
+    /// Skip files of more lines in file_comm, whose samples are whole files, 0 for no limit
+    /// [default: 1000]
+    #[clap(long)]
+    pub max_file_lines: Option<usize>,
+    /// Comma separated identifier styles, camel and snake, whose identifiers are split into
+    /// lowercase sub-tokens in the token lists of func_call, e.g. `parseHttpResponse` into
+    /// `parse`, `http` and `response` [default: identifiers are kept whole]
+    #[clap(long, use_value_delimiter = true)]
+    pub subtokenize: Option<Vec<IdentifierStyle>>,
+    /// Negative samples of func_call_comm per positive sample, with callees of the same file the
+    /// caller does not call and, when those run out, of the other extracted files [default: 1]
+    #[clap(long)]
+    pub negative_ratio: Option<usize>,
+    /// Keep a uniform random sample of at most N samples of each task, drawn in one pass by
+    /// reservoir sampling once the labels are balanced
+    #[clap(long)]
+    pub max_samples: Option<usize>,
+    /// Seed of the negative samples and of the sampling of --max-samples
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Only extract the first N files, to try a configuration on a slice of the corpus
+    #[clap(long)]
+    pub limit_files: Option<usize>,
+    /// Stop extracting once N samples were extracted, to try a configuration on a slice of the
+    /// corpus
+    #[clap(long)]
+    pub limit_samples: Option<usize>,
+    /// Print the first N samples for reading instead of writing a dataset, to check what a task
+    /// extracts before a full run
+    #[clap(long)]
+    pub preview: Option<usize>,
+    /// Output directory or `s3://`/`gs://` prefix
+    #[clap(short = 'o', long)]
+    pub out_dir: Option<String>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub walk: WalkArgs,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub partition: PartitionArgs,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+    /// Write a HuggingFace datasets compatible directory layout
+    #[clap(long)]
+    pub hf_layout: bool,
+    /// Also write a dataset card stub in the README of the HuggingFace layout
+    #[clap(long)]
+    pub hf_readme: bool,
+    /// Also write the frequencies of the tokens and sub-tokens of each split, as `vocab.tsv` in
+    /// its directory in the HuggingFace layout or `<split>.vocab.tsv` next to it otherwise
+    #[clap(long)]
+    pub vocab: bool,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub split: SplitStrategyArgs,
+}
+
+/// A parsed source file handed to the extractors
+pub struct FileCtx<'a> {
+    /// Path of the file, `archive/member` for members of archives
+    pub path: &'a str,
+    pub tree: &'a Tree,
+    pub spec: LanguageSpec,
+    /// layout of the code of samples, `None` for the default of each task and language
+    pub layout: Option<CodeLayout>,
+    /// whether functions are paired with themselves for their recursive calls
+    pub include_recursive: bool,
+    /// lines of the longest file of `file_comm` samples, `None` for the default
+    pub max_file_lines: Option<usize>,
+    /// how the identifiers of token lists are split, `None` to keep them whole
+    pub subtokenizer: Option<Subtokenizer>,
+    /// seed of the random choices of extractors, `None` for fresh entropy
+    pub seed: Option<u64>,
+}
+
+impl FileCtx<'_> {
+    /// `code` laid out as asked, as it is by default
+    pub fn lay_out(&self, code: &str) -> String {
+        match self.layout {
+            Some(layout) => layout.apply(code),
+            None => code.to_string(),
+        }
+    }
+
+    /// `tokens` with their identifiers split as asked, as they are by default
+    pub fn subtokens(&self, tokens: Vec<String>) -> Vec<String> {
+        match self.subtokenizer {
+            Some(subtokenizer) => subtokenizer.apply(&tokens),
+            None => tokens,
+        }
+    }
+
+    /// Whether the calls of `caller` to `callee` are paired, recursive calls only if asked for
+    pub fn pairs_call(&self, caller: &str, callee: &str) -> bool {
+        self.include_recursive || caller != callee
+    }
+
+    /// Generator of the random choices of extractors, seeded by the path of the file as well so
+    /// that the choices of a file do not depend on the files extracted before it
+    pub fn rng(&self) -> StdRng {
+        let path_hash = stable_hash(self.path.as_bytes());
+        seeded_rng(self.seed.map(|seed| seed ^ path_hash))
+    }
+
+    /// The `recursive` field of the samples of a call of `callee` by `caller`, which only runs
+    /// with --include-recursive have
+    pub fn recursive_tag(&self, caller: &str, callee: &str) -> Option<bool> {
+        match self.include_recursive {
+            true => Some(caller == callee),
+            false => None,
+        }
+    }
+}
+
+/// An extraction task, producing the samples of a source file
+pub trait Extractor: Send + Sync {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>>;
+}
+
+/// Extraction tasks by the name they are selected with by `--task`. The default registry holds
+/// the built-in tasks, other crates can register their own and pass the registry to `run_sync`
+/// or `run_async`.
+pub struct ExtractorRegistry {
+    extractors: BTreeMap<String, Arc<dyn Extractor>>,
+}
+
+impl ExtractorRegistry {
+    /// A registry without any tasks
+    pub fn empty() -> Self {
+        ExtractorRegistry {
+            extractors: BTreeMap::new(),
+        }
+    }
+
+    /// Register a task, replacing a task of the same name
+    pub fn register<E: Extractor + 'static>(&mut self, name: &str, extractor: E) {
+        self.extractors
+            .insert(name.to_string(), Arc::new(extractor));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Extractor>> {
+        self.extractors.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.extractors.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+impl Default for ExtractorRegistry {
+    fn default() -> Self {
+        let mut registry = ExtractorRegistry::empty();
+        registry.register("func_call", FuncCall);
+        registry.register("func_call_comm", FuncCallComm);
+        registry.register("func_comm", FuncComm);
+        registry.register("file_comm", FileComm);
+        registry.register("class_comm", ClassComm);
+        registry.register("readme_api", ReadmeApi);
+        registry.register("stale_comment", StaleComment);
+        registry.register("func_history", FuncHistory);
+        registry
+    }
+}
+
+/// Default of --max-error-ratio
+pub const MAX_ERROR_RATIO: f64 = 0.1;
+/// Default of --negative-ratio
+pub const NEGATIVE_RATIO: usize = 1;
+/// Default of --max-file-lines
+pub const MAX_FILE_LINES: usize = 1000;
+
+/// Number of samples `Samples::into_stream` extracts ahead of the consumer
+const STREAM_BUFFER: usize = 1024;
+
+/// Counts of the files an extraction converted, normalized or skipped
+#[derive(Debug, Default)]
+pub struct ExtractSummary {
+    /// files and archive members visited
+    pub num_files: usize,
+    /// files which could not be read, parsed or extracted, unreadable files are also skipped
+    pub num_failed: usize,
+    /// files which were not UTF-8
+    pub num_converted: usize,
+    /// files with a byte order mark, CRLF line endings and mixed indentation
+    pub num_normalized: (usize, usize, usize),
+    pub skipped: SkipReport,
+}
+
+/// The samples of a list of source files or archives, which are read, parsed and extracted one
+/// file at a time as the iterator advances. Every extractor runs on the syntax tree of a file,
+/// samples are yielded with the index of their extractor. Files which cannot be read are
+/// reported as errors and recorded in the summary like the files skipped by the filters of
+/// `WalkArgs`.
+pub struct Samples {
+    extractors: Vec<Arc<dyn Extractor>>,
+    parser: Parser,
+    spec: LanguageSpec,
+    paths: std::vec::IntoIter<PathBuf>,
+    num_paths: usize,
+    max_file_size: u64,
+    max_error_ratio: f64,
+    skip_non_utf8: bool,
+    keep_generated: bool,
+    layout: Option<CodeLayout>,
+    include_recursive: bool,
+    max_file_lines: Option<usize>,
+    subtokenizer: Option<Subtokenizer>,
+    seed: Option<u64>,
+    progress: bool,
+    /// samples and errors of the current file
+    pending: VecDeque<Result<(usize, DataSample)>>,
+    summary: ExtractSummary,
+}
+
+impl Samples {
+    /// Extract `paths` with the file filters of `walk`, skipping files with a larger share of
+    /// ERROR nodes than `max_error_ratio`
+    pub fn new(
+        paths: Vec<PathBuf>,
+        extractors: Vec<Arc<dyn Extractor>>,
+        walk: &WalkArgs,
+        max_error_ratio: f64,
+    ) -> Result<Self> {
+        let spec = LanguageSpec::solidity();
+        let mut parser = Parser::new();
+        parser.set_language(spec.language)?;
+        Ok(Samples {
+            extractors,
+            parser,
+            spec,
+            num_paths: paths.len(),
+            paths: paths.into_iter(),
+            max_file_size: match walk.max_file_size.unwrap_or(MAX_FILE_SIZE) {
+                0 => u64::MAX,
+                size => size,
+            },
+            max_error_ratio,
+            skip_non_utf8: walk.skip_non_utf8,
+            keep_generated: walk.keep_generated,
+            layout: None,
+            include_recursive: false,
+            max_file_lines: None,
+            subtokenizer: None,
+            seed: None,
+            progress: false,
+            pending: VecDeque::new(),
+            summary: ExtractSummary::default(),
+        })
+    }
+
+    /// Parse and extract with `spec` rather than the built-in Solidity spec
+    pub fn with_spec(mut self, spec: LanguageSpec) -> Result<Self> {
+        self.parser.set_language(spec.language)?;
+        self.spec = spec;
+        Ok(self)
+    }
+
+    /// Lay out the code of samples as `layout`, `None` for the default of each task
+    pub fn with_layout(mut self, layout: Option<CodeLayout>) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Pair functions with themselves for their recursive calls
+    pub fn with_recursive(mut self, include_recursive: bool) -> Self {
+        self.include_recursive = include_recursive;
+        self
+    }
+
+    /// Skip files of more than `max_file_lines` lines in `file_comm`, `None` for the default
+    pub fn with_max_file_lines(mut self, max_file_lines: Option<usize>) -> Self {
+        self.max_file_lines = max_file_lines;
+        self
+    }
+
+    /// Split the identifiers of token lists with `subtokenizer`, `None` to keep them whole
+    pub fn with_subtokenizer(mut self, subtokenizer: Option<Subtokenizer>) -> Self {
+        self.subtokenizer = subtokenizer;
+        self
+    }
+
+    /// Seed the random choices of extractors with `seed`, `None` for fresh entropy
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Print the number of files extracted so far
+    pub fn show_progress(mut self) -> Self {
+        self.progress = true;
+        self
+    }
+
+    /// The files converted, normalized and skipped so far
+    pub fn summary(&self) -> &ExtractSummary {
+        &self.summary
+    }
+
+    pub fn into_summary(self) -> ExtractSummary {
+        self.summary
+    }
+
+    /// Number of files not extracted yet
+    pub fn num_remaining(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Extract on a blocking thread of the tokio runtime, at most a bounded number of samples
+    /// ahead of the consumer
+    pub fn into_stream(self) -> impl Stream<Item = Result<(usize, DataSample)>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            for sample in self {
+                if metrics::timed_send(|| tx.blocking_send(sample)).is_err() {
+                    break;
+                }
+            }
+        });
+        tokio_stream::wrappers::ReceiverStream::new(rx)
+    }
+
+    fn extract_file(&mut self, file_path: &Path) {
+        let Samples {
+            extractors,
+            parser,
+            spec,
+            max_file_size,
+            max_error_ratio,
+            skip_non_utf8,
+            keep_generated,
+            layout,
+            include_recursive,
+            max_file_lines,
+            subtokenizer,
+            seed,
+            pending,
+            summary,
+            ..
+        } = self;
+        let result = visit_input_file(file_path, |name, reader| {
+            summary.num_files += 1;
+            let mut bytes = Vec::new();
+            // archive members have no size up front, so the size is checked while reading
+            let read = reader
+                .take(max_file_size.saturating_add(1))
+                .read_to_end(&mut bytes);
+            if let Ok(len) = read {
+                metrics::add_file(len as u64);
+            }
+            match read {
+                Ok(len) if len as u64 > *max_file_size => {
+                    let detail = format!("more than {} bytes", max_file_size);
+                    summary.skipped.skip(name, SkipReason::Size, Some(detail));
+                }
+                Ok(_) => match decode_source(bytes) {
+                    (_, Some(encoding)) if *skip_non_utf8 => {
+                        summary.skipped.skip(
+                            name,
+                            SkipReason::Encoding,
+                            Some(encoding.to_string()),
+                        );
+                    }
+                    (src, _) if !*keep_generated && is_generated(&src) => {
+                        summary.skipped.skip(name, SkipReason::Generated, None);
+                    }
+                    (src, encoding) => {
+                        if encoding.is_some() {
+                            summary.num_converted += 1;
+                        }
+                        let (src, normalization) = normalize_source(src);
+                        summary.num_normalized.0 += normalization.bom as usize;
+                        summary.num_normalized.1 += normalization.crlf as usize;
+                        summary.num_normalized.2 += normalization.mixed_indent as usize;
+                        // files of repositories are of any language, only those detected as
+                        // another language are skipped as the others may be of the grammar
+                        match detect_language(Some(Path::new(name)), &src) {
+                            Some(lang) if lang != spec.name => {
+                                let detail = Some(lang.to_string());
+                                summary.skipped.skip(name, SkipReason::Language, detail);
+                                return Ok(());
+                            }
+                            _ => {}
+                        }
+                        let parsed = match parser.parse(&src, None) {
+                            Some(parsed) => parsed,
+                            None => {
+                                metrics::add_parse_failure();
+                                summary.num_failed += 1;
+                                let path = name.to_string();
+                                pending.push_back(Err(Error::Parse { path }));
+                                return Ok(());
+                            }
+                        };
+                        let error_ratio = error_node_ratio(&parsed);
+                        if error_ratio > *max_error_ratio {
+                            let detail = format!("{:.3} of ERROR nodes", error_ratio);
+                            summary
+                                .skipped
+                                .skip(name, SkipReason::ParseErrors, Some(detail));
+                            return Ok(());
+                        }
+                        let ctx = FileCtx {
+                            path: name,
+                            tree: &parsed,
+                            spec: *spec,
+                            layout: *layout,
+                            include_recursive: *include_recursive,
+                            max_file_lines: *max_file_lines,
+                            subtokenizer: *subtokenizer,
+                            seed: *seed,
+                        };
+                        let mut failed = false;
+                        for (idx, extractor) in extractors.iter().enumerate() {
+                            match extractor.extract(&src, &ctx) {
+                                Ok(samples) => {
+                                    metrics::add_samples(samples.len());
+                                    pending.extend(samples.into_iter().map(|s| Ok((idx, s))))
+                                }
+                                Err(e) => {
+                                    failed = true;
+                                    pending.push_back(Err(e))
+                                }
+                            }
+                        }
+                        summary.num_failed += failed as usize;
+                    }
+                },
+                Err(e) => {
+                    summary.num_failed += 1;
+                    summary
+                        .skipped
+                        .skip(name, SkipReason::Unreadable, Some(e.to_string()));
+                    let message = format!("{} NOT FOUND: {}", name, e);
+                    pending.push_back(Err(io::Error::new(e.kind(), message).into()));
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            let name = file_path.to_string_lossy();
+            summary.num_files += 1;
+            summary.num_failed += 1;
+            summary
+                .skipped
+                .skip(&name, SkipReason::Unreadable, Some(e.to_string()));
+            let message = format!("{} NOT FOUND: {}", name, e);
+            pending.push_back(Err(io::Error::new(e.kind(), message).into()));
+        }
+    }
+}
+
+impl Iterator for Samples {
+    type Item = Result<(usize, DataSample)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(sample) = self.pending.pop_front() {
+                return Some(sample);
+            }
+            // an interrupted run ends with the samples of the files extracted so far
+            if shutdown::requested() {
+                return None;
+            }
+            let path = self.paths.next()?;
+            if self.progress {
+                let done = self.num_paths - self.paths.len();
+                print!("\x1b[K\r{}/{}", done, self.num_paths);
+            }
+            self.extract_file(&path);
+        }
+    }
+}
+
+/// Label counts of the `FuncCallComm` samples of a task after balancing
+#[derive(Debug, Default)]
+struct LabelBalance {
+    num_positive: usize,
+    num_negative: usize,
+    /// negative samples with a callee of another file
+    num_backfilled: usize,
+    /// negative samples missing as the callers call all extracted functions
+    num_missing: usize,
+}
+
+/// Balance the `FuncCallComm` samples to `ratio` negative samples per positive sample of each
+/// caller. Surplus negative samples are dropped, missing ones are drawn from the callees of all
+/// samples which the caller does not call and placed after its last positive sample. Other
+/// samples are kept as they are.
+///
+/// The code of a caller is masked differently for each of its callees, so the callees it calls
+/// are gathered by its comment and the shape of its code, see `caller_shape`.
+fn balance_labels(
+    samples: Vec<DataSample>,
+    ratio: usize,
+    rng: &mut impl Rng,
+) -> (Vec<DataSample>, LabelBalance) {
+    // positive and negative samples of each masked caller, and callees of each caller
+    let mut callers: HashMap<(&str, &str), (usize, usize)> = HashMap::new();
+    let mut callees: HashMap<(u64, &str), HashSet<&str>> = HashMap::new();
+    let mut pool = BTreeSet::new();
+    for sample in &samples {
+        if let DataSample::FuncCallComm(
+            caller_code,
+            caller_comm,
+            callee_code,
+            callee_comm,
+            label,
+            _,
+        ) = sample
+        {
+            let caller = callers.entry((caller_code, caller_comm)).or_default();
+            match label {
+                true => caller.0 += 1,
+                false => caller.1 += 1,
+            }
+            callees
+                .entry((caller_shape(caller_code), caller_comm.as_str()))
+                .or_default()
+                .insert(callee_code.as_str());
+            pool.insert((callee_code.as_str(), callee_comm.as_str()));
+        }
+    }
+    let num_positive: usize = callers.values().map(|caller| caller.0).sum();
+    if num_positive == 0 {
+        return (samples, LabelBalance::default());
+    }
+    let mut pool: Vec<(&str, &str)> = pool.into_iter().collect();
+    pool.shuffle(rng);
+    // negative samples of the file kept and positive samples not seen yet of each caller
+    let mut progress: HashMap<(&str, &str), (usize, usize)> = HashMap::new();
+    let mut balanced = Vec::new();
+    let (mut num_negative, mut num_backfilled, mut num_missing) = (0, 0, 0);
+    for sample in &samples {
+        let (caller_code, caller_comm, label, recursive) = match sample {
+            DataSample::FuncCallComm(caller_code, caller_comm, _, _, label, recursive) => {
+                (caller_code, caller_comm, *label, *recursive)
+            }
+            _ => {
+                balanced.push(sample.clone());
+                continue;
+            }
+        };
+        let key = (caller_code.as_str(), caller_comm.as_str());
+        let (positives, negatives) = &callers[&key];
+        let callees = &callees[&(caller_shape(caller_code), caller_comm.as_str())];
+        let target = ratio * positives;
+        let (kept, positives_left) = progress.entry(key).or_insert((0, *positives));
+        if !label {
+            if *kept < target {
+                *kept += 1;
+                num_negative += 1;
+                balanced.push(sample.clone());
+            }
+            continue;
+        }
+        balanced.push(sample.clone());
+        *positives_left -= 1;
+        if *positives_left > 0 {
+            continue;
+        }
+        // negative samples of the file may follow the last positive sample of the caller
+        let missing = target - (*negatives).min(target);
+        let start = rng.gen_range(0..pool.len());
+        let backfill: Vec<&(&str, &str)> = pool[start..]
+            .iter()
+            .chain(&pool[..start])
+            .filter(|(code, _)| !callees.contains(code))
+            .take(missing)
+            .collect();
+        num_backfilled += backfill.len();
+        num_missing += missing - backfill.len();
+        for (callee_code, callee_comm) in backfill {
+            balanced.push(DataSample::FuncCallComm(
+                caller_code.clone(),
+                caller_comm.clone(),
+                callee_code.to_string(),
+                callee_comm.to_string(),
+                false,
+                recursive.map(|_| false),
+            ));
+        }
+    }
+    let balance = LabelBalance {
+        num_positive,
+        num_negative: num_negative + num_backfilled,
+        num_backfilled,
+        num_missing,
+    };
+    (balanced, balance)
+}
+
+/// Hash of the code of a caller with each identifier, the masked callee among them, collapsed to
+/// one character, which is the same for all the ways the caller is masked
+fn caller_shape(masked_code: &str) -> u64 {
+    let code = masked_code.replace(FUNC_CALL_ID_MASK, "_");
+    let mut shape = Vec::with_capacity(code.len());
+    let mut in_identifier = false;
+    for byte in code.bytes() {
+        let is_word = byte == b'_' || byte.is_ascii_alphanumeric();
+        match (is_word, in_identifier) {
+            (true, true) => continue,
+            (true, false) if !byte.is_ascii_digit() => {
+                in_identifier = true;
+                shape.push(b'_');
+                continue;
+            }
+            _ => in_identifier = false,
+        }
+        shape.push(byte);
+    }
+    stable_hash(&shape)
+}
+
+/// `func_call_comm`: caller-callee pairs with their comments, each followed by a negative sample
+pub struct FuncCallComm;
+
+impl Extractor for FuncCallComm {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let (root, spec) = (ctx.tree.root_node(), &ctx.spec);
+        let (func_code_map, func_comm_map) = find_function_comments(spec, code, root)?;
+
+        // find all function calls
+        let calling_pairs =
+            find_function_calls(spec, code, root, |func| func_comm_map.contains_key(func))?;
+        // generate dataset
+        let mut rng = ctx.rng();
+        let mut samples = HashSet::new();
+        for (caller, callee) in &calling_pairs {
+            if !ctx.pairs_call(caller, callee) {
+                continue;
+            }
+            if let (
+                Some(caller_code),
+                Some(caller_comment),
+                Some(callee_code),
+                Some(callee_comment),
+            ) = (
+                func_code_map.get(caller),
+                func_comm_map.get(caller),
+                func_code_map.get(callee),
+                func_comm_map.get(callee),
+            ) {
+                let masked_caller_code =
+                    ctx.lay_out(caller_code).replace(callee, FUNC_CALL_ID_MASK);
+                samples.insert(DataSample::FuncCallComm(
+                    masked_caller_code.clone(),
+                    caller_comment.clone(),
+                    ctx.lay_out(callee_code),
+                    callee_comment.clone(),
+                    true,
+                    ctx.recursive_tag(caller, callee),
+                ));
+                // a negative sample with a function of the file the caller does not call,
+                // callers without one are balanced with functions of other files later
+                let non_callees: Vec<&&str> = func_comm_map
+                    .keys()
+                    .filter(|name| *name != caller && func_code_map.contains_key(*name))
+                    .filter(|name| !calling_pairs.contains(&(*caller, **name)))
+                    .collect();
+                if let Some(name) = non_callees.choose(&mut rng) {
+                    samples.insert(DataSample::FuncCallComm(
+                        masked_caller_code,
+                        caller_comment.clone(),
+                        ctx.lay_out(func_code_map[**name]),
+                        func_comm_map[**name].clone(),
+                        false,
+                        ctx.recursive_tag(caller, name),
+                    ));
+                }
+            }
+        }
+
+        Ok(samples.into_iter().collect::<Vec<DataSample>>())
+    }
+}
+
+const FUNC_BODY_QUERY_FILE: &str = "./query/func_body.sexp";
+
+lazy_static::lazy_static! {
+    static ref FUNC_BODY_QUERY: std::result::Result<String, String> =
+        fs::read_to_string(FUNC_BODY_QUERY_FILE)
+            .map_err(|e| format!("{}: {}", FUNC_BODY_QUERY_FILE, e));
+}
+
+/// The query of `func_call`, read from `FUNC_BODY_QUERY_FILE` once per process
+fn func_body_query() -> io::Result<&'static str> {
+    match &*FUNC_BODY_QUERY {
+        Ok(query) => Ok(query),
+        Err(message) => Err(io::Error::new(io::ErrorKind::NotFound, message.clone())),
+    }
+}
+
+/// `func_call`: caller-callee pairs with their tokens
+pub struct FuncCall;
+
+impl Extractor for FuncCall {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let (root, spec) = (ctx.tree.root_node(), &ctx.spec);
+        let fc_query = spec.compiled_query(func_body_query()?)?;
+        let mut fc_qc = QueryCursor::new();
+        let matches = fc_qc.matches(fc_query, root, |_| code.as_bytes());
+        let mut func_src_map: HashMap<&str, (String, Vec<String>)> = HashMap::new();
+        let mut dup_funcs = HashSet::new(); // duplicated function names are ignore for simplicity
+        for m in matches {
+            // match a function name with its comment
+            let mut name = "";
+            let mut func_body = "".to_string();
+            let mut func_tokens = Vec::new();
+            for capture in m.captures {
+                let capture_name = &fc_query.capture_names()[capture.index as usize];
+                match capture_name.as_str() {
+                    "name" => {
+                        name = node_text(capture.node, code);
+                        if dup_funcs.contains(name) {
+                            continue;
+                        }
+                        if func_src_map.contains_key(name) {
+                            dup_funcs.insert(name);
+                            func_src_map.remove(name);
+                        }
+                    }
+                    "func_body" => {
+                        let body = node_text(capture.node, code);
+                        func_body = match ctx.layout.unwrap_or(ctx.spec.default_layout) {
+                            // collapsed bodies end with a space, as they always did
+                            CodeLayout::Collapsed => {
+                                format!("{} ", CodeLayout::Collapsed.apply(body))
+                            }
+                            layout => layout.apply(body),
+                        };
+                        func_tokens = ctx.subtokens(get_node_tokens(capture.node, code));
+                    }
+                    unhandled => {
+                        debug!("unhandled capture {} of the function body query", unhandled);
+                    }
+                }
+            }
+            func_src_map.insert(name, (func_body, func_tokens));
+        }
+
+        // find all function calls
+        let calling_pairs =
+            find_function_calls(spec, code, root, |func| func_src_map.contains_key(func))?;
+        // generate dataset, in name order as the order of a hash set changes from run to run
+        let mut calling_pairs: Vec<(&str, &str)> = calling_pairs.into_iter().collect();
+        calling_pairs.sort_unstable();
+        let mut seen = HashSet::new();
+        let mut samples = Vec::new();
+        for (caller, callee) in &calling_pairs {
+            if !ctx.pairs_call(caller, callee) {
+                continue;
+            }
+            if let (Some((caller_code, caller_tokens)), Some((callee_code, callee_tokens))) =
+                (func_src_map.get(caller), func_src_map.get(callee))
+            {
+                let sample = DataSample::FuncCall(
+                    caller_code.clone(),
+                    caller_tokens.clone(),
+                    callee_code.clone(),
+                    callee_tokens.clone(),
+                    ctx.recursive_tag(caller, callee),
+                );
+                if seen.insert(sample.clone()) {
+                    samples.push(sample);
+                }
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// `func_comm`: functions with their comments
+pub struct FuncComm;
+
+impl Extractor for FuncComm {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let (root, spec) = (ctx.tree.root_node(), &ctx.spec);
+        let (func_code, func_comments) = find_function_comments(spec, code, root)?;
+        // generate dataset
+        let mut samples = Vec::new();
+        for (name, comment) in func_comments {
+            if comment.is_empty() {
+                continue;
+            }
+            if let Some(src) = func_code.get(name) {
+                samples.push(DataSample::FuncComm(ctx.lay_out(src), comment));
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// `file_comm`: files without their header comment or module docstring, paired with it
+pub struct FileComm;
+
+impl Extractor for FileComm {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let max_lines = ctx.max_file_lines.unwrap_or(MAX_FILE_LINES);
+        if max_lines > 0 && code.lines().count() > max_lines {
+            return Ok(Vec::new());
+        }
+        let (comment, ranges) = match find_header_comment(&ctx.spec, code, ctx.tree.root_node()) {
+            Some(header) => header,
+            None => return Ok(Vec::new()),
+        };
+        let (mut src, mut end) = (String::new(), 0);
+        for range in ranges {
+            src.push_str(&code[end..range.start]);
+            // the lines of the header are removed with their line breaks
+            end = match code[range.end..].starts_with('\n') {
+                true => range.end + 1,
+                false => range.end,
+            };
+        }
+        src.push_str(&code[end..]);
+        let src = src.trim();
+        match src.is_empty() {
+            true => Ok(Vec::new()),
+            false => Ok(vec![DataSample::FileComm(ctx.lay_out(src), comment)]),
+        }
+    }
+}
+
+/// `class_comm`: classes as their declaration and the signatures and documentation of their
+/// methods, with the documentation of the class
+pub struct ClassComm;
+
+impl Extractor for ClassComm {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let summaries = find_class_summaries(&ctx.spec, code, ctx.tree.root_node())?;
+        Ok(summaries
+            .into_iter()
+            .map(|(skeleton, doc)| DataSample::ClassComm(ctx.lay_out(&skeleton), doc))
+            .collect())
+    }
+}
+
+pub fn run(args: ExtractArgs) -> Result<()> {
+    run_sync(args, &ExtractorRegistry::default())
+}
+
+/// Run an extraction with the tasks of `registry` on the current thread. Files which cannot be
+/// read or parsed are skipped, other errors end the run.
+pub fn run_sync(mut args: ExtractArgs, registry: &ExtractorRegistry) -> Result<()> {
+    let tasks = task_extractors(&mut args, registry)?;
+    run_extractors(args, tasks)
+}
+
+/// Run an extraction with the tasks of `registry` on a blocking thread of the tokio runtime of
+/// the caller, as parsing is CPU bound
+pub async fn run_async(mut args: ExtractArgs, registry: &ExtractorRegistry) -> Result<()> {
+    let tasks = task_extractors(&mut args, registry)?;
+    tokio::task::spawn_blocking(move || run_extractors(args, tasks)).await?
+}
+
+/// Print the first `num_samples` samples of `samples`, which are not balanced or sampled
+fn print_preview(
+    mut samples: Samples,
+    tasks: &[(String, Arc<dyn Extractor>)],
+    num_samples: usize,
+) -> Result<()> {
+    let color = preview::use_color();
+    let mut num_shown = 0;
+    while num_shown < num_samples {
+        match samples.next() {
+            Some(Ok((idx, sample))) => {
+                num_shown += 1;
+                let title = format!("{} sample {}", tasks[idx].0, num_shown);
+                let record = data_sample_record(&sample);
+                print!("{}", preview::format_record(&title, &record, color));
+            }
+            Some(Err(e)) if e.is_recoverable() => eprintln!("{}", e),
+            Some(Err(e)) => return Err(e),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// The names and extractors of `--task-file` or `--task`, a `Task` error if the registry has no
+/// such task
+fn task_extractors(
+    args: &mut ExtractArgs,
+    registry: &ExtractorRegistry,
+) -> Result<Vec<(String, Arc<dyn Extractor>)>> {
+    if let Some(task_file) = &args.task_file {
+        let task = QueryTask::load(task_file)?;
+        args.task = Some(task.name.clone());
+        return Ok(vec![(task.name.clone(), Arc::new(task))]);
+    }
+    let tasks = args.task.get_or_insert("func_call".to_string());
+    let mut extractors: Vec<(String, Arc<dyn Extractor>)> = Vec::new();
+    for task in tasks.split(',').map(|task| task.trim()) {
+        let extractor = registry.get(task).ok_or_else(|| {
+            Error::Task(format!(
+                "unknown task {}, available tasks: {}",
+                task,
+                registry.names().join(", ")
+            ))
+        })?;
+        if extractors.iter().all(|(name, _)| name != task) {
+            extractors.push((task.to_string(), extractor));
+        }
+    }
+    Ok(extractors)
+}
+
+fn run_extractors(mut args: ExtractArgs, tasks: Vec<(String, Arc<dyn Extractor>)>) -> Result<()> {
+    metrics::start();
+    args.output.format.get_or_insert(OutputFormat::Jsonl);
+    args.partition.check()?;
+    let negative_ratio = *args.negative_ratio.get_or_insert(NEGATIVE_RATIO);
+    let spec = match &args.query_dir {
+        Some(query_dir) => LanguageSpec::solidity().with_query_dir(Path::new(query_dir))?,
+        None => LanguageSpec::solidity(),
+    };
+    let subtokenizer = args.subtokenize.as_deref().and_then(Subtokenizer::new);
+    let layout = match args.preserve_layout {
+        true => Some(CodeLayout::Raw),
+        false => args.code_layout,
+    };
+    // a single source file on stdin is extracted to stdout
+    if args.data.as_deref() == Some(STDIN_PATH) {
+        let mut parser = Parser::new();
+        parser.set_language(spec.language)?;
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+        let (src, _) = normalize_source(decode_source(bytes).0);
+        let parsed = parser.parse(&src, None).ok_or_else(|| {
+            metrics::add_parse_failure();
+            Error::Parse {
+                path: STDIN_PATH.to_string(),
+            }
+        })?;
+        let ctx = FileCtx {
+            path: STDIN_PATH,
+            tree: &parsed,
+            spec,
+            layout,
+            include_recursive: args.include_recursive,
+            max_file_lines: args.max_file_lines,
+            subtokenizer,
+            seed: args.seed,
+        };
+        let mut rng = seeded_rng(args.seed);
+        for (_, extractor) in &tasks {
+            let samples = extractor.extract(&src, &ctx)?;
+            let (samples, _) = balance_labels(samples, negative_ratio, &mut rng);
+            write_data_samples(&samples, STDOUT_PATH, &args.output.to_options())?;
+        }
+        return Ok(());
+    }
+    let mut roots: Vec<PathBuf> = Vec::new();
+    if let Some(data) = &args.data {
+        roots.extend(match data {
+            data if remote::is_remote(data) => vec![remote::download(data, REMOTE_CACHE)?],
+            data if is_glob(data) => expand_glob(data)?,
+            data => vec![PathBuf::from(data)],
+        });
+    }
+    if let Some(repo_list) = &args.repos {
+        let cache_dir = args.repo_cache.get_or_insert(".sparser/repos".to_string());
+        roots.extend(clone_repos(repo_list, cache_dir)?);
+    }
+    if let Some(address_list) = &args.addresses {
+        let api_key = std::env::var("ETHERSCAN_API_KEY").unwrap_or_else(|_| {
+            clap::Error::raw(
+                clap::ErrorKind::MissingRequiredArgument,
+                "the ETHERSCAN_API_KEY environment variable is required for --addresses\n",
+            )
+            .exit()
+        });
+        let cache_dir = args
+            .source_cache
+            .get_or_insert(".sparser/etherscan".to_string());
+        let chain_id = *args.chain_id.get_or_insert(1);
+        roots.extend(fetch_verified_sources(
+            address_list,
+            cache_dir,
+            chain_id,
+            &api_key,
+        )?);
+    }
+    if args.data.is_none() && args.repos.is_none() && args.addresses.is_none() {
+        required_arg(args.data.clone(), "--data, --repos or --addresses");
+    }
+    let paths: Vec<PathBuf> = roots
+        .iter()
+        .flat_map(|root| walk_source_files(root, &args.walk))
+        .filter(|path| args.partition.contains(path))
+        .take(args.limit_files.unwrap_or(usize::MAX))
+        .collect();
+    args.walk.max_file_size.get_or_insert(MAX_FILE_SIZE);
+    let max_error_ratio = *args.max_error_ratio.get_or_insert(MAX_ERROR_RATIO);
+    let max_failure_rate = *args.max_failure_rate.get_or_insert(MAX_FAILURE_RATE);
+    let extractors = tasks
+        .iter()
+        .map(|(_, extractor)| extractor.clone())
+        .collect();
+    if let Some(num_samples) = args.preview {
+        let samples = Samples::new(paths, extractors, &args.walk, max_error_ratio)?
+            .with_spec(spec)?
+            .with_layout(layout)
+            .with_recursive(args.include_recursive)
+            .with_max_file_lines(args.max_file_lines)
+            .with_subtokenizer(subtokenizer)
+            .with_seed(args.seed);
+        return print_preview(samples, &tasks, num_samples);
+    }
+    let out_dir = required_arg(args.out_dir.clone(), "--out-dir");
+    let out_dir = out_dir.strip_suffix("/").unwrap_or(&out_dir).to_string();
+    // remote datasets are written locally and uploaded when complete
+    let remote_out = match remote::is_remote(&out_dir) {
+        true => Some(remote::stage_output(&out_dir, REMOTE_CACHE)?),
+        false => None,
+    };
+    let local_out = match &remote_out {
+        Some(dir) => dir.to_string_lossy().into_owned(),
+        None => out_dir.clone(),
+    };
+    let input_files: Vec<String> = paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    let mut samples = Samples::new(paths, extractors, &args.walk, max_error_ratio)?
+        .with_spec(spec)?
+        .with_layout(layout)
+        .with_recursive(args.include_recursive)
+        .with_max_file_lines(args.max_file_lines)
+        .with_subtokenizer(subtokenizer)
+        .with_seed(args.seed)
+        .show_progress();
+    let mut task_samples = vec![Vec::new(); tasks.len()];
+    let mut samples_left = args.limit_samples.unwrap_or(usize::MAX);
+    while samples_left > 0 {
+        match samples.next() {
+            Some(Ok((idx, sample))) => {
+                task_samples[idx].push(sample);
+                samples_left -= 1;
+            }
+            Some(Err(e)) if e.is_recoverable() => eprintln!("{}", e),
+            Some(Err(e)) => return Err(e),
+            None => break,
+        }
+    }
+    let num_completed = input_files.len() - samples.num_remaining();
+    let ExtractSummary {
+        num_files,
+        num_failed,
+        num_converted,
+        num_normalized,
+        skipped,
+    } = samples.into_summary();
+    println!();
+    let mut rng = seeded_rng(args.seed);
+    for ((name, _), samples) in tasks.iter().zip(task_samples.iter_mut()) {
+        let (balanced, balance) = balance_labels(std::mem::take(samples), negative_ratio, &mut rng);
+        *samples = match args.max_samples {
+            Some(max_samples) if balanced.len() > max_samples => {
+                println!(
+                    "{}: kept {} of {} samples",
+                    name,
+                    max_samples,
+                    balanced.len()
+                );
+                reservoir_sample(balanced, max_samples, &mut rng)
+            }
+            _ => balanced,
+        };
+        println!("{}: {} samples", name, samples.len());
+        if balance.num_positive == 0 {
+            continue;
+        }
+        println!(
+            "{}: {} positive and {} negative samples, {} of them with callees of other files",
+            name, balance.num_positive, balance.num_negative, balance.num_backfilled
+        );
+        if balance.num_missing > 0 {
+            println!(
+                "{}: {} negative samples are missing as their callers call all extracted functions",
+                name, balance.num_missing
+            );
+        }
+    }
+    if num_converted > 0 {
+        println!("Converted {} files which were not UTF-8", num_converted);
+    }
+    if num_normalized != (0, 0, 0) {
+        println!(
+            "Normalized {} byte order marks, {} CRLF line endings and {} mixed indentations",
+            num_normalized.0, num_normalized.1, num_normalized.2
+        );
+    }
+    let reasons = [
+        (SkipReason::Unreadable, "unreadable"),
+        (SkipReason::Size, "larger than --max-file-size"),
+        (SkipReason::Encoding, "not UTF-8"),
+        (SkipReason::Generated, "minified or generated"),
+        (SkipReason::ParseErrors, "above --max-error-ratio"),
+        (SkipReason::Language, "of another language"),
+    ];
+    for (reason, description) in reasons {
+        let count = skipped.count(reason);
+        if count > 0 {
+            println!("Skipped {} files {}", count, description);
+        }
+    }
+    // unreadable files are failures rather than files left out by the filters
+    let num_skipped = skipped.files.len() - skipped.count(SkipReason::Unreadable);
+    println!(
+        "Processed {} files: {} extracted, {} skipped, {} failed",
+        num_files,
+        num_files.saturating_sub(num_skipped + num_failed),
+        num_skipped,
+        num_failed
+    );
+    let output_opts = OutputOptions {
+        hf_layout: args.hf_layout,
+        hf_readme: args.hf_readme,
+        vocab: args.vocab,
+        split: args.split.to_options(),
+        ..args.output.to_options()
+    };
+    match tasks.len() {
+        1 => save_dataset(&local_out, &task_samples[0], &output_opts)?,
+        _ => {
+            for ((name, _), samples) in tasks.iter().zip(&task_samples) {
+                save_dataset(&format!("{}/{}", local_out, name), samples, &output_opts)?;
+            }
+        }
+    }
+    let skip_report = Path::new(&local_out).join("skipped.jsonl");
+    skipped.write(&skip_report)?;
+    save_config(&args, &format!("{}/config.toml", local_out), &output_opts)?;
+    metrics::write_report(&format!("{}/metrics.json", local_out), &output_opts)?;
+    if shutdown::requested() {
+        let manifest = format!("{}/interrupted.json", local_out);
+        shutdown::write_manifest(&manifest, &input_files[..num_completed], &output_opts)?;
+        println!(
+            "Interrupted after {} of {} files, see {}",
+            num_completed,
+            input_files.len(),
+            manifest
+        );
+        // a partial dataset is not uploaded where it would pass for a complete one
+        if let Some(dir) = remote_out {
+            println!(
+                "The dataset was not uploaded, it is kept in {}",
+                dir.display()
+            );
+        }
+        return shutdown::check();
+    }
+    if let Some(dir) = remote_out {
+        remote::upload(&dir, &out_dir)?;
+    }
+    check_failure_rate(num_failed, num_files, "files", max_failure_rate)
+}
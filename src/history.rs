@@ -0,0 +1,237 @@
+//! Tasks on the git history of the extracted files. The revisions of a file are the commits
+//! changing it, read with `git` from the repository of its checkout, so files outside of git
+//! checkouts such as members of archives have none.
+//!
+//! `stale_comment` compares the functions of each revision with the previous one. The comment
+//! of a function is stale after a commit changing its code without its comment, and not stale
+//! after a commit changing its comment without its code; commits changing both or neither are
+//! ambiguous and skipped.
+//!
+//! `func_history` follows each function of a file by its name through the revisions, keeping the
+//! revisions changing its code. Functions with a single version have no history and are skipped.
+
+use crate::analysis::{find_function_comments, named_functions, LanguageSpec};
+use crate::error::{Error, Result};
+use crate::extract::{Extractor, FileCtx};
+use crate::{node_text, DataSample, FuncVersion};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tree_sitter::{Parser, Tree};
+
+/// Most recent revisions of a file which are compared
+const MAX_REVISIONS: usize = 50;
+
+/// A commit changing a file
+struct Revision {
+    hash: String,
+    author: String,
+    /// author date in ISO 8601
+    date: String,
+    subject: String,
+}
+
+/// Directory and name of the file at `path`, to run `git` on it from its directory
+fn split_path(path: &str) -> Option<(&Path, &str)> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some((dir, name))
+}
+
+/// Output of `git args` run in `dir`, `None` if git fails, e.g. as `dir` is not in a checkout
+fn git(dir: &Path, args: &[&str]) -> Result<Option<Vec<u8>>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    Ok(match output.status.success() {
+        true => Some(output.stdout),
+        false => None,
+    })
+}
+
+/// The commits changing the file at `path`, oldest first
+fn revisions(path: &str) -> Result<Vec<Revision>> {
+    let (dir, name) = match split_path(path) {
+        Some(split) => split,
+        None => return Ok(Vec::new()),
+    };
+    let max_count = format!("--max-count={}", MAX_REVISIONS);
+    let log = match git(
+        dir,
+        &[
+            "log",
+            "--no-merges",
+            // fields are separated by NUL, which subjects cannot contain
+            "--format=%H%x00%an%x00%aI%x00%s",
+            &max_count,
+            "--",
+            name,
+        ],
+    )? {
+        Some(log) => log,
+        None => return Ok(Vec::new()),
+    };
+    let mut revisions: Vec<Revision> = String::from_utf8_lossy(&log)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\0').map(|field| field.to_string());
+            Some(Revision {
+                hash: fields.next()?,
+                author: fields.next()?,
+                date: fields.next()?,
+                subject: fields.next()?,
+            })
+        })
+        .collect();
+    revisions.reverse();
+    Ok(revisions)
+}
+
+/// The file at `path` as of commit `hash`, `None` if the commit does not have it
+fn file_at(path: &str, hash: &str) -> Result<Option<String>> {
+    let (dir, name) = match split_path(path) {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+    let object = format!("{}:./{}", hash, name);
+    let file = git(dir, &["show", &object])?;
+    Ok(file.map(|file| String::from_utf8_lossy(&file).into_owned()))
+}
+
+/// Parse a revision of the file at `path`
+fn parse(parser: &mut Parser, path: &str, code: &str) -> Result<Tree> {
+    parser.parse(code, None).ok_or_else(|| Error::Parse {
+        path: path.to_string(),
+    })
+}
+
+/// Whitespace of `text` collapsed into single spaces, so that changes of the layout alone are
+/// not changes of code or comments
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Code and comment of each function of a revision of a file, by name
+fn commented_functions(
+    parser: &mut Parser,
+    spec: &LanguageSpec,
+    path: &str,
+    code: &str,
+) -> Result<HashMap<String, (String, String)>> {
+    let tree = parse(parser, path, code)?;
+    let (func_code, func_comments) = find_function_comments(spec, code, tree.root_node())?;
+    let mut functions = HashMap::new();
+    for (name, comment) in func_comments {
+        if let Some(src) = func_code.get(name) {
+            functions.insert(name.to_string(), (src.to_string(), comment));
+        }
+    }
+    Ok(functions)
+}
+
+/// `stale_comment`: comments of functions before a commit, the code of the functions after it
+/// and whether the commit left the comment stale
+pub struct StaleComment;
+
+impl Extractor for StaleComment {
+    fn extract(&self, _code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let mut parser = Parser::new();
+        parser.set_language(ctx.spec.language)?;
+        let mut samples = Vec::new();
+        let mut previous: Option<HashMap<String, (String, String)>> = None;
+        for revision in revisions(ctx.path)? {
+            let functions = match file_at(ctx.path, &revision.hash)? {
+                Some(code) => commented_functions(&mut parser, &ctx.spec, ctx.path, &code)?,
+                None => HashMap::new(),
+            };
+            for (name, (old_src, old_comment)) in previous.iter().flatten() {
+                let (new_src, new_comment) = match functions.get(name) {
+                    Some(function) => function,
+                    None => continue,
+                };
+                if old_comment.trim().is_empty() {
+                    continue;
+                }
+                let (old_com, new_com) = (collapse(old_comment), collapse(new_comment));
+                // docstrings are part of the code of their functions
+                let old_body = collapse(old_src).replacen(&old_com, "", 1);
+                let new_body = collapse(new_src).replacen(&new_com, "", 1);
+                let is_stale = match (old_body != new_body, old_com != new_com) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => continue,
+                };
+                samples.push(DataSample::StaleComment(
+                    old_comment.clone(),
+                    ctx.lay_out(new_src),
+                    is_stale,
+                ));
+            }
+            previous = Some(functions);
+        }
+        Ok(samples)
+    }
+}
+
+/// `func_history`: the versions of each function of a file with the commits making them
+pub struct FuncHistory;
+
+impl Extractor for FuncHistory {
+    fn extract(&self, _code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let mut parser = Parser::new();
+        parser.set_language(ctx.spec.language)?;
+        // functions in the order they first appear
+        let mut histories: Vec<(String, Vec<FuncVersion>)> = Vec::new();
+        for revision in revisions(ctx.path)? {
+            let code = match file_at(ctx.path, &revision.hash)? {
+                Some(code) => code,
+                None => continue,
+            };
+            let tree = parse(&mut parser, ctx.path, &code)?;
+            let mut functions: HashMap<&str, Option<&str>> = HashMap::new();
+            for (name, node) in named_functions(&ctx.spec, tree.root_node(), &code) {
+                // functions sharing a name cannot be told apart across revisions
+                let src = node_text(node, &code);
+                functions
+                    .entry(name)
+                    .and_modify(|src| *src = None)
+                    .or_insert(Some(src));
+            }
+            for (name, src) in functions {
+                let src = match src {
+                    Some(src) => ctx.lay_out(src),
+                    None => continue,
+                };
+                let index = match histories.iter().position(|(func, _)| func == name) {
+                    Some(index) => index,
+                    None => {
+                        histories.push((name.to_string(), Vec::new()));
+                        histories.len() - 1
+                    }
+                };
+                let versions = &mut histories[index].1;
+                if versions.last().is_none_or(|version| version.code != src) {
+                    versions.push(FuncVersion {
+                        commit: revision.hash.clone(),
+                        author: revision.author.clone(),
+                        date: revision.date.clone(),
+                        message: revision.subject.clone(),
+                        code: src,
+                    });
+                }
+            }
+        }
+        Ok(histories
+            .into_iter()
+            .filter(|(_, versions)| versions.len() > 1)
+            .map(|(name, versions)| DataSample::FuncHistory(name, versions))
+            .collect())
+    }
+}
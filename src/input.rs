@@ -0,0 +1,727 @@
+//! Input sources of the extraction pipelines
+
+use crate::error::{self, Error};
+use crate::split::stable_hash;
+use flate2::read::GzDecoder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use walkdir::WalkDir;
+
+/// Input path which reads from stdin instead of a file
+pub const STDIN_PATH: &str = "-";
+
+/// Options of the walk over the source directories and of reading the files found
+#[derive(clap::Args, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WalkArgs {
+    /// Skip paths ignored by `.gitignore`, `.ignore` and the global git excludes, such as build
+    /// artifacts, `node_modules` and virtualenvs
+    #[clap(long)]
+    pub respect_gitignore: bool,
+    /// Follow symbolic links, links to an ancestor directory are skipped
+    #[clap(long)]
+    pub follow_symlinks: bool,
+    /// Skip files which are not valid UTF-8 instead of converting them from their detected
+    /// encoding
+    #[clap(long)]
+    pub skip_non_utf8: bool,
+    /// Skip files larger than this many bytes, which are mostly generated or bundled code, 0
+    /// disables the limit [default: 4194304]
+    #[clap(long)]
+    pub max_file_size: Option<u64>,
+    /// Also extract files which look minified or generated
+    #[clap(long)]
+    pub keep_generated: bool,
+    /// Also walk vendored dependency and build output directories such as `vendor` and
+    /// `node_modules`, which are skipped by default as they duplicate code of other repositories
+    #[clap(long)]
+    pub include_vendored: bool,
+}
+
+/// Options to process a share of the input files, so that the same command can be run as several
+/// jobs, e.g. of a SLURM array, whose outputs are concatenated afterward
+#[derive(clap::Args, Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartitionArgs {
+    /// Split the input files into this many partitions by a stable hash of their path and only
+    /// process the --partition, requires --partition
+    #[clap(long)]
+    pub num_partitions: Option<usize>,
+    /// Index of the partition to process, from 0 to --num-partitions - 1
+    #[clap(long)]
+    pub partition: Option<usize>,
+}
+
+impl PartitionArgs {
+    /// A usage error if only one of the options is given or the partition is out of range
+    pub fn check(&self) -> error::Result<()> {
+        let message = match (self.num_partitions, self.partition) {
+            (None, None) => return Ok(()),
+            (Some(0), _) => "--num-partitions must be at least 1".to_string(),
+            (Some(num), Some(idx)) if idx >= num => {
+                format!("--partition must be less than --num-partitions {}", num)
+            }
+            (Some(_), Some(_)) => return Ok(()),
+            _ => "--num-partitions and --partition must be given together".to_string(),
+        };
+        Err(Error::Usage(message))
+    }
+
+    /// Whether an input file is in the partition of the run, all are without partitions
+    pub fn contains(&self, path: &Path) -> bool {
+        match (self.num_partitions, self.partition) {
+            (Some(num), Some(idx)) => {
+                let hash = stable_hash(path.to_string_lossy().as_bytes());
+                hash % num as u64 == idx as u64
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Directories of vendored dependencies and build outputs
+const VENDORED_DIRS: [&str; 4] = ["vendor", "node_modules", "third_party", "dist"];
+
+/// Whether the walk descends into a directory entry
+fn walk_dir_entry(name: &std::ffi::OsStr, is_dir: bool, include_vendored: bool) -> bool {
+    name != ".git" && (include_vendored || !is_dir || !VENDORED_DIRS.iter().any(|dir| name == *dir))
+}
+
+/// Default of --max-file-size, 4 MiB
+pub const MAX_FILE_SIZE: u64 = 4 << 20;
+
+/// Why a source file was not extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Unreadable,
+    Size,
+    Encoding,
+    Generated,
+    ParseErrors,
+    /// detected as another language than the one extracted
+    Language,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// The source files skipped by an extraction, written to `skipped.jsonl` next to the dataset so
+/// the coverage of a corpus can be audited
+#[derive(Debug, Default)]
+pub struct SkipReport {
+    pub files: Vec<SkippedFile>,
+}
+
+impl SkipReport {
+    pub fn skip(&mut self, path: &str, reason: SkipReason, detail: Option<String>) {
+        self.files.push(SkippedFile {
+            path: path.to_string(),
+            reason,
+            detail,
+        });
+    }
+
+    pub fn count(&self, reason: SkipReason) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.reason == reason)
+            .count()
+    }
+
+    /// Write the skipped files as jsonl
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        for file in &self.files {
+            serde_json::to_writer(&mut writer, file)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+}
+
+/// Markers of generated code, searched for in the head of a file
+const GENERATED_MARKERS: [&str; 5] = [
+    "@generated",
+    "DO NOT EDIT",
+    "Code generated",
+    "autogenerated",
+    "auto-generated",
+];
+/// Lines longer than this are considered minified
+const MINIFIED_LINE_LEN: usize = 500;
+
+/// Whether a source file looks generated, i.e. has a generated code marker in its first lines,
+/// or minified, i.e. has a high average line length or most of its content on very long lines
+pub fn is_generated(src: &str) -> bool {
+    let head: String = src.lines().take(10).collect::<Vec<_>>().join("\n");
+    if GENERATED_MARKERS.iter().any(|marker| head.contains(marker)) {
+        return true;
+    }
+    let (mut num_lines, mut long_line_len) = (0, 0);
+    for line in src.lines() {
+        num_lines += 1;
+        if line.len() > MINIFIED_LINE_LEN {
+            long_line_len += line.len();
+        }
+    }
+    num_lines > 0
+        && (src.len() / num_lines > MINIFIED_LINE_LEN / 2 || long_line_len * 2 > src.len())
+}
+
+/// Columns of a tab when mixed indentation is expanded
+pub(crate) const TAB_WIDTH: usize = 4;
+
+/// Normalizations applied to a source file before it is parsed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Normalization {
+    pub bom: bool,
+    pub crlf: bool,
+    pub mixed_indent: bool,
+}
+
+/// Expand the tabs of the indentation of a line to spaces
+pub(crate) fn expand_indent(line: &str) -> String {
+    let indent_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let mut expanded = String::new();
+    for c in line[..indent_len].chars() {
+        match c {
+            '\t' => {
+                let width = TAB_WIDTH - expanded.len() % TAB_WIDTH;
+                expanded.extend(std::iter::repeat_n(' ', width));
+            }
+            c => expanded.push(c),
+        }
+    }
+    expanded + &line[indent_len..]
+}
+
+/// Strip a byte order mark, convert CRLF and CR line endings to LF and expand the indentation of
+/// files indented with both tabs and spaces, so that the byte offsets of the emitted spans refer
+/// to the same text the parser saw
+pub fn normalize_source(src: String) -> (String, Normalization) {
+    let mut normalization = Normalization::default();
+    let mut src = match src.strip_prefix('\u{feff}') {
+        Some(stripped) => {
+            normalization.bom = true;
+            stripped.to_string()
+        }
+        None => src,
+    };
+    if src.contains('\r') {
+        normalization.crlf = true;
+        src = src.replace("\r\n", "\n").replace('\r', "\n");
+    }
+    let tab_indented = src.lines().any(|line| line.starts_with('\t'));
+    let space_indented = src.lines().any(|line| line.starts_with(' '));
+    if tab_indented && space_indented {
+        normalization.mixed_indent = true;
+        src = src
+            .split('\n')
+            .map(expand_indent)
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    (src, normalization)
+}
+
+/// Decode the content of a source file, which is converted lossily from its detected encoding if
+/// it is not valid UTF-8. Returns the name of the encoding if the content was converted.
+pub fn decode_source(bytes: Vec<u8>) -> (String, Option<&'static str>) {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(src) => return (src, None),
+        Err(e) => e.into_bytes(),
+    };
+    let encoding = match encoding_rs::Encoding::for_bom(&bytes) {
+        Some((encoding, _)) => encoding,
+        None => {
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(&bytes, true);
+            detector.guess(None, true)
+        }
+    };
+    let (src, _, _) = encoding.decode(&bytes);
+    (src.into_owned(), Some(encoding.name()))
+}
+
+/// Skip entries of the walk which cannot be read, such as directories without permission or
+/// symlink loops
+fn skip_unreadable<T, E: std::fmt::Display>(entry: Result<T, E>) -> Option<T> {
+    entry.map_err(|e| eprintln!("Skipping {}", e)).ok()
+}
+
+/// The files below a source root, skipping `.git` and vendored directories and, unless they are
+/// followed, symbolic links. The root itself is always walked, even if it is such a directory.
+/// URLs are not walked but returned as a single file or archive.
+pub fn walk_source_files(root: &Path, opts: &WalkArgs) -> Vec<PathBuf> {
+    if is_url(&root.to_string_lossy()) {
+        return vec![root.to_path_buf()];
+    }
+    let include_vendored = opts.include_vendored;
+    let files: Vec<PathBuf> = if opts.respect_gitignore {
+        ignore::WalkBuilder::new(root)
+            .hidden(false)
+            // sources of archives and clones without their .git are ignored alike
+            .require_git(false)
+            .follow_links(opts.follow_symlinks)
+            .filter_entry(move |e| {
+                let is_dir = e.file_type().is_some_and(|t| t.is_dir());
+                e.depth() == 0 || walk_dir_entry(e.file_name(), is_dir, include_vendored)
+            })
+            .build()
+            .filter_map(skip_unreadable)
+            .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+            .map(|e| e.into_path())
+            .collect()
+    } else {
+        WalkDir::new(root)
+            .follow_links(opts.follow_symlinks)
+            .into_iter()
+            .filter_entry(|e| {
+                e.depth() == 0
+                    || walk_dir_entry(e.file_name(), e.file_type().is_dir(), include_vendored)
+            })
+            .filter_map(skip_unreadable)
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    };
+    if !opts.follow_symlinks {
+        return files;
+    }
+    // files reachable through several links are read once
+    let mut seen = HashSet::new();
+    files
+        .into_iter()
+        .filter(|path| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())))
+        .collect()
+}
+
+/// Whether an input path is a glob pattern such as `corpus/**/train-*.jsonl`
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// The paths matching a glob pattern in lexicographic order, an error if there are none
+pub fn expand_glob(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let paths = glob::glob(pattern)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::from)?;
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no files match {}", pattern),
+        ));
+    }
+    Ok(paths)
+}
+
+/// Attempts of an HTTP request before an input URL or a contract is given up
+const HTTP_RETRIES: u32 = 5;
+
+/// Whether an input path is an `http://` or `https://` URL
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+fn http_error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+/// GET `url` from byte `offset` on, retrying failed requests with exponential backoff
+fn http_get(url: &str, offset: u64) -> io::Result<Box<dyn Read + Send + Sync>> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_read(Duration::from_secs(60))
+        .build();
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let mut request = agent.get(url);
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={}-", offset));
+        }
+        let error = match request.call() {
+            Ok(response) if offset > 0 && response.status() != 206 => {
+                return Err(http_error(format!("{} cannot resume downloads", url)))
+            }
+            Ok(response) => return Ok(response.into_reader()),
+            // client errors other than rate limiting are not transient
+            Err(ureq::Error::Status(status, _))
+                if (400..500).contains(&status) && status != 429 =>
+            {
+                return Err(http_error(format!("{} returned {}", url, status)))
+            }
+            Err(e) => e.to_string(),
+        };
+        if attempt == HTTP_RETRIES {
+            return Err(http_error(error));
+        }
+        eprintln!("Retrying {} in {}s: {}", url, delay.as_secs(), error);
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// A streamed HTTP(S) download which resumes interrupted transfers with range requests
+struct HttpReader {
+    url: String,
+    offset: u64,
+    reader: Box<dyn Read + Send + Sync>,
+}
+
+impl HttpReader {
+    fn open(url: &str) -> io::Result<Self> {
+        Ok(HttpReader {
+            url: url.to_string(),
+            offset: 0,
+            reader: http_get(url, 0)?,
+        })
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut retries = 0;
+        loop {
+            match self.reader.read(buf) {
+                Ok(len) => {
+                    self.offset += len as u64;
+                    return Ok(len);
+                }
+                Err(e) if retries < HTTP_RETRIES => {
+                    eprintln!("Resuming {} at byte {}: {}", self.url, self.offset, e);
+                    self.reader = http_get(&self.url, self.offset)?;
+                    retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Archive formats the walkers descend into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    /// a single gzip compressed file, e.g. `corpus.jsonl.gz`
+    Gz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".gz") {
+        Some(ArchiveKind::Gz)
+    } else {
+        None
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+fn visit_tar<R, F>(name: &str, reader: R, visit: &mut F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(&str, &mut dyn Read) -> io::Result<()>,
+{
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() {
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let member = format!("{}/{}", name, path.trim_start_matches("./"));
+            visit(&member, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn visit_zip<R, F>(name: &str, reader: R, visit: &mut F) -> io::Result<()>
+where
+    R: Read + Seek,
+    F: FnMut(&str, &mut dyn Read) -> io::Result<()>,
+{
+    let to_io_error = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut archive = zip::ZipArchive::new(reader).map_err(to_io_error)?;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(to_io_error)?;
+        if entry.is_file() {
+            let member = format!("{}/{}", name, entry.name());
+            visit(&member, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Visit a file or the members of an archive which can be read sequentially
+fn visit_stream<R, F>(
+    name: &str,
+    kind: Option<ArchiveKind>,
+    reader: R,
+    visit: &mut F,
+) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(&str, &mut dyn Read) -> io::Result<()>,
+{
+    match kind {
+        None => visit(name, &mut BufReader::new(reader)),
+        Some(ArchiveKind::Gz) => visit(
+            name.trim_end_matches(".gz"),
+            &mut GzDecoder::new(BufReader::new(reader)),
+        ),
+        Some(ArchiveKind::Tar) => visit_tar(name, BufReader::new(reader), visit),
+        Some(ArchiveKind::TarGz) => visit_tar(name, GzDecoder::new(BufReader::new(reader)), visit),
+        Some(ArchiveKind::Zip) => unreachable!("zip archives need to be seekable"),
+    }
+}
+
+/// Call `visit` with the name and a reader of a file, or of every file in it if it is a `.zip`,
+/// `.tar`, `.tar.gz`/`.tgz` or `.gz` archive. Archive members are read in a streaming fashion
+/// without extracting them and are named `archive/member`. `http://` and `https://` URLs are
+/// streamed as well, except for zip archives which are downloaded into memory first.
+pub fn visit_input_file<F>(path: &Path, mut visit: F) -> io::Result<()>
+where
+    F: FnMut(&str, &mut dyn Read) -> io::Result<()>,
+{
+    let name = path.to_string_lossy();
+    let kind = archive_kind(path);
+    if is_url(&name) {
+        let mut reader = HttpReader::open(&name)?;
+        if kind == Some(ArchiveKind::Zip) {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            return visit_zip(&name, Cursor::new(bytes), &mut visit);
+        }
+        return visit_stream(&name, kind, reader, &mut visit);
+    }
+    let file = File::open(path)?;
+    match kind {
+        Some(ArchiveKind::Zip) => visit_zip(&name, BufReader::new(file), &mut visit),
+        kind => visit_stream(&name, kind, file, &mut visit),
+    }
+}
+
+/// Checkout directory of a git URL below `cache_dir`, e.g.
+/// `https://github.com/owner/name.git` is cloned into `cache_dir/github.com/owner/name`
+pub fn repo_checkout_dir(cache_dir: &str, url: &str) -> PathBuf {
+    let path = url
+        .trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_start_matches("ssh://")
+        .trim_start_matches("file://")
+        .trim_start_matches("git@")
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .replace(':', "/");
+    let mut dir = PathBuf::from(cache_dir);
+    dir.extend(
+        path.split('/')
+            .filter(|part| !part.is_empty() && *part != "." && *part != ".."),
+    );
+    dir
+}
+
+/// Shallow-clone a repository unless its checkout already exists in the cache
+fn clone_repo(url: &str, dir: &Path) -> Result<(), String> {
+    if dir.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir.parent().unwrap()).map_err(|e| e.to_string())?;
+    // clone next to the final directory so interrupted clones are not mistaken for checkouts
+    let tmp = dir.with_extension("tmp");
+    let _ = fs::remove_dir_all(&tmp);
+    let status = Command::new("git")
+        .args(["clone", "--depth", "1", "--quiet", url])
+        .arg(&tmp)
+        // fail instead of waiting for credentials of private or missing repositories
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .status()
+        .map_err(|e| format!("cannot run git: {}", e))?;
+    if !status.success() {
+        let _ = fs::remove_dir_all(&tmp);
+        return Err(format!("git clone exited with {}", status));
+    }
+    fs::rename(&tmp, dir).map_err(|e| e.to_string())
+}
+
+/// Shallow-clone the git URLs listed in `repo_list` (one per line, `#` starts a comment) in
+/// parallel, returning the checkouts which are available
+pub fn clone_repos(repo_list: &str, cache_dir: &str) -> std::io::Result<Vec<PathBuf>> {
+    let urls: Vec<String> = fs::read_to_string(repo_list)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    Ok(urls
+        .par_iter()
+        .filter_map(|url| {
+            let dir = repo_checkout_dir(cache_dir, url);
+            match clone_repo(url, &dir) {
+                Ok(()) => Some(dir),
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", url, e);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+const ETHERSCAN_API: &str = "https://api.etherscan.io/v2/api";
+
+#[derive(Debug, Deserialize)]
+struct EtherscanResponse {
+    status: String,
+    result: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EtherscanSource {
+    source_code: String,
+    contract_name: String,
+}
+
+/// Relative path of a source file which cannot escape the directory it is written into
+fn sanitize_source_path(path: &str) -> PathBuf {
+    path.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+        .collect()
+}
+
+/// Source files of an Etherscan `SourceCode` field, which is either a single flattened file,
+/// a json object of files or a solc standard json input wrapped in an extra pair of braces
+fn etherscan_source_files(source: &EtherscanSource) -> Result<Vec<(PathBuf, String)>, String> {
+    let code = source.source_code.trim();
+    if !code.starts_with('{') {
+        let name = format!("{}.sol", source.contract_name);
+        return Ok(vec![(sanitize_source_path(&name), code.to_string())]);
+    }
+    let json = match code.starts_with("{{") {
+        true => &code[1..code.len() - 1],
+        false => code,
+    };
+    let payload: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let files = payload
+        .get("sources")
+        .unwrap_or(&payload)
+        .as_object()
+        .ok_or("unexpected source payload")?;
+    Ok(files
+        .iter()
+        .filter_map(|(path, file)| {
+            let content = file.get("content")?.as_str()?;
+            Some((sanitize_source_path(path), content.to_string()))
+        })
+        .collect())
+}
+
+/// Download the verified source of a contract, retrying with exponential backoff while rate
+/// limited
+fn fetch_contract(
+    address: &str,
+    chain_id: u64,
+    api_key: &str,
+) -> Result<Vec<(PathBuf, String)>, String> {
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let response: EtherscanResponse = ureq::get(ETHERSCAN_API)
+            .query("chainid", &chain_id.to_string())
+            .query("module", "contract")
+            .query("action", "getsourcecode")
+            .query("address", address)
+            .query("apikey", api_key)
+            .call()
+            .map_err(|e| e.to_string())?
+            .into_json()
+            .map_err(|e| e.to_string())?;
+        if response.status != "1" {
+            let message = response.result.as_str().unwrap_or_default().to_string();
+            if message.contains("rate limit") && attempt < HTTP_RETRIES {
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+                continue;
+            }
+            return Err(message);
+        }
+        let sources: Vec<EtherscanSource> =
+            serde_json::from_value(response.result).map_err(|e| e.to_string())?;
+        let mut files = Vec::new();
+        for source in sources.iter().filter(|s| !s.source_code.is_empty()) {
+            files.extend(etherscan_source_files(source)?);
+        }
+        if files.is_empty() {
+            return Err("source code is not verified".to_string());
+        }
+        return Ok(files);
+    }
+}
+
+/// Download the verified sources of the contract addresses listed in `address_list` (one per
+/// line, `#` starts a comment) from Etherscan into `cache_dir/<address>/`, returning the
+/// directories which are available. Contracts in the cache are not downloaded again.
+pub fn fetch_verified_sources(
+    address_list: &str,
+    cache_dir: &str,
+    chain_id: u64,
+    api_key: &str,
+) -> std::io::Result<Vec<PathBuf>> {
+    let addresses: Vec<String> = fs::read_to_string(address_list)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim().to_lowercase())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let mut dirs = Vec::new();
+    for (idx, address) in addresses.iter().enumerate() {
+        eprint!("\x1b[K\r{}/{} {}", idx + 1, addresses.len(), address);
+        let dir = Path::new(cache_dir).join(sanitize_source_path(address));
+        if !dir.exists() {
+            match fetch_contract(address, chain_id, api_key) {
+                Ok(files) => {
+                    let tmp = dir.with_extension("tmp");
+                    let _ = fs::remove_dir_all(&tmp);
+                    for (path, content) in files {
+                        let path = tmp.join(path);
+                        fs::create_dir_all(path.parent().unwrap())?;
+                        fs::write(path, content)?;
+                    }
+                    fs::rename(&tmp, &dir)?;
+                }
+                Err(e) => {
+                    eprintln!("\nSkipping {}: {}", address, e);
+                    continue;
+                }
+            }
+        }
+        dirs.push(dir);
+    }
+    eprintln!();
+    Ok(dirs)
+}
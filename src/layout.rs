@@ -0,0 +1,191 @@
+//! Layout of the code of samples, `--code-layout`. Code is written as it is in the source, with
+//! its whitespace collapsed to single spaces on one line, or re-indented: the indentation the
+//! function had in its file is removed and the indentation of its lines is made a multiple of
+//! four spaces, which keeps the code parseable where indentation is significant.
+
+use crate::input::{expand_indent, TAB_WIDTH};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Indentation of a level of re-indented code
+const INDENT_WIDTH: usize = 4;
+
+/// How the code of samples is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CodeLayout {
+    /// as in the source
+    Raw,
+    /// runs of whitespace replaced by a single space, on one line
+    Collapsed,
+    /// dedented and indented by levels of four spaces, trailing whitespace removed
+    NormalizedIndent,
+}
+
+impl FromStr for CodeLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(CodeLayout::Raw),
+            "collapsed" => Ok(CodeLayout::Collapsed),
+            "normalized-indent" => Ok(CodeLayout::NormalizedIndent),
+            _ => Err(format!("Unknown code layout: {}", s)),
+        }
+    }
+}
+
+impl CodeLayout {
+    pub fn apply(self, code: &str) -> String {
+        match self {
+            CodeLayout::Raw => code.to_string(),
+            CodeLayout::Collapsed => code.split_whitespace().collect::<Vec<&str>>().join(" "),
+            CodeLayout::NormalizedIndent => normalize_indent(code),
+        }
+    }
+}
+
+fn indent_width(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        b => gcd(b, a % b),
+    }
+}
+
+/// Whether `line` only closes blocks, such as `}`, `});` or `end`
+fn is_closer(line: &str) -> bool {
+    let line = line.trim();
+    line == "end"
+        || (line.starts_with(['}', ')', ']']) && line.chars().all(|c| "})];,".contains(c)))
+}
+
+/// Column the first line of `lines` started at in its file. The code of a function starts at the
+/// function, so only the lines after the first keep the indentation of the file. A last line
+/// which only closes blocks, such as `}` or `end`, and is shallower than the lines before it is
+/// at the column of the function, otherwise the shallowest line is taken to be a level deeper
+/// than it.
+fn first_line_column(lines: &[String]) -> usize {
+    let rest: Vec<&String> = lines[1..]
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let (last, body) = match rest.split_last() {
+        Some(split) => split,
+        None => return 0,
+    };
+    let body_min = body.iter().map(|line| indent_width(line)).min();
+    if is_closer(last) && body_min.is_none_or(|min| indent_width(last) < min) {
+        return indent_width(last);
+    }
+    let min = rest
+        .iter()
+        .map(|line| indent_width(line))
+        .min()
+        .unwrap_or(0);
+    let level = rest
+        .iter()
+        .map(|line| indent_width(line) - min)
+        .fold(0, gcd);
+    let level = match level {
+        0 => INDENT_WIDTH.min(min),
+        level => level.min(min),
+    };
+    min - level
+}
+
+/// `code` dedented by the column it started at, with the indentation of its lines rescaled to
+/// levels of `INDENT_WIDTH` spaces
+fn normalize_indent(code: &str) -> String {
+    let mut lines: Vec<String> = code
+        .lines()
+        .map(|line| expand_indent(line).trim_end().to_string())
+        .collect();
+    if lines.is_empty() {
+        return String::new();
+    }
+    lines[0] = lines[0].trim_start().to_string();
+    let column = first_line_column(&lines);
+    for line in lines.iter_mut().skip(1) {
+        let dedent = indent_width(line).min(column);
+        line.drain(..dedent);
+    }
+    // the indentation unit of the code, levels of other widths are rescaled
+    let unit = lines.iter().map(|line| indent_width(line)).fold(0, gcd);
+    if unit > 1 && unit != INDENT_WIDTH && unit <= TAB_WIDTH * 2 {
+        for line in lines.iter_mut() {
+            let width = indent_width(line);
+            let indent = " ".repeat(width / unit * INDENT_WIDTH);
+            line.replace_range(..width, &indent);
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_dedented_to_its_closing_brace() {
+        let code = "function f() {\n        return 1;\n    }";
+        assert_eq!(normalize_indent(code), "function f() {\n    return 1;\n}");
+    }
+
+    #[test]
+    fn ruby_method_dedented_to_its_end() {
+        let code = "def f\n    x\n  end";
+        assert_eq!(normalize_indent(code), "def f\n    x\nend");
+    }
+
+    #[test]
+    fn statement_starting_with_end_is_not_a_closer() {
+        let code = "def f():\n        start = now()\n        end_time = now()";
+        assert_eq!(
+            normalize_indent(code),
+            "def f():\n    start = now()\n    end_time = now()"
+        );
+    }
+
+    #[test]
+    fn closing_parenthesis_in_the_body_is_not_a_closer() {
+        let code = "def f():\n        return g(\n            a,\n        )";
+        assert_eq!(
+            normalize_indent(code),
+            "def f():\n    return g(\n        a,\n    )"
+        );
+    }
+
+    #[test]
+    fn indentation_rescaled_to_four_spaces() {
+        let code = "function f() {\n  if (x) {\n    y();\n  }\n}";
+        assert_eq!(
+            normalize_indent(code),
+            "function f() {\n    if (x) {\n        y();\n    }\n}"
+        );
+    }
+
+    #[test]
+    fn tabs_and_trailing_whitespace() {
+        let code = "def f():  \n\tif x:\n\t\treturn 1\n";
+        assert_eq!(
+            normalize_indent(code),
+            "def f():\n    if x:\n        return 1"
+        );
+    }
+
+    #[test]
+    fn single_line() {
+        assert_eq!(normalize_indent("  f = lambda: 1"), "f = lambda: 1");
+        assert_eq!(normalize_indent(""), "");
+    }
+
+    #[test]
+    fn collapsed() {
+        let code = "def f():\n    return 1";
+        assert_eq!(CodeLayout::Collapsed.apply(code), "def f(): return 1");
+    }
+}
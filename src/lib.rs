@@ -1,65 +1,396 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     fs::{self, File},
     io::Write,
+    num::NonZeroUsize,
+    ops::Range,
+    path::Path,
 };
-use tree_sitter::{Node, Query, QueryCapture};
-
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
-pub struct JsonSample {
-    pub func_name: String,
-    // pub path: String,
-    pub repo: String,
-    pub original_string: String,
-    pub code: String,
-    pub code_tokens: Vec<String>,
-    pub docstring: String,
-    pub docstring_tokens: Vec<String>,
-}
-
-/// A caller-callee pair data sample
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
-pub struct CallJsonSample {
-    pub caller_code: String,
-    pub caller_comm: String,
-    pub callee_code: String,
-    pub callee_comm: String,
-    pub label: bool,
-    pub caller_code_tokens: Vec<String>,
-    pub caller_comm_tokens: Vec<String>,
-    pub callee_code_tokens: Vec<String>,
-    pub callee_comm_tokens: Vec<String>,
-}
-
-#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
-pub enum DataSample {
-    FuncCall(String, String),
-    FuncCallComm(String, String, String, String, bool),
-    /// function src and function comment
-    FuncComm(String, String),
-}
-
-pub const FUNC_CALL_ID_MASK: &str = "<masked_func_id>";
-
-pub fn write_to_json(samples: &Vec<DataSample>, file_path: &str) {
-    println!("Writing to {}", file_path);
-    let mut file = File::create(file_path).unwrap();
-    for sample in samples {
-        // writer.write_fmt();
-        let json_string = match sample {
-            DataSample::FuncComm(src, com) => serde_json::to_string(&(src, com)).unwrap(),
-            DataSample::FuncCallComm(caller_src, caller_com, callee_src, callee_com, label) => {
-                serde_json::to_string(&(caller_src, caller_com, callee_src, callee_com, label))
-                    .unwrap()
+use tree_sitter::{Node, Query, QueryCapture, Tree};
+
+pub mod analysis;
+pub mod anonymize;
+pub mod ast;
+pub mod benchmark;
+pub mod complexity;
+pub mod crawl;
+pub mod dataset;
+pub mod detect;
+pub mod error;
+pub mod extract;
+pub mod history;
+pub mod input;
+pub mod layout;
+pub mod metrics;
+pub mod pair_calls;
+pub mod parallel;
+pub mod predicate;
+pub mod preview;
+pub mod readme;
+pub mod remote;
+pub mod samples;
+pub mod selftest;
+pub mod serve;
+pub mod shuffle;
+pub mod shutdown;
+pub mod split;
+pub mod subtoken;
+pub mod task;
+pub mod vocab;
+pub mod writer;
+
+/// The types most embedding crates need, `use sparser::prelude::*`
+pub mod prelude {
+    pub use crate::analysis::LanguageSpec;
+    pub use crate::error::Error;
+    pub use crate::extract::{ExtractSummary, Extractor, ExtractorRegistry, FileCtx, Samples};
+    pub use crate::samples::{CallJsonSample, DataSample, JsonSample, FUNC_CALL_ID_MASK};
+    pub use crate::writer::{DatasetWriter, OutputFormat, Record};
+    pub use crate::OutputOptions;
+}
+
+pub use error::Error;
+pub use extract::{ExtractSummary, Extractor, ExtractorRegistry, FileCtx, Samples};
+use samples::{
+    lengths, FuncCallRecord, FILE_COMM_COLUMNS, FUNC_CALL_COMM_COLUMNS, FUNC_COMM_COLUMNS,
+    FUNC_HISTORY_COLUMNS, README_API_COLUMNS, STALE_COMMENT_COLUMNS,
+};
+pub use samples::{
+    CallJsonSample, ChainCallSample, DataSample, FuncVersion, JsonSample, ParallelSample,
+    RankedCallSample, SchemaMap, TripletCallSample, FUNC_CALL_ID_MASK,
+};
+use vocab::{vocab_path, Vocab};
+pub use writer::{
+    dataset_writer, DatasetWriter, OutputFileWriter, OutputFormat, OutputSink, Record, STDOUT_PATH,
+};
+
+/// Options controlling how output files are written
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// fsync output files before they are moved into place
+    pub fsync: bool,
+    /// overwrite existing output files
+    pub force: bool,
+    /// maximum number of records per output file, `None` writes a single file
+    pub shard_size: Option<usize>,
+    pub format: OutputFormat,
+    /// write splits into `train/`, `validation/` and `test/` with HuggingFace `datasets` metadata
+    pub hf_layout: bool,
+    /// write a dataset card stub below the metadata of the README of the HuggingFace layout
+    pub hf_readme: bool,
+    /// write the token and sub-token frequencies of each split next to it
+    pub vocab: bool,
+    /// only write these fields of each record, in this order
+    pub fields: Option<Vec<String>>,
+    /// how samples are assigned to the train/val/test splits
+    pub split: split::SplitOptions,
+}
+
+/// Output options shared by the command line interfaces and config files
+#[derive(clap::Args, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputArgs {
+    /// fsync output files before closing them
+    #[clap(long)]
+    pub fsync: bool,
+    /// Overwrite existing output files
+    #[clap(long)]
+    pub force: bool,
+    /// Split each output into shards of at most N records
+    #[clap(long)]
+    pub shard_size: Option<NonZeroUsize>,
+    /// Output format: jsonl, jsonl.gz, csv, tsv, arrow, parquet, msgpack, tfrecord or sqlite (all
+    /// but the first four require the cargo feature of the same name) [default: jsonl]
+    #[clap(long)]
+    pub format: Option<OutputFormat>,
+    /// Comma separated list of fields to keep in the output, e.g. caller_code,callee_code,label
+    #[clap(long, use_value_delimiter = true)]
+    pub fields: Option<Vec<String>>,
+}
+
+impl OutputArgs {
+    pub fn to_options(&self) -> OutputOptions {
+        OutputOptions {
+            fsync: self.fsync,
+            force: self.force,
+            shard_size: self.shard_size.map(NonZeroUsize::get),
+            format: self.format.unwrap_or_default(),
+            fields: self.fields.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Read options from a toml or yaml file, depending on its extension
+pub fn load_config<T: DeserializeOwned>(path: &str) -> Result<T, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("cannot read config {}: {}", path, e))?;
+    if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(&content).map_err(|e| format!("invalid config {}: {}", path, e))
+    } else {
+        toml::from_str(&content).map_err(|e| format!("invalid config {}: {}", path, e))
+    }
+}
+
+/// Overlay the options given on the command line onto those read from a config file.
+/// Options which are unset (`None`), empty lists or disabled flags on the command line keep the
+/// file value. A `ConfigValue` error names the first option whose value does not fit.
+pub fn merge_config<T: Serialize + DeserializeOwned>(cli: &T, file: &T) -> error::Result<T> {
+    let mut merged = serde_json::to_value(file)?;
+    if let (serde_json::Value::Object(merged), serde_json::Value::Object(cli)) =
+        (&mut merged, serde_json::to_value(cli)?)
+    {
+        for (key, value) in cli {
+            match value {
+                serde_json::Value::Null | serde_json::Value::Bool(false) => {}
+                serde_json::Value::Array(values) if values.is_empty() => {}
+                value => {
+                    merged.insert(key, value);
+                }
             }
-            _ => todo!(),
-        } + "\n";
-        file.write(json_string.as_bytes()).unwrap();
+        }
+    }
+    serde_json::from_value(merged.clone()).map_err(|e| Error::ConfigValue {
+        key: invalid_option::<T>(&merged).unwrap_or_default(),
+        message: e.to_string(),
+    })
+}
+
+/// The first of the `options` which does not deserialize on its own, the options of a
+/// subcommand all have defaults
+fn invalid_option<T: DeserializeOwned>(options: &serde_json::Value) -> Option<String> {
+    let options = options.as_object()?;
+    options.iter().find_map(|(key, value)| {
+        let mut single = serde_json::Map::new();
+        single.insert(key.clone(), value.clone());
+        serde_json::from_value::<T>(serde_json::Value::Object(single))
+            .err()
+            .map(|_| key.clone())
+    })
+}
+
+/// Options of a subcommand merged with the config file given by `--config`, if any
+pub fn with_config<T: Serialize + DeserializeOwned>(
+    cli: T,
+    config: &Option<String>,
+) -> error::Result<T> {
+    match config {
+        Some(path) => {
+            let file = load_config(path).map_err(Error::Config)?;
+            merge_config(&cli, &file)
+        }
+        None => Ok(cli),
+    }
+}
+
+/// Value of a required option which may come from either the command line or a config file
+pub fn required_arg<T>(value: Option<T>, flag: &str) -> T {
+    value.unwrap_or_else(|| {
+        clap::Error::raw(
+            clap::ErrorKind::MissingRequiredArgument,
+            format!("{} is required\n", flag),
+        )
+        .exit()
+    })
+}
+
+/// Write the effective configuration of a run as toml
+pub fn save_config<T: Serialize>(
+    config: &T,
+    path: &str,
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let content = toml::to_string(config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    write_metadata_file(path, content.as_bytes(), opts)
+}
+
+/// Write records to `file_path` in the output format, or to stdout if the path is `-`
+pub fn write_records<I: IntoIterator<Item = std::io::Result<Record>>>(
+    records: I,
+    file_path: &str,
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    if file_path != STDOUT_PATH {
+        println!("Writing to {}", file_path);
+    }
+    let mut writer = OutputFileWriter::create(file_path, opts)?;
+    for record in records {
+        writer.append_record(record?)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_samples<T: Serialize>(
+    samples: &[T],
+    file_path: &str,
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let records = samples
+        .iter()
+        .map(|s| Record::new(s, None).map_err(std::io::Error::from));
+    write_records(records, file_path, opts)
+}
+
+/// Write `DataSample`s, whose tuple variants are named by the `*_COLUMNS` constants
+pub fn write_data_samples(
+    samples: &[DataSample],
+    file_path: &str,
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let records = samples.iter().map(|sample| Ok(data_sample_record(sample)));
+    write_records(records, file_path, opts)
+}
+
+/// Path of the temporary file an output is written to before being renamed into place
+pub fn tmp_path(file_path: &str) -> String {
+    format!("{}.tmp", file_path)
+}
+
+/// Path of the `idx`-th shard of `file_path`, e.g. `train-00000-of-00004.jsonl`
+pub fn shard_path(file_path: &str, idx: usize, num_shards: usize) -> String {
+    let (stem, ext) = split_extension(file_path);
+    format!("{}-{:05}-of-{:05}{}", stem, idx, num_shards, ext)
+}
+
+fn split_extension(file_path: &str) -> (&str, &str) {
+    let name_start = file_path.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match file_path[name_start..].find('.') {
+        Some(dot) => file_path.split_at(name_start + dot),
+        None => (file_path, ""),
+    }
+}
+
+/// Existing files that would be replaced by writing `file_path` with the given options
+pub fn existing_outputs(file_path: &str, opts: &OutputOptions) -> Vec<String> {
+    if opts.shard_size.is_none() {
+        return match Path::new(file_path).exists() {
+            true => vec![file_path.to_string()],
+            false => vec![],
+        };
+    }
+    let (stem, ext) = split_extension(file_path);
+    let dir = Path::new(stem).parent().filter(|p| !p.as_os_str().is_empty());
+    let prefix = format!("{}-", Path::new(stem).file_name().unwrap().to_str().unwrap());
+    let entries = match fs::read_dir(dir.unwrap_or(Path::new("."))) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    // only names `shard_path` gives, `{stem}-NNNNN-of-NNNNN{ext}`
+    let is_shard = |name: &str| {
+        let numbers = name.strip_prefix(&prefix).and_then(|n| n.strip_suffix(ext));
+        match numbers.and_then(|numbers| numbers.split_once("-of-")) {
+            Some((idx, num)) => [idx, num]
+                .iter()
+                .all(|n| n.len() >= 5 && n.bytes().all(|b| b.is_ascii_digit())),
+            None => false,
+        }
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| is_shard(name))
+        .map(|name| match dir {
+            Some(dir) => dir.join(name).to_string_lossy().to_string(),
+            None => name,
+        })
+        .collect()
+}
+
+/// Fail if any of the output files already exists and overwriting is not allowed
+pub fn check_overwrite(paths: &[String], opts: &OutputOptions) -> std::io::Result<()> {
+    if opts.force {
+        return Ok(());
+    }
+    for path in paths {
+        if let Some(existing) = existing_outputs(path, opts).first() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists, use --force to overwrite", existing),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Split a serialized record into its field names and values
+pub(crate) fn split_record(value: serde_json::Value) -> (Vec<String>, Vec<serde_json::Value>) {
+    match value {
+        serde_json::Value::Object(map) => map.into_iter().unzip(),
+        serde_json::Value::Array(values) => values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (format!("field{}", i), v))
+            .unzip(),
+        other => (vec!["value".to_string()], vec![other]),
+    }
+}
+
+/// An error naming the fields of `fields` which records with `columns` do not have, if any
+pub(crate) fn check_fields(
+    columns: &[String],
+    fields: &Option<Vec<String>>,
+) -> std::result::Result<(), String> {
+    let unknown: Vec<&str> = fields
+        .iter()
+        .flatten()
+        .filter(|field| !columns.contains(field))
+        .map(String::as_str)
+        .collect();
+    match unknown.is_empty() {
+        true => Ok(()),
+        false => Err(format!(
+            "unknown --fields {}, the records have the fields {}",
+            unknown.join(", "),
+            columns.join(", ")
+        )),
+    }
+}
+
+/// Keep only the requested fields of a record, in the requested order
+pub(crate) fn project_fields(
+    columns: Vec<String>,
+    values: Vec<serde_json::Value>,
+    fields: &Option<Vec<String>>,
+) -> std::io::Result<(Vec<String>, Vec<serde_json::Value>)> {
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok((columns, values)),
+    };
+    let mut projected = Vec::with_capacity(fields.len());
+    for field in fields {
+        match columns.iter().position(|c| c == field) {
+            Some(idx) => projected.push(values[idx].clone()),
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unknown output field: {}", field),
+                ))
+            }
+        }
+    }
+    Ok((fields.clone(), projected))
+}
+
+/// Names and output paths of the dataset splits under `path_prefix`
+pub fn split_paths(path_prefix: &str, opts: &OutputOptions) -> Vec<(&'static str, String)> {
+    let ext = opts.format.extension();
+    if opts.hf_layout {
+        ["train", "validation", "test"]
+            .iter()
+            .map(|split| (*split, format!("{}/{}/data.{}", path_prefix, split, ext)))
+            .collect()
+    } else {
+        ["all", "train", "val", "test"]
+            .iter()
+            .map(|split| (*split, format!("{}/{}.{}", path_prefix, split, ext)))
+            .collect()
     }
 }
 
 pub fn split_array<T: Clone>(
-    arr: &Vec<T>,
+    arr: &[T],
     proportion0: usize,
     proportion1: usize,
 ) -> (Vec<T>, Vec<T>) {
@@ -67,49 +398,319 @@ pub fn split_array<T: Clone>(
     let size0 = (proportion0 as f64 / sum as f64 * arr.len() as f64).ceil() as usize;
     let arr0 = arr[0..size0].to_vec();
     let arr1 = arr[size0..].to_vec();
-    return (arr0, arr1);
+    (arr0, arr1)
 }
 
-pub fn save_dataset(path_prefix: &str, samples: &Vec<DataSample>) {
-    fs::create_dir_all(path_prefix).unwrap();
-    write_to_json(samples, &format!("{}/all.jsonl", path_prefix));
-    // split into train:val:test = 8:1:1
-    let (train_samples, other_samples) = split_array(samples, 8, 2);
-    let (val_samples, test_samples) = split_array(&other_samples, 1, 1);
-    write_to_json(&train_samples, &format!("{}/train.jsonl", path_prefix));
-    write_to_json(&val_samples, &format!("{}/val.jsonl", path_prefix));
-    write_to_json(&test_samples, &format!("{}/test.jsonl", path_prefix));
-}
+/// Number of samples written of each split
+type SplitCounts = Vec<(&'static str, usize)>;
 
-pub fn append_jsonl_to_file<T: Serialize>(
+/// Split samples and write each split with `write`, returning the directory and number of
+/// samples per split of each partition. `to_record` gives the json record of a sample for
+/// content dependent split strategies.
+fn save_splits<T: Clone, F, R>(
+    path_prefix: &str,
     samples: &Vec<T>,
-    file: &mut File,
+    opts: &OutputOptions,
+    to_record: R,
+    write: F,
+) -> std::io::Result<Vec<(String, SplitCounts)>>
+where
+    F: Fn(&Vec<T>, &str) -> std::io::Result<()>,
+    R: Fn(&T) -> serde_json::Value,
+{
+    let partitions = split::assign_splits(samples, &to_record, &opts.split);
+    let mut outputs = Vec::new();
+    for partition in &partitions {
+        let dir = match &partition.name {
+            Some(name) => format!("{}/{}", path_prefix, name),
+            None => path_prefix.to_string(),
+        };
+        // every partition covers all samples, so `all` is not repeated per partition
+        let paths: Vec<(&'static str, String)> = split_paths(&dir, opts)
+            .into_iter()
+            .filter(|(split, _)| partition.name.is_none() || *split != "all")
+            .collect();
+        let files: Vec<String> = paths.iter().map(|(_, path)| path.clone()).collect();
+        check_overwrite(&files, opts)?;
+        outputs.push((dir, paths));
+    }
+    let mut counts = Vec::new();
+    for (partition, (dir, paths)) in partitions.iter().zip(outputs) {
+        for (_, path) in &paths {
+            fs::create_dir_all(Path::new(path).parent().unwrap())?;
+        }
+        let [train_samples, val_samples, test_samples] = partition.splits.clone().map(|indices| {
+            indices
+                .iter()
+                .map(|&i| samples[i].clone())
+                .collect::<Vec<T>>()
+        });
+        let mut split_counts = Vec::new();
+        for (split, path) in &paths {
+            let split_samples = match *split {
+                "all" => samples,
+                "train" => &train_samples,
+                "val" | "validation" => &val_samples,
+                _ => &test_samples,
+            };
+            write(split_samples, path)?;
+            if opts.vocab {
+                let mut vocab = Vocab::new();
+                for sample in split_samples {
+                    vocab.add(&to_record(sample));
+                }
+                vocab.write(&vocab_path(split, path, opts), opts)?;
+            }
+            split_counts.push((*split, split_samples.len()));
+        }
+        counts.push((dir, split_counts));
+    }
+    Ok(counts)
+}
+
+/// The code, code tokens and comment of the functions of a `DataSample` whose lengths are
+/// written, with the prefix of their length columns. Samples pairing two functions have the
+/// lengths of both, samples without a comment have no `doc_token_count`.
+fn length_fields(sample: &DataSample) -> Vec<(&str, &str, &[String], Option<&str>)> {
+    match sample {
+        DataSample::FuncCall(caller_src, caller_tokens, callee_src, callee_tokens, _) => vec![
+            ("caller_", caller_src, caller_tokens, None),
+            ("callee_", callee_src, callee_tokens, None),
+        ],
+        DataSample::FuncCallComm(caller_src, caller_com, callee_src, callee_com, ..) => vec![
+            ("caller_", caller_src, &[], Some(caller_com)),
+            ("callee_", callee_src, &[], Some(callee_com)),
+        ],
+        DataSample::FuncComm(src, com)
+        | DataSample::FileComm(src, com)
+        | DataSample::ClassComm(src, com)
+        | DataSample::StaleComment(com, src, _) => vec![("", src, &[], Some(com))],
+        DataSample::ReadmeApi(_, _, src) => vec![("", src, &[], None)],
+        DataSample::FuncHistory(..) | DataSample::Fields(_) => Vec::new(),
+    }
+}
+
+/// The record written for a `DataSample`, with the lines and token counts of its functions
+pub(crate) fn data_sample_record(sample: &DataSample) -> Record {
+    let mut record = sample_record(sample);
+    for (prefix, code, code_tokens, comm) in length_fields(sample) {
+        let (code_lines, code_token_count, doc_token_count) =
+            lengths(code, code_tokens, comm.unwrap_or_default(), &[]);
+        record.columns.push(format!("{}code_lines", prefix));
+        record.values.push(code_lines.into());
+        record.columns.push(format!("{}code_token_count", prefix));
+        record.values.push(code_token_count.into());
+        if comm.is_some() {
+            record.columns.push(format!("{}doc_token_count", prefix));
+            record.values.push(doc_token_count.into());
+        }
+    }
+    record
+}
+
+fn sample_record(sample: &DataSample) -> Record {
+    let (columns, value): (&[&str], serde_json::Value) = match sample {
+        DataSample::FuncCall(caller_src, caller_tokens, callee_src, callee_tokens, recursive) => {
+            let record = FuncCallRecord {
+                caller_code: caller_src,
+                caller_code_tokens: caller_tokens,
+                callee_code: callee_src,
+                callee_code_tokens: callee_tokens,
+                recursive: *recursive,
+            };
+            return Record::new(&record, None).unwrap();
+        }
+        DataSample::FuncCallComm(caller_src, caller_com, callee_src, callee_com, label, _) => (
+            &FUNC_CALL_COMM_COLUMNS,
+            serde_json::json!([caller_src, caller_com, callee_src, callee_com, label]),
+        ),
+        DataSample::FuncComm(src, com) => (&FUNC_COMM_COLUMNS, serde_json::json!([src, com])),
+        DataSample::FileComm(src, com) => (&FILE_COMM_COLUMNS, serde_json::json!([src, com])),
+        DataSample::ClassComm(src, com) => (&FUNC_COMM_COLUMNS, serde_json::json!([src, com])),
+        DataSample::ReadmeApi(section, name, src) => {
+            (&README_API_COLUMNS, serde_json::json!([section, name, src]))
+        }
+        DataSample::FuncHistory(name, versions) => {
+            let field = |field: fn(&FuncVersion) -> &String| -> Vec<&String> {
+                versions.iter().map(field).collect()
+            };
+            (
+                &FUNC_HISTORY_COLUMNS,
+                serde_json::json!([
+                    name,
+                    field(|version| &version.code),
+                    field(|version| &version.commit),
+                    field(|version| &version.author),
+                    field(|version| &version.date),
+                    field(|version| &version.message),
+                ]),
+            )
+        }
+        DataSample::StaleComment(comment, src, is_stale) => (
+            &STALE_COMMENT_COLUMNS,
+            serde_json::json!([comment, src, is_stale]),
+        ),
+        DataSample::Fields(fields) => {
+            return Record {
+                columns: fields.iter().map(|(name, _)| name.clone()).collect(),
+                values: fields
+                    .iter()
+                    .map(|(_, value)| serde_json::Value::String(value.clone()))
+                    .collect(),
+                is_object: true,
+            }
+        }
+    };
+    let values = match value {
+        serde_json::Value::Array(values) => values,
+        _ => unreachable!(),
+    };
+    let mut record = Record {
+        columns: columns.iter().map(|c| c.to_string()).collect(),
+        values,
+        is_object: false,
+    };
+    // the samples of runs with --include-recursive all have the column
+    if let DataSample::FuncCallComm(.., Some(recursive)) = sample {
+        record.columns.push("recursive".to_string());
+        record.values.push((*recursive).into());
+    }
+    record
+}
+
+/// HuggingFace `datasets` type of a json value as the keys of its feature in the YAML metadata
+/// of a dataset card, indented by `indent` spaces. Lists take the type of their first item,
+/// empty lists and nulls are taken as strings.
+fn hf_feature(value: &serde_json::Value, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+    let dtype = match value {
+        serde_json::Value::Bool(_) => "bool",
+        serde_json::Value::Number(n) if n.is_i64() => "int64",
+        serde_json::Value::Number(n) if n.is_u64() => "uint64",
+        serde_json::Value::Number(_) => "float64",
+        serde_json::Value::Array(items) => {
+            let item = items.first().unwrap_or(&serde_json::Value::Null);
+            return match item {
+                serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                    format!("{}sequence:\n{}", pad, hf_feature(item, indent + 2))
+                }
+                _ => hf_feature(item, indent).replacen("dtype:", "sequence:", 1),
+            };
+        }
+        serde_json::Value::Object(fields) => {
+            let mut feature = format!("{}struct:\n", pad);
+            for (name, value) in fields {
+                feature.push_str(&format!("{}- name: {}\n", pad, name));
+                feature.push_str(&hf_feature(value, indent + 2));
+            }
+            return feature;
+        }
+        _ => "string",
+    };
+    format!("{}dtype: {}\n", pad, dtype)
+}
+
+/// Write a small metadata file through a temporary file
+pub(crate) fn write_metadata_file(
+    path: &str,
+    content: &[u8],
+    opts: &OutputOptions,
 ) -> std::io::Result<()> {
-    for sample in samples {
-        let json_string = serde_json::to_string(sample).unwrap() + "\n";
-        file.write(json_string.as_bytes())?;
+    let tmp = tmp_path(path);
+    let mut file = File::create(&tmp)?;
+    file.write_all(content)?;
+    if opts.fsync {
+        file.sync_all()?;
     }
-    Ok(())
+    fs::rename(&tmp, path)
 }
 
-pub fn write_to_json_gen<T: Serialize>(samples: &Vec<T>, file_path: &str) {
-    println!("Writing to {}", file_path);
-    let mut file = File::create(file_path).unwrap();
-    for sample in samples {
-        let json_string = serde_json::to_string(sample).unwrap() + "\n";
-        file.write(json_string.as_bytes()).unwrap();
+/// Write a README whose YAML metadata describes the splits and features of the dataset so that
+/// the output directory can be loaded with `datasets.load_dataset`, optionally followed by a
+/// dataset card stub
+pub(crate) fn write_hf_metadata(
+    path_prefix: &str,
+    record: Option<Record>,
+    counts: &[(&str, usize)],
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let (columns, values) = match record {
+        Some(record) => {
+            let record = record.project(&opts.fields)?;
+            (record.columns, record.values)
+        }
+        None => Default::default(),
+    };
+    let mut readme = String::from("---\nconfigs:\n- config_name: default\n  data_files:\n");
+    for (split, _) in counts {
+        readme.push_str(&format!("  - split: {}\n    path: {}/*\n", split, split));
+    }
+    readme.push_str("dataset_info:\n  features:\n");
+    for (column, value) in columns.iter().zip(&values) {
+        readme.push_str(&format!("  - name: {}\n", column));
+        readme.push_str(&hf_feature(value, 4));
     }
+    readme.push_str("  splits:\n");
+    for (split, num_examples) in counts {
+        readme.push_str(&format!(
+            "  - name: {}\n    num_examples: {}\n",
+            split, num_examples
+        ));
+    }
+    readme.push_str("---\n");
+    if opts.hf_readme {
+        readme.push_str("\n# Dataset\n\nGenerated by sparser.\n");
+    }
+    write_metadata_file(
+        &format!("{}/README.md", path_prefix),
+        readme.as_bytes(),
+        opts,
+    )
 }
 
-pub fn save_data_gen<T: Serialize + Clone>(path_prefix: &str, samples: &Vec<T>) {
-    fs::create_dir_all(path_prefix).unwrap();
-    write_to_json_gen(samples, &format!("{}/all.jsonl", path_prefix));
-    // split into train:val:test = 8:1:1
-    let (train_samples, other_samples) = split_array(samples, 8, 2);
-    let (val_samples, test_samples) = split_array(&other_samples, 1, 1);
-    write_to_json_gen(&train_samples, &format!("{}/train.jsonl", path_prefix));
-    write_to_json_gen(&val_samples, &format!("{}/val.jsonl", path_prefix));
-    write_to_json_gen(&test_samples, &format!("{}/test.jsonl", path_prefix));
+pub fn save_dataset(
+    path_prefix: &str,
+    samples: &Vec<DataSample>,
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let to_record =
+        |sample: &DataSample| serde_json::Value::Object(data_sample_record(sample).to_object());
+    let counts = save_splits(
+        path_prefix,
+        samples,
+        opts,
+        to_record,
+        |split_samples, path| write_data_samples(split_samples, path, opts),
+    )?;
+    if opts.hf_layout {
+        for (dir, split_counts) in &counts {
+            let record = samples.first().map(data_sample_record);
+            write_hf_metadata(dir, record, split_counts, opts)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn save_data_gen<T: Serialize + Clone>(
+    path_prefix: &str,
+    samples: &Vec<T>,
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let to_record = |sample: &T| serde_json::to_value(sample).unwrap();
+    let counts = save_splits(
+        path_prefix,
+        samples,
+        opts,
+        to_record,
+        |split_samples, path| write_samples(split_samples, path, opts),
+    )?;
+    if opts.hf_layout {
+        for (dir, split_counts) in &counts {
+            let record = samples.first().map(|s| Record::new(s, None)).transpose()?;
+            write_hf_metadata(dir, record, split_counts, opts)?;
+        }
+    }
+    Ok(())
 }
 
 #[allow(dead_code)]
@@ -122,7 +723,7 @@ pub fn print_node_text(capture: &QueryCapture, query: &Query, code: &str) {
             "    capture: {}, start: {}, text: {:?}",
             capture_name,
             start,
-            capture.node.utf8_text(&code.as_bytes()).unwrap_or("")
+            capture.node.utf8_text(code.as_bytes()).unwrap_or("")
         );
     } else {
         let start_byte = capture.node.start_byte();
@@ -137,5 +738,64 @@ pub fn print_node_text(capture: &QueryCapture, query: &Query, code: &str) {
 }
 
 pub fn get_node_text(node: Node, code: &str) -> String {
-    node.utf8_text(code.as_bytes()).unwrap_or("").to_string()
+    node_text(node, code).to_string()
+}
+
+/// Text of `node`, borrowed from `code`
+pub fn node_text<'a>(node: Node, code: &'a str) -> &'a str {
+    node.utf8_text(code.as_bytes()).unwrap_or("")
+}
+
+/// Texts of the leaf nodes under `node`, skipping comments
+pub fn get_node_tokens(node: Node, code: &str) -> Vec<String> {
+    get_node_token_ranges(node)
+        .into_iter()
+        .map(|range| code.get(range).unwrap_or("").to_string())
+        .collect()
+}
+
+/// Byte ranges of the leaf nodes under `node`, skipping comments
+pub fn get_node_token_ranges(node: Node) -> Vec<Range<usize>> {
+    let mut tokens = Vec::new();
+    let mut cursor = node.walk();
+    loop {
+        let current = cursor.node();
+        if current.kind() != "comment" {
+            if current.child_count() == 0 {
+                tokens.push(current.byte_range());
+            } else if cursor.goto_first_child() {
+                continue;
+            }
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() || cursor.node() == node {
+                return tokens;
+            }
+        }
+    }
+}
+
+/// Share of the nodes of a syntax tree which are ERROR or MISSING nodes, high for sources in a
+/// dialect the grammar cannot handle
+pub fn error_node_ratio(tree: &Tree) -> f64 {
+    if !tree.root_node().has_error() {
+        return 0.0;
+    }
+    let (mut num_nodes, mut num_errors) = (0, 0);
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        num_nodes += 1;
+        if node.is_error() || node.is_missing() {
+            num_errors += 1;
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return num_errors as f64 / num_nodes as f64;
+            }
+        }
+    }
 }
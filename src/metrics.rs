@@ -0,0 +1,234 @@
+//! Throughput and failure counters of a run, to tune `--threads` empirically. The counters are
+//! process-wide and written as a JSON report next to the config of `extract` and `pair-calls`
+//! runs. `serve` and `pair-calls --metrics-addr` also expose them in the Prometheus text format
+//! at `/metrics`.
+//!
+//! The wait times tell where a run is bound: readers waiting to send mean the workers are the
+//! bottleneck, workers waiting to receive mean the input is.
+
+use crate::{write_metadata_file, OutputOptions};
+use log::{debug, info};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+static FILES: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static SAMPLES: AtomicU64 = AtomicU64::new(0);
+static PARSE_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// `binary` pairs whose caller code tokens do not have as many masks as the masked code
+static INCONSISTENT_TOKENS: AtomicU64 = AtomicU64::new(0);
+/// nanoseconds readers waited for room in the channel to the workers
+static SEND_WAIT: AtomicU64 = AtomicU64::new(0);
+/// nanoseconds workers waited for input from the channel
+static RECV_WAIT: AtomicU64 = AtomicU64::new(0);
+
+lazy_static::lazy_static! {
+    static ref START: Instant = Instant::now();
+}
+
+/// Start the clock of the rates, at the start of a run
+pub fn start() {
+    lazy_static::initialize(&START);
+}
+
+/// A file or archive member was read
+pub fn add_file(num_bytes: u64) {
+    FILES.fetch_add(1, Ordering::Relaxed);
+    BYTES_READ.fetch_add(num_bytes, Ordering::Relaxed);
+}
+
+/// Bytes were read from a file counted with `add_file(0)`
+pub fn add_bytes(num_bytes: u64) {
+    BYTES_READ.fetch_add(num_bytes, Ordering::Relaxed);
+}
+
+/// Samples were generated
+pub fn add_samples(num_samples: usize) {
+    SAMPLES.fetch_add(num_samples as u64, Ordering::Relaxed);
+}
+
+pub fn add_parse_failure() {
+    PARSE_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add_inconsistent_tokens() {
+    INCONSISTENT_TOKENS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Run `send`, counting the time as the wait of a reader for the workers
+pub fn timed_send<T>(send: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = send();
+    add_duration(&SEND_WAIT, start.elapsed());
+    result
+}
+
+/// A worker waited `wait` for input
+pub fn add_recv_wait(wait: Duration) {
+    add_duration(&RECV_WAIT, wait);
+}
+
+fn add_duration(counter: &AtomicU64, duration: Duration) {
+    counter.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+fn seconds(counter: &AtomicU64) -> f64 {
+    counter.load(Ordering::Relaxed) as f64 / 1e9
+}
+
+/// The counters of the run so far and the rates derived from them
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub elapsed_seconds: f64,
+    pub files: u64,
+    pub files_per_second: f64,
+    pub bytes_read: u64,
+    pub samples: u64,
+    pub samples_per_second: f64,
+    pub parse_failures: u64,
+    pub inconsistent_tokens: u64,
+    pub send_wait_seconds: f64,
+    pub recv_wait_seconds: f64,
+}
+
+pub fn report() -> Report {
+    let elapsed_seconds = START.elapsed().as_secs_f64();
+    let rate = |count: u64| match elapsed_seconds > 0.0 {
+        true => count as f64 / elapsed_seconds,
+        false => 0.0,
+    };
+    let files = FILES.load(Ordering::Relaxed);
+    let samples = SAMPLES.load(Ordering::Relaxed);
+    Report {
+        elapsed_seconds,
+        files,
+        files_per_second: rate(files),
+        bytes_read: BYTES_READ.load(Ordering::Relaxed),
+        samples,
+        samples_per_second: rate(samples),
+        parse_failures: PARSE_FAILURES.load(Ordering::Relaxed),
+        inconsistent_tokens: INCONSISTENT_TOKENS.load(Ordering::Relaxed),
+        send_wait_seconds: seconds(&SEND_WAIT),
+        recv_wait_seconds: seconds(&RECV_WAIT),
+    }
+}
+
+/// Write the report of the run as JSON to `path`
+pub fn write_report(path: &str, opts: &OutputOptions) -> std::io::Result<()> {
+    let report = report();
+    info!(
+        "{} files ({:.1}/s), {} samples ({:.1}/s) in {:.1}s",
+        report.files,
+        report.files_per_second,
+        report.samples,
+        report.samples_per_second,
+        report.elapsed_seconds
+    );
+    write_metadata_file(path, &serde_json::to_vec_pretty(&report)?, opts)
+}
+
+/// The counters in the Prometheus text exposition format
+pub fn prometheus() -> String {
+    let metrics = [
+        (
+            "files_total",
+            "counter",
+            "Files read",
+            FILES.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "bytes_read_total",
+            "counter",
+            "Bytes of input read",
+            BYTES_READ.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "samples_total",
+            "counter",
+            "Samples generated",
+            SAMPLES.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "parse_failures_total",
+            "counter",
+            "Sources which could not be parsed",
+            PARSE_FAILURES.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "inconsistent_tokens_total",
+            "counter",
+            "Pairs whose code tokens do not match the masked code",
+            INCONSISTENT_TOKENS.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "send_wait_seconds_total",
+            "counter",
+            "Time readers waited for the workers",
+            seconds(&SEND_WAIT),
+        ),
+        (
+            "recv_wait_seconds_total",
+            "counter",
+            "Time workers waited for input",
+            seconds(&RECV_WAIT),
+        ),
+        (
+            "uptime_seconds",
+            "gauge",
+            "Time since the start of the run",
+            START.elapsed().as_secs_f64(),
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        text.push_str(&format!(
+            "# HELP sparser_{name} {help}\n# TYPE sparser_{name} {kind}\nsparser_{name} {value}\n",
+            name = name,
+            help = help,
+            kind = kind,
+            value = value
+        ));
+    }
+    text
+}
+
+/// Answer every HTTP request on `addr` with the Prometheus metrics, until the runtime shuts down.
+/// Returns once the address is bound so that a taken address is reported up front.
+pub async fn serve_prometheus(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    debug!("metrics connection error: {}", e);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                // the request is not looked at, the headers only need to be read
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = prometheus();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                if let Err(e) = stream.write_all(response.as_bytes()).await {
+                    debug!("metrics connection error: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,2322 @@
+use crate::analysis::{find_call_sites, find_called_functions, find_comments, LanguageSpec};
+use crate::anonymize::anonymize_group;
+use crate::ast::{function_ast, AstFormat};
+use crate::complexity::{function_complexity, Complexity};
+use crate::dataset::{reservoir_sample, seeded_rng, Reservoir};
+use crate::detect::detect_language;
+use crate::error::{check_failure_rate, Error, Result, MAX_FAILURE_RATE};
+use crate::input::{
+    expand_glob, is_archive, is_glob, is_url, visit_input_file, walk_source_files, PartitionArgs,
+    WalkArgs, STDIN_PATH,
+};
+use crate::layout::CodeLayout;
+use crate::metrics;
+use crate::preview;
+use crate::remote::{self, REMOTE_CACHE};
+use crate::samples::lengths;
+use crate::shutdown;
+use crate::split::stable_hash;
+use crate::subtoken::{IdentifierStyle, Subtokenizer};
+use crate::{
+    get_node_token_ranges, required_arg, save_config, CallJsonSample, ChainCallSample, JsonSample,
+    OutputArgs, OutputFileWriter, OutputFormat, OutputOptions, RankedCallSample, Record, SchemaMap,
+    TripletCallSample, FUNC_CALL_ID_MASK, STDOUT_PATH,
+};
+use futures::{StreamExt, TryStreamExt};
+use linya::{Bar, Progress};
+use log::{debug, error, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::Mutex;
+
+/// Pair callers and callees of CodeSearchNet style jsonl corpora
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PairCallsArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Jsonl file, directory of jsonl files, glob pattern such as `'corpus/**/train-*.jsonl'`,
+    /// `s3://`/`gs://` path or `http(s)://` URL, `-` reads samples from stdin
+    #[clap(short = 'd', long)]
+    pub data: Option<String>,
+    /// Output file or `s3://`/`gs://` path, `-` writes to stdout [default: output, or `-` when
+    /// reading stdin]
+    #[clap(short = 'o', long)]
+    pub out: Option<String>,
+    /// Language of the samples, or a comma separated list such as `python,java` or `auto` for
+    /// all languages to read the language of each sample from its `language` field, from the
+    /// name of its input file, e.g. `python_train_0.jsonl`, or to detect it from its path and
+    /// code
+    #[clap(short = 'l', long)]
+    pub lang: Option<LangSelection>,
+    /// Map the fields of other jsonl corpora onto the sample fields, e.g.
+    /// `code=content, func_name=name, repo=repository.full_name`
+    #[clap(long)]
+    pub schema_map: Option<SchemaMap>,
+    /// Directory of queries overriding the built-in ones, as `<language>/func_call.scm`
+    #[clap(long)]
+    pub query_dir: Option<String>,
+    /// How the callees of negative pairs are picked among the functions of the repo the caller
+    /// does not call: `repo` for any of them, `cross-file` to prefer those of other files than
+    /// the caller's, which are less trivially unrelated than its neighbours [default: repo]
+    #[clap(long)]
+    pub negative_strategy: Option<NegativeStrategy>,
+    /// Rows written for the calls: `binary` for a positive and a negative caller-callee pair
+    /// each, `ranked` for the callee among --distractors functions the caller does not call,
+    /// `triplet` for the caller, the callee and a function the caller does not call, `chain` for
+    /// the caller, the callee and each function of the repo the callee calls in turn
+    /// [default: binary]
+    #[clap(long)]
+    pub pair_format: Option<PairFormat>,
+    /// Negative pairs per positive pair of the `binary` format, with distinct functions the
+    /// caller does not call [default: 1]
+    #[clap(long)]
+    pub negatives_per_positive: Option<usize>,
+    /// Functions the caller reaches in up to this many calls are not used as negatives, 1 only
+    /// leaves out its callees [default: all functions it reaches]
+    #[clap(long)]
+    pub exclude_reachable_depth: Option<usize>,
+    /// What becomes of mentions of the callee in the comment of the caller of positive pairs and
+    /// in the comments of its code, which give away the masked callee: `keep`, `mask` or `drop`
+    /// them [default: keep]
+    #[clap(long)]
+    pub comment_mentions: Option<CommentMentions>,
+    /// Also write the caller code before masking, as `caller_code_original` or
+    /// `anchor_code_original`
+    #[clap(long)]
+    pub keep_original: bool,
+    /// Also write the syntax tree of each function of a row, of callers before masking, as the
+    /// tree-sitter S-expression or a compact JSON tree: sexp or json. The trees are written as
+    /// `caller_ast` and `callee_ast`, `candidate_asts` of `ranked` rows or `anchor_ast`,
+    /// `positive_ast` and `negative_ast` of `triplet` rows.
+    #[clap(long)]
+    pub with_ast: Option<AstFormat>,
+    /// Layout of the code of pairs: raw, collapsed onto one line or normalized-indent, dedented
+    /// and indented by levels of four spaces. The mask spans refer to the code as laid out.
+    /// [default: raw]
+    #[clap(long)]
+    pub code_layout: Option<CodeLayout>,
+    /// Also write structural metrics of the callers computed from their syntax tree, to stratify
+    /// or filter pairs by difficulty: the cyclomatic complexity, how deeply blocks nest and the
+    /// number of parameters, as `caller_complexity`, `caller_nesting_depth` and
+    /// `caller_num_params`, and alike of the callees of `binary` pairs. Triplet rows have them of
+    /// the anchor.
+    #[clap(long)]
+    pub complexity: bool,
+    /// Derive the code tokens of callers again from their code, with the calls of the callee
+    /// masked, where the given tokens do not have as many masks as the code. Such pairs are only
+    /// counted otherwise.
+    #[clap(long)]
+    pub repair_tokens: bool,
+    /// Comma separated identifier styles, camel and snake, whose identifiers are split into
+    /// lowercase sub-tokens in the code and comment token lists of `binary` pairs, e.g.
+    /// `parseHttpResponse` into `parse`, `http` and `response`. The mask token indices refer to
+    /// the split tokens. [default: identifiers are kept whole]
+    #[clap(long, use_value_delimiter = true)]
+    pub subtokenize: Option<Vec<IdentifierStyle>>,
+    /// Rename the functions of each repo `FUNC_1`, `FUNC_2`, … and the other identifiers of
+    /// each function `VAR_1`, `VAR_2`, … before pairing, for an obfuscated variant of the
+    /// dataset. Library functions and types keep their names.
+    #[clap(long)]
+    pub anonymize: bool,
+    /// Write a positive `binary` pair per call of the callee instead of per caller, with the
+    /// lines of the caller around the call, and negative pairs with the same lines
+    #[clap(long)]
+    pub per_call_site: bool,
+    /// Lines before and after the call in the caller code of --per-call-site pairs
+    /// [default: 3]
+    #[clap(long)]
+    pub call_site_context: Option<usize>,
+    /// Distractors of each `ranked` row, calls of callers which do not leave as many functions
+    /// of their repo uncalled are skipped [default: 4]
+    #[clap(long)]
+    pub distractors: Option<usize>,
+    /// Write a uniform random sample of at most N pairs, drawn by reservoir sampling while the
+    /// pairs are generated so that only the sample is held in memory
+    #[clap(long)]
+    pub max_samples: Option<usize>,
+    /// Keep at most N pairs of each repo, so that a few large repos do not dominate the dataset.
+    /// The cap is filled as the pairs are generated: the pairs of a sample group which do not all
+    /// fit in what the earlier groups of the repo left are sampled uniformly, so the groups of a
+    /// repo read first are favoured over its later ones.
+    #[clap(long)]
+    pub max_samples_per_repo: Option<usize>,
+    /// Print the first N pairs for reading instead of writing an output, to check what a language
+    /// or query pairs before a full run
+    #[clap(long)]
+    pub preview: Option<usize>,
+    /// Seed of the sampling of --max-samples and --max-samples-per-repo. A seed with either
+    /// implies --stable-order, as the sample drawn depends on the order of the pairs
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Only read the first N input files, to try a configuration on a slice of the corpus
+    #[clap(long)]
+    pub limit_files: Option<usize>,
+    /// Stop reading input once N samples were read, to try a configuration on a slice of the
+    /// corpus
+    #[clap(long)]
+    pub limit_samples: Option<usize>,
+    /// Exit with an error once the pairs are written when a larger share of the input files
+    /// could not be read, or of the input rows are not samples [default: 0.1]
+    #[clap(long)]
+    pub max_failure_rate: Option<f64>,
+    /// Write the same output on every run over the same input: files are read one after the
+    /// other in path order and sample groups are paired one at a time, in input order, and the
+    /// position of the callee among the candidates of `ranked` rows is derived from their code.
+    /// Only the work within a group runs in parallel then, so runs are slower.
+    #[clap(long)]
+    pub stable_order: bool,
+    /// Number of sample groups processed concurrently [default: number of cpus]
+    #[clap(short = 't', long)]
+    pub threads: Option<usize>,
+    /// Keep watching the --data directory and append the pairs of new files, and of lines
+    /// appended to jsonl files, to the output until interrupted
+    #[clap(long)]
+    pub watch: bool,
+    /// Serve the throughput and failure counters of the run in the Prometheus text format on
+    /// this address, e.g. 127.0.0.1:9100, mostly useful with --watch
+    #[clap(long)]
+    pub metrics_addr: Option<SocketAddr>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub partition: PartitionArgs,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetLanguage {
+    Python,
+    Javascript,
+    Java,
+    Go,
+    Php,
+    Ruby,
+}
+
+impl FromStr for TargetLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "go" => Ok(TargetLanguage::Go),
+            "javascript" => Ok(TargetLanguage::Javascript),
+            "java" => Ok(TargetLanguage::Java),
+            "php" => Ok(TargetLanguage::Php),
+            "python" => Ok(TargetLanguage::Python),
+            "ruby" => Ok(TargetLanguage::Ruby),
+            _ => Err(format!("Unknown language: {}", s)),
+        }
+    }
+}
+
+impl TargetLanguage {
+    pub const ALL: [TargetLanguage; 6] = [
+        TargetLanguage::Python,
+        TargetLanguage::Javascript,
+        TargetLanguage::Java,
+        TargetLanguage::Go,
+        TargetLanguage::Php,
+        TargetLanguage::Ruby,
+    ];
+
+    pub fn name(self) -> &'static str {
+        self.spec().name
+    }
+
+    pub fn spec(self) -> LanguageSpec {
+        match self {
+            TargetLanguage::Python => LanguageSpec::python(),
+            TargetLanguage::Javascript => LanguageSpec::javascript(),
+            TargetLanguage::Go => LanguageSpec::go(),
+            TargetLanguage::Java => LanguageSpec::java(),
+            TargetLanguage::Ruby => LanguageSpec::ruby(),
+            TargetLanguage::Php => LanguageSpec::php(),
+        }
+    }
+}
+
+/// How the callees of negative pairs are picked
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NegativeStrategy {
+    /// any function of the repo in name order
+    #[default]
+    Repo,
+    /// functions of other files of the repo first
+    CrossFile,
+}
+
+impl FromStr for NegativeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "repo" => Ok(NegativeStrategy::Repo),
+            "cross-file" => Ok(NegativeStrategy::CrossFile),
+            _ => Err(format!("Unknown negative strategy: {}", s)),
+        }
+    }
+}
+
+/// Rows written for the calls of a sample group
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PairFormat {
+    /// `CallJsonSample`s labeled whether the caller calls the callee
+    #[default]
+    Binary,
+    /// `RankedCallSample`s
+    Ranked,
+    /// `TripletCallSample`s
+    Triplet,
+    /// `ChainCallSample`s
+    Chain,
+}
+
+impl FromStr for PairFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(PairFormat::Binary),
+            "ranked" => Ok(PairFormat::Ranked),
+            "triplet" => Ok(PairFormat::Triplet),
+            "chain" => Ok(PairFormat::Chain),
+            _ => Err(format!("Unknown pair format: {}", s)),
+        }
+    }
+}
+
+/// What becomes of mentions of the callee in the comment of the caller and the comments of its code
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommentMentions {
+    #[default]
+    Keep,
+    /// replaced by `FUNC_CALL_ID_MASK` like the calls in the code
+    Mask,
+    /// removed
+    Drop,
+}
+
+impl FromStr for CommentMentions {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(CommentMentions::Keep),
+            "mask" => Ok(CommentMentions::Mask),
+            "drop" => Ok(CommentMentions::Drop),
+            _ => Err(format!("Unknown comment mentions: {}", s)),
+        }
+    }
+}
+
+/// Default of --distractors
+pub const DISTRACTORS: usize = 4;
+/// Default of --call-site-context
+pub const CALL_SITE_CONTEXT: usize = 3;
+
+/// How the pairs of a sample group are built
+#[derive(Debug, Clone, Copy)]
+pub struct PairOptions {
+    pub negative_strategy: NegativeStrategy,
+    pub format: PairFormat,
+    pub negatives_per_positive: usize,
+    /// calls through which functions reached by a caller are not negatives, `None` for any
+    pub exclusion_depth: Option<usize>,
+    /// lines around the call in the caller code of pairs per call site, `None` for pairs per
+    /// caller
+    pub call_site_context: Option<usize>,
+    pub comment_mentions: CommentMentions,
+    /// whether the caller code before masking is written too
+    pub keep_original: bool,
+    /// how the syntax trees of the functions are written, `None` if they are not
+    pub with_ast: Option<AstFormat>,
+    /// layout of the code of the pairs, `None` for the code as it is in the input
+    pub code_layout: Option<CodeLayout>,
+    /// whether the structural metrics of the functions are written
+    pub complexity: bool,
+    /// whether code tokens which do not match the masked code are derived from it again
+    pub repair_tokens: bool,
+    /// how the identifiers of token lists are split, `None` to keep them whole
+    pub subtokenizer: Option<Subtokenizer>,
+    /// whether the identifiers of the samples are renamed before pairing
+    pub anonymize: bool,
+    pub distractors: usize,
+    /// whether pairs are generated in input order, for outputs which are the same on every run
+    pub stable_order: bool,
+    pub max_samples_per_repo: Option<usize>,
+    /// seed of the sampling of the pairs of each repo
+    pub seed: Option<u64>,
+}
+
+impl Default for PairOptions {
+    fn default() -> Self {
+        PairOptions {
+            negative_strategy: NegativeStrategy::default(),
+            format: PairFormat::default(),
+            negatives_per_positive: 1,
+            exclusion_depth: None,
+            call_site_context: None,
+            comment_mentions: CommentMentions::default(),
+            keep_original: false,
+            with_ast: None,
+            code_layout: None,
+            complexity: false,
+            repair_tokens: false,
+            subtokenizer: None,
+            anonymize: false,
+            distractors: DISTRACTORS,
+            stable_order: false,
+            max_samples_per_repo: None,
+            seed: None,
+        }
+    }
+}
+
+impl PairCallsArgs {
+    pub fn to_pair_options(&self) -> PairOptions {
+        PairOptions {
+            negative_strategy: self.negative_strategy.unwrap_or_default(),
+            format: self.pair_format.unwrap_or_default(),
+            negatives_per_positive: self.negatives_per_positive.unwrap_or(1),
+            exclusion_depth: self.exclude_reachable_depth,
+            call_site_context: match self.per_call_site {
+                true => Some(self.call_site_context.unwrap_or(CALL_SITE_CONTEXT)),
+                false => None,
+            },
+            comment_mentions: self.comment_mentions.unwrap_or_default(),
+            keep_original: self.keep_original,
+            with_ast: self.with_ast,
+            code_layout: self.code_layout,
+            complexity: self.complexity,
+            repair_tokens: self.repair_tokens,
+            subtokenizer: self.subtokenize.as_deref().and_then(Subtokenizer::new),
+            anonymize: self.anonymize,
+            distractors: self.distractors.unwrap_or(DISTRACTORS),
+            stable_order: self.stable_order,
+            max_samples_per_repo: self.max_samples_per_repo,
+            seed: self.seed,
+        }
+    }
+}
+
+/// An output row in the --pair-format of the run
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum PairRecord {
+    Binary(CallJsonSample),
+    Ranked(RankedCallSample),
+    Triplet(TripletCallSample),
+    Chain(ChainCallSample),
+}
+
+impl PairRecord {
+    /// The record with its code laid out as `layout` and its mask spans moved along
+    fn lay_out(self, layout: CodeLayout) -> Self {
+        let lay_out_caller = |code: &mut String, spans: &mut Vec<[usize; 2]>| {
+            let laid_out = layout.apply(code);
+            *spans = moved_mask_spans(code, spans, &laid_out);
+            *code = laid_out;
+        };
+        let lay_out = |code: &mut String| *code = layout.apply(code);
+        match self {
+            PairRecord::Binary(mut pair) => {
+                lay_out_caller(&mut pair.caller_code, &mut pair.caller_mask_spans);
+                lay_out(&mut pair.callee_code);
+                pair.caller_code_original.as_mut().map(lay_out);
+                PairRecord::Binary(pair)
+            }
+            PairRecord::Ranked(mut row) => {
+                lay_out_caller(&mut row.caller_code, &mut row.caller_mask_spans);
+                row.candidate_codes.iter_mut().for_each(lay_out);
+                row.caller_code_original.as_mut().map(lay_out);
+                PairRecord::Ranked(row)
+            }
+            PairRecord::Triplet(mut row) => {
+                lay_out_caller(&mut row.anchor_code, &mut row.anchor_mask_spans);
+                lay_out(&mut row.positive_code);
+                lay_out(&mut row.negative_code);
+                row.anchor_code_original.as_mut().map(lay_out);
+                PairRecord::Triplet(row)
+            }
+            PairRecord::Chain(mut row) => {
+                lay_out_caller(&mut row.caller_code, &mut row.caller_mask_spans);
+                lay_out_caller(&mut row.callee_code, &mut row.callee_mask_spans);
+                lay_out(&mut row.second_callee_code);
+                row.caller_code_original.as_mut().map(lay_out);
+                row.callee_code_original.as_mut().map(lay_out);
+                PairRecord::Chain(row)
+            }
+        }
+    }
+}
+
+impl CallJsonSample {
+    /// The pair with the identifiers of its token lists split by `subtokenizer`
+    fn subtokenize(mut self, subtokenizer: Subtokenizer) -> Self {
+        let (caller_code_tokens, caller_mask_token_indices) =
+            subtokenizer.apply_indexed(&self.caller_code_tokens, &self.caller_mask_token_indices);
+        self.caller_code_tokens = caller_code_tokens;
+        self.caller_mask_token_indices = caller_mask_token_indices;
+        self.caller_comm_tokens = subtokenizer.apply(&self.caller_comm_tokens);
+        self.callee_code_tokens = subtokenizer.apply(&self.callee_code_tokens);
+        self.callee_comm_tokens = subtokenizer.apply(&self.callee_comm_tokens);
+        self
+    }
+}
+
+/// The spans of `spans` in `code` in `laid_out`, the same code laid out another way. Masks have
+/// no whitespace and keep their order, so the masks of the spans are found by their index among
+/// the masks of the code, of which those masking comment mentions have no span.
+fn moved_mask_spans(code: &str, spans: &[[usize; 2]], laid_out: &str) -> Vec<[usize; 2]> {
+    let starts: Vec<usize> = code
+        .match_indices(FUNC_CALL_ID_MASK)
+        .map(|(start, _)| start)
+        .collect();
+    let moved: Vec<usize> = laid_out
+        .match_indices(FUNC_CALL_ID_MASK)
+        .map(|(start, _)| start)
+        .collect();
+    spans
+        .iter()
+        .filter_map(|[start, _]| starts.iter().position(|s| s == start))
+        .filter_map(|idx| moved.get(idx))
+        .map(|&start| [start, start + FUNC_CALL_ID_MASK.len()])
+        .collect()
+}
+
+/// The languages of a run, `auto` or a comma separated list of languages
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LangSelection {
+    Auto,
+    List(Vec<TargetLanguage>),
+}
+
+impl FromStr for LangSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.trim() == "auto" {
+            return Ok(LangSelection::Auto);
+        }
+        let languages = s
+            .split(',')
+            .map(str::trim)
+            .filter(|lang| !lang.is_empty())
+            .map(TargetLanguage::from_str)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        match languages.is_empty() {
+            true => Err("no language given".to_string()),
+            false => Ok(LangSelection::List(languages)),
+        }
+    }
+}
+
+impl TryFrom<String> for LangSelection {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for LangSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LangSelection::Auto => f.write_str("auto"),
+            LangSelection::List(languages) => {
+                let names: Vec<&str> = languages.iter().map(|lang| lang.name()).collect();
+                f.write_str(&names.join(","))
+            }
+        }
+    }
+}
+
+impl From<LangSelection> for String {
+    fn from(selection: LangSelection) -> Self {
+        selection.to_string()
+    }
+}
+
+/// The specs of the languages of a run. The samples of a run of one language are all of that
+/// language, otherwise the language of a sample is read from its `language` field or detected.
+#[derive(Clone)]
+struct Languages {
+    specs: Vec<LanguageSpec>,
+}
+
+impl Languages {
+    fn new(selection: &LangSelection, query_dir: Option<&str>) -> Result<Self> {
+        let languages = match selection {
+            LangSelection::Auto => &TargetLanguage::ALL[..],
+            LangSelection::List(languages) => &languages[..],
+        };
+        let specs = languages
+            .iter()
+            .map(|lang| match query_dir {
+                Some(query_dir) => lang.spec().with_query_dir(Path::new(query_dir)),
+                None => Ok(lang.spec()),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Languages { specs })
+    }
+
+    /// The spec of the language of `sample`, `None` if it is of no language of the run
+    fn of(&self, sample: &JsonSample) -> Option<LanguageSpec> {
+        if let [spec] = self.specs[..] {
+            return Some(spec);
+        }
+        let language = match sample.language.as_deref() {
+            Some(language) => language,
+            None => detect_language(sample.path.as_deref().map(Path::new), &sample.code)?,
+        };
+        self.specs
+            .iter()
+            .find(|spec| spec.name.eq_ignore_ascii_case(language))
+            .copied()
+    }
+}
+
+/// The language named by the file name or a directory of an input file, e.g.
+/// `python/final/jsonl/train/python_train_0.jsonl.gz`, searched from the file name up
+fn language_of_path(path: &Path) -> Option<&'static str> {
+    path.iter().rev().find_map(|component| {
+        component
+            .to_string_lossy()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .find_map(|token| TargetLanguage::from_str(&token.to_ascii_lowercase()).ok())
+            .map(TargetLanguage::name)
+    })
+}
+
+/// Input files and archives, and those which could not be read
+static NUM_FILES: AtomicUsize = AtomicUsize::new(0);
+static NUM_UNREADABLE: AtomicUsize = AtomicUsize::new(0);
+/// Non-empty input rows
+static NUM_ROWS: AtomicUsize = AtomicUsize::new(0);
+/// Input samples still to be read under --limit-samples
+static SAMPLES_LEFT: AtomicUsize = AtomicUsize::new(usize::MAX);
+/// Input rows which are not a `JsonSample`, samples missing optional fields, samples of no
+/// language of the run, and calls without enough functions the caller does not call for a ranked
+/// or triplet row
+static NUM_INVALID: AtomicUsize = AtomicUsize::new(0);
+static NUM_PARTIAL: AtomicUsize = AtomicUsize::new(0);
+static NUM_OTHER_LANGUAGE: AtomicUsize = AtomicUsize::new(0);
+static NUM_FEW_UNCALLED: AtomicUsize = AtomicUsize::new(0);
+/// `binary` pairs left out as the output already has a pair of their caller and callee
+static NUM_DUPLICATE_PAIRS: AtomicUsize = AtomicUsize::new(0);
+/// Pairs left out as their repo has --max-samples-per-repo pairs already
+static NUM_REPO_CAPPED: AtomicUsize = AtomicUsize::new(0);
+/// Rows written, and the positive and negative ones among the `binary` pairs
+static NUM_WRITTEN: AtomicUsize = AtomicUsize::new(0);
+static NUM_POSITIVE: AtomicUsize = AtomicUsize::new(0);
+static NUM_NEGATIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Count the rows written and the labels of `binary` pairs for the summary of the run
+fn count_written(pairs: &[PairRecord]) {
+    NUM_WRITTEN.fetch_add(pairs.len(), Ordering::Relaxed);
+    for pair in pairs {
+        match pair {
+            PairRecord::Binary(pair) if pair.label => NUM_POSITIVE.fetch_add(1, Ordering::Relaxed),
+            PairRecord::Binary(_) => NUM_NEGATIVE.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+}
+
+/// Time without changes in the watched directory after which the changed files are read
+const WATCH_DELAY: Duration = Duration::from_secs(2);
+
+lazy_static::lazy_static! {
+    static ref PROGRESS: Mutex<Progress> = Mutex::new(Progress::new());
+    /// Pairs written so far of each repo, under --max-samples-per-repo
+    static ref REPO_PAIRS: std::sync::Mutex<HashMap<String, usize>> = Default::default();
+    /// Input files whose sample groups were all sent, for the manifest of an interrupted run
+    static ref FILES_COMPLETED: std::sync::Mutex<Vec<String>> = Default::default();
+}
+
+/// The hashes of the caller and callee code of the `binary` pairs of a run so far, whose pairs
+/// are skipped when they come up again. A 128-bit key per pair keeps the set small however long
+/// the code is.
+#[derive(Debug, Default)]
+pub struct SeenPairs(std::sync::Mutex<HashSet<(u64, u64)>>);
+
+impl SeenPairs {
+    /// Whether there is no pair of `caller` and `callee` yet, which there is from now on
+    fn is_new_pair(&self, caller: &JsonSample, callee: &JsonSample) -> bool {
+        let key = (
+            stable_hash(caller.code.as_bytes()),
+            stable_hash(callee.code.as_bytes()),
+        );
+        let is_new = self.0.lock().unwrap().insert(key);
+        if !is_new {
+            NUM_DUPLICATE_PAIRS.fetch_add(1, Ordering::Relaxed);
+        }
+        is_new
+    }
+}
+
+/// The pairs of a group of `repo` which fit in what --max-samples-per-repo leaves of the repo,
+/// sampled uniformly if they do not all fit. The sample is not uniform over the whole repo, whose
+/// earlier groups take their pairs first, as holding the pairs of every repo until the input
+/// ends would hold the dataset in memory.
+fn cap_repo_pairs(repo: &str, pairs: Vec<PairRecord>, opts: &PairOptions) -> Vec<PairRecord> {
+    let max_pairs = match opts.max_samples_per_repo {
+        Some(max_pairs) => max_pairs,
+        None => return pairs,
+    };
+    let mut repo_pairs = REPO_PAIRS.lock().unwrap();
+    let num_written = repo_pairs.entry(repo.to_string()).or_insert(0);
+    let num_left = max_pairs.saturating_sub(*num_written);
+    let pairs = match pairs.len() > num_left {
+        true => {
+            NUM_REPO_CAPPED.fetch_add(pairs.len() - num_left, Ordering::Relaxed);
+            // seeded per repo, so that a seed keeps the same pairs in whatever order repos come
+            let mut rng = seeded_rng(opts.seed.map(|seed| seed ^ stable_hash(repo.as_bytes())));
+            reservoir_sample(pairs, num_left, &mut rng)
+        }
+        false => pairs,
+    };
+    *num_written += pairs.len();
+    pairs
+}
+
+/// A run with its remote input downloaded and its output staged, the steps before and after
+/// processing are shared by `run_sync` and `run_async`
+struct Job {
+    args: PairCallsArgs,
+    data_dir: String,
+    languages: Languages,
+    out_file: String,
+    remote_out: Option<(PathBuf, String)>,
+    output_opts: OutputOptions,
+    /// pairs of the first pass and of the changes watched after it
+    seen: Arc<SeenPairs>,
+}
+
+impl Job {
+    fn prepare(mut args: PairCallsArgs) -> Result<Job> {
+        metrics::start();
+        SAMPLES_LEFT.store(args.limit_samples.unwrap_or(usize::MAX), Ordering::Relaxed);
+        let mut data_dir = required_arg(args.data.clone(), "--data");
+        if remote::is_remote(&data_dir) {
+            let local = remote::download(&data_dir, REMOTE_CACHE)?;
+            data_dir = local.to_string_lossy().into_owned();
+        }
+        args.partition.check()?;
+        // a seed only draws the same sample again from pairs generated in the same order
+        if args.seed.is_some()
+            && (args.max_samples.is_some() || args.max_samples_per_repo.is_some())
+        {
+            args.stable_order = true;
+        }
+        let selection = required_arg(args.lang.clone(), "--lang");
+        let languages = Languages::new(&selection, args.query_dir.as_deref())?;
+        // samples read from stdin are written to stdout unless an output file is given
+        let default_out = match data_dir.as_str() {
+            STDIN_PATH => STDOUT_PATH,
+            _ => "output",
+        };
+        let out = args.out.get_or_insert(default_out.to_string()).clone();
+        // remote outputs are written locally and uploaded when complete
+        let (out_file, remote_out) = match remote::is_remote(&out) {
+            true => {
+                let (prefix, name) = remote::split_remote_path(&out)?;
+                let dir = remote::stage_output(&prefix, REMOTE_CACHE)?;
+                (
+                    dir.join(name).to_string_lossy().into_owned(),
+                    Some((dir, prefix)),
+                )
+            }
+            false => (out, None),
+        };
+        args.threads.get_or_insert(num_cpus::get());
+        let format = *args.output.format.get_or_insert(OutputFormat::Jsonl);
+        let output_opts = args.output.to_options();
+        if args.watch {
+            let message = if !Path::new(&data_dir).is_dir() {
+                Some("--watch needs a local --data directory")
+            } else if remote_out.is_some() {
+                Some("--watch cannot write to an object store")
+            } else if !format.is_appendable() {
+                Some("--watch needs an appendable --format: jsonl, jsonl.gz, msgpack or tfrecord")
+            } else if output_opts.shard_size.is_some() {
+                Some("--watch cannot be used with --shard-size")
+            } else if args.max_samples.is_some() {
+                Some("--watch cannot be used with --max-samples")
+            } else if args.limit_files.is_some() || args.limit_samples.is_some() {
+                Some("--watch cannot be used with --limit-files or --limit-samples")
+            } else if args.preview.is_some() {
+                Some("--watch cannot be used with --preview")
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                return Err(Error::Usage(message.to_string()));
+            }
+        }
+        Ok(Job {
+            args,
+            data_dir,
+            languages,
+            out_file,
+            remote_out,
+            output_opts,
+            seen: Arc::default(),
+        })
+    }
+
+    async fn process(&self) -> Result<()> {
+        if let Some(addr) = self.args.metrics_addr {
+            metrics::serve_prometheus(addr).await?;
+        }
+        run_preprocessing(
+            &self.data_dir,
+            &self.out_file,
+            self.languages.clone(),
+            &self.args,
+            &self.output_opts,
+            self.seen.clone(),
+        )
+        .await
+    }
+
+    /// Append the pairs of the files changed in the data directory to the output, until the
+    /// process is interrupted
+    async fn watch(&self) -> Result<()> {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = events_tx.send(event);
+        })?;
+        watcher.watch(Path::new(&self.data_dir), RecursiveMode::Recursive)?;
+        info!("Watching {} for new samples", self.data_dir);
+        // the files read by the first run are read from their current end
+        let opts = WalkArgs {
+            include_vendored: true,
+            ..WalkArgs::default()
+        };
+        let mut offsets: HashMap<PathBuf, u64> =
+            walk_source_files(Path::new(&self.data_dir), &opts)
+                .into_iter()
+                .map(|path| {
+                    let len = fs::metadata(&path).map_or(0, |m| m.len());
+                    (path, len)
+                })
+                .collect();
+        // the output may be written into the watched directory
+        let out_file = fs::canonicalize(&self.out_file).ok();
+        let is_input = |path: &PathBuf| {
+            let is_output = match (&out_file, fs::canonicalize(path)) {
+                (Some(out_file), Ok(path)) => path == *out_file,
+                _ => false,
+            };
+            !is_output && !path.components().any(|c| c.as_os_str() == ".git")
+        };
+        let mut changed = BTreeSet::new();
+        loop {
+            tokio::select! {
+                event = events_rx.recv() => match event {
+                    Some(Ok(event)) => {
+                        if let EventKind::Create(_) | EventKind::Modify(_) = event.kind {
+                            changed.extend(event.paths.into_iter().filter(is_input));
+                        }
+                    }
+                    Some(Err(e)) => return Err(e.into()),
+                    None => break,
+                },
+                // files are read once no change was seen for WATCH_DELAY, so that files being
+                // written are read in one go
+                _ = tokio::time::sleep(WATCH_DELAY), if !changed.is_empty() => {
+                    let paths = std::mem::take(&mut changed);
+                    offsets = self.append_changes(paths, offsets).await?;
+                }
+                _ = shutdown::wait() => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Append the pairs of the samples added to `paths` since `offsets`, returning the new offsets
+    async fn append_changes(
+        &self,
+        paths: BTreeSet<PathBuf>,
+        offsets: HashMap<PathBuf, u64>,
+    ) -> Result<HashMap<PathBuf, u64>> {
+        let (tx, rx) = mpsc::channel(10);
+        let schema_map = self.args.schema_map.clone();
+        let input_th =
+            tokio::task::spawn_blocking(move || read_changes(paths, offsets, schema_map, tx));
+        let file = OutputFileWriter::open_append(&self.out_file, &self.output_opts)?;
+        let languages = self.languages.clone();
+        let pair_opts = self.args.to_pair_options();
+        let threads = self.args.threads.unwrap();
+        let sink = PairSink::File(file);
+        let seen = self.seen.clone();
+        let sink = write_pairs(rx, languages, pair_opts, threads, sink, seen).await?;
+        sink.finish()?;
+        input_th.await?
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.args.preview.is_some() {
+            return Ok(());
+        }
+        let Job {
+            args,
+            out_file,
+            remote_out,
+            output_opts,
+            ..
+        } = self;
+        let (num_files, num_unreadable, num_rows) = (
+            NUM_FILES.load(Ordering::Relaxed),
+            NUM_UNREADABLE.load(Ordering::Relaxed),
+            NUM_ROWS.load(Ordering::Relaxed),
+        );
+        let (num_invalid, num_partial, num_other_language, num_few_uncalled) = (
+            NUM_INVALID.load(Ordering::Relaxed),
+            NUM_PARTIAL.load(Ordering::Relaxed),
+            NUM_OTHER_LANGUAGE.load(Ordering::Relaxed),
+            NUM_FEW_UNCALLED.load(Ordering::Relaxed),
+        );
+        if num_invalid > 0 {
+            info!("Dropped {} rows which are not samples", num_invalid);
+        }
+        if num_partial > 0 {
+            info!("Read {} partially filled samples", num_partial);
+        }
+        if num_other_language > 0 {
+            info!(
+                "Dropped {} samples of no language of --lang {}",
+                num_other_language,
+                args.lang.as_ref().unwrap()
+            );
+        }
+        let num_duplicate_pairs = NUM_DUPLICATE_PAIRS.load(Ordering::Relaxed);
+        if num_duplicate_pairs > 0 {
+            info!("Left out {} pairs already written", num_duplicate_pairs);
+        }
+        let num_repo_capped = NUM_REPO_CAPPED.load(Ordering::Relaxed);
+        if num_repo_capped > 0 {
+            info!(
+                "Left out {} pairs of repos with --max-samples-per-repo {} pairs",
+                num_repo_capped,
+                args.max_samples_per_repo.unwrap()
+            );
+        }
+        let num_inconsistent_tokens = metrics::report().inconsistent_tokens;
+        if num_inconsistent_tokens > 0 {
+            let action = match args.repair_tokens {
+                true => "derived them from the code again",
+                false => "use --repair-tokens to derive them from the code",
+            };
+            info!(
+                "Found {} pairs whose code tokens do not match the masked code, {}",
+                num_inconsistent_tokens, action
+            );
+        }
+        if num_few_uncalled > 0 {
+            let pair_opts = args.to_pair_options();
+            let required = match pair_opts.format {
+                PairFormat::Ranked => pair_opts.distractors,
+                _ => 1,
+            };
+            info!(
+                "Skipped {} calls of callers which call all but less than {} functions",
+                num_few_uncalled, required
+            );
+        }
+        info!(
+            "Read {} rows of {} files, {} files could not be read",
+            num_rows, num_files, num_unreadable
+        );
+        let num_written = NUM_WRITTEN.load(Ordering::Relaxed);
+        match args.to_pair_options().format {
+            PairFormat::Binary => info!(
+                "Wrote {} pairs, {} positive and {} negative",
+                num_written,
+                NUM_POSITIVE.load(Ordering::Relaxed),
+                NUM_NEGATIVE.load(Ordering::Relaxed)
+            ),
+            _ => info!("Wrote {} rows", num_written),
+        }
+        if out_file != STDOUT_PATH {
+            save_config(&args, &format!("{}.config.toml", out_file), &output_opts)?;
+            let report_path = format!("{}.metrics.json", out_file);
+            metrics::write_report(&report_path, &output_opts)?;
+        }
+        // watched runs end with an interruption
+        if shutdown::requested() && !args.watch {
+            if out_file != STDOUT_PATH {
+                let mut completed = FILES_COMPLETED.lock().unwrap().clone();
+                completed.sort();
+                let manifest = format!("{}.interrupted.json", out_file);
+                shutdown::write_manifest(&manifest, &completed, &output_opts)?;
+                info!(
+                    "Interrupted after {} files, see {}",
+                    completed.len(),
+                    manifest
+                );
+            }
+            // a partial output is not uploaded where it would pass for a complete one
+            if let Some((dir, _)) = remote_out {
+                info!(
+                    "The output was not uploaded, it is kept in {}",
+                    dir.display()
+                );
+            }
+            return shutdown::check();
+        }
+        if let Some((dir, prefix)) = remote_out {
+            remote::upload(&dir, &prefix)?;
+        }
+        let max_failure_rate = args.max_failure_rate.unwrap_or(MAX_FAILURE_RATE);
+        check_failure_rate(num_unreadable, num_files, "files", max_failure_rate)?;
+        check_failure_rate(num_invalid, num_rows, "rows", max_failure_rate)
+    }
+}
+
+/// Run on a tokio runtime of its own, blocking the current thread until the output is written
+pub fn run_sync(args: PairCallsArgs) -> Result<()> {
+    let job = Job::prepare(args)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(job.process())?;
+    if job.args.watch {
+        runtime.block_on(job.watch())?;
+    }
+    job.finish()
+}
+
+/// Run on the tokio runtime of the caller
+pub async fn run_async(args: PairCallsArgs) -> Result<()> {
+    // downloads and uploads block on a runtime of their own
+    let job = tokio::task::spawn_blocking(move || Job::prepare(args)).await??;
+    job.process().await?;
+    if job.args.watch {
+        job.watch().await?;
+    }
+    tokio::task::spawn_blocking(move || job.finish()).await?
+}
+
+async fn run_preprocessing(
+    data_dir: &str,
+    out_file: &str,
+    languages: Languages,
+    args: &PairCallsArgs,
+    output_opts: &OutputOptions,
+    seen: Arc<SeenPairs>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel(10);
+    let data_dir = data_dir.to_string();
+    let (schema_map, partition) = (args.schema_map.clone(), args.partition.clone());
+    let (stable_order, limit_files) = (args.stable_order, args.limit_files);
+    let input_th = tokio::spawn(async move {
+        let data_dir = data_dir.as_str();
+        read_input_data(
+            data_dir,
+            schema_map,
+            partition,
+            stable_order,
+            limit_files,
+            tx,
+        )
+        .await
+    });
+    let sink = match args.preview {
+        Some(num_pairs) => PairSink::Preview {
+            num_left: num_pairs,
+            color: preview::use_color(),
+        },
+        None => {
+            let parent = Path::new(out_file).parent();
+            fs::create_dir_all(parent.unwrap())?;
+            let file = OutputFileWriter::create(out_file, output_opts)?;
+            match args.max_samples {
+                Some(max_samples) => {
+                    let reservoir = Reservoir::new(max_samples, seeded_rng(args.seed));
+                    PairSink::Sample(Box::new(reservoir), file)
+                }
+                None => PairSink::File(file),
+            }
+        }
+    };
+    let pair_opts = args.to_pair_options();
+    let sink = write_pairs(rx, languages, pair_opts, args.threads.unwrap(), sink, seen).await?;
+    input_th.await??;
+    sink.finish()?;
+    Ok(())
+}
+
+/// Where the generated pairs go
+enum PairSink {
+    File(OutputFileWriter),
+    /// pairs sampled for --max-samples, written to the file once all pairs are generated
+    Sample(Box<Reservoir<PairRecord, StdRng>>, OutputFileWriter),
+    /// pairs printed for --preview, input stops being read once `num_left` is 0
+    Preview {
+        num_left: usize,
+        color: bool,
+    },
+}
+
+impl PairSink {
+    fn add(&mut self, pairs: Vec<PairRecord>) -> io::Result<()> {
+        match self {
+            PairSink::File(file) => {
+                metrics::add_samples(pairs.len());
+                count_written(&pairs);
+                file.append(&pairs)
+            }
+            PairSink::Sample(reservoir, _) => {
+                metrics::add_samples(pairs.len());
+                pairs.into_iter().for_each(|pair| reservoir.push(pair));
+                Ok(())
+            }
+            PairSink::Preview { num_left, color } => {
+                for pair in pairs.iter().take(*num_left) {
+                    let record = Record::new(pair, None)?;
+                    let title = format!("pair {}", NUM_WRITTEN.load(Ordering::Relaxed) + 1);
+                    print!("{}", preview::format_record(&title, &record, *color));
+                    NUM_WRITTEN.fetch_add(1, Ordering::Relaxed);
+                    *num_left -= 1;
+                }
+                if *num_left == 0 {
+                    SAMPLES_LEFT.store(0, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the pairs not written yet and finish the output file
+    fn finish(self) -> io::Result<()> {
+        match self {
+            PairSink::File(file) => {
+                file.finish()?;
+            }
+            PairSink::Sample(reservoir, mut file) => {
+                let num_pairs = reservoir.num_seen();
+                let pairs = reservoir.into_vec();
+                if pairs.len() < num_pairs {
+                    info!("Sampled {} of {} pairs", pairs.len(), num_pairs);
+                }
+                count_written(&pairs);
+                file.append(&pairs)?;
+                file.finish()?;
+            }
+            PairSink::Preview { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// Send the pairs of the sample groups received from `rx` to `sink`
+async fn write_pairs(
+    mut rx: mpsc::Receiver<Vec<JsonSample>>,
+    languages: Languages,
+    pair_opts: PairOptions,
+    num_threads: usize,
+    sink: PairSink,
+    seen: Arc<SeenPairs>,
+) -> Result<PairSink> {
+    // pairs are sampled in the order their groups are sent, which is the input order under
+    // --stable-order, implied by a seed
+    let in_order = pair_opts.stable_order || matches!(sink, PairSink::Sample(..));
+    // pairs already written are skipped, so which of the pairs of concurrent groups are
+    // written first would depend on timing
+    let num_threads = match pair_opts.stable_order {
+        true => 1,
+        false => num_threads,
+    };
+    let sink = Arc::new(Mutex::new(sink));
+
+    // let mut processing_threads = Vec::new();
+    let rx_stream = async_stream::stream! {
+        loop {
+            let start = Instant::now();
+            let item = rx.recv().await;
+            metrics::add_recv_wait(start.elapsed());
+            match item {
+                Some(item) => yield item,
+                None => break,
+            }
+        }
+    };
+    let generated_samples = rx_stream.map(|sample_group: Vec<JsonSample>| {
+        let repo = sample_group
+            .first()
+            .map(|sample| sample.group().to_string())
+            .unwrap_or_default();
+        let pairs = pair_languages(sample_group, languages.clone(), pair_opts, seen.clone());
+        async move { Ok::<_, Error>((repo, pairs.await?)) }
+    });
+    let add = |(repo, samples): (String, Vec<PairRecord>)| {
+        let sink = sink.clone();
+        let samples = cap_repo_pairs(&repo, samples, &pair_opts);
+        async move {
+            sink.lock().await.add(samples)?;
+            Ok(())
+        }
+    };
+    match in_order {
+        true => {
+            generated_samples
+                .buffered(num_threads)
+                .try_for_each(&add)
+                .await?
+        }
+        false => {
+            generated_samples
+                .buffer_unordered(num_threads)
+                .try_for_each(&add)
+                .await?
+        }
+    }
+    Ok(Arc::try_unwrap(sink).ok().unwrap().into_inner())
+}
+
+/// Caller and callee pairs of a group of samples of any languages of the run, samples are only
+/// paired with samples of the same language
+async fn pair_languages(
+    sample_group: Vec<JsonSample>,
+    languages: Languages,
+    opts: PairOptions,
+    seen: Arc<SeenPairs>,
+) -> Result<Vec<PairRecord>> {
+    let mut groups: BTreeMap<&str, (LanguageSpec, Vec<JsonSample>)> = BTreeMap::new();
+    for sample in sample_group {
+        match languages.of(&sample) {
+            Some(spec) => {
+                let group = groups
+                    .entry(spec.name)
+                    .or_insert_with(|| (spec, Vec::new()));
+                group.1.push(sample);
+            }
+            None => {
+                NUM_OTHER_LANGUAGE.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    let mut pairs: Vec<PairRecord> = Vec::new();
+    for (_, (spec, samples)) in groups {
+        let samples = match opts.anonymize {
+            true => anonymize_group(samples, spec)?,
+            false => samples,
+        };
+        match opts.format {
+            PairFormat::Binary => {
+                let mut binary = pair_samples(samples, spec, opts, &seen).await?;
+                if let Some(subtokenizer) = opts.subtokenizer {
+                    binary = binary
+                        .into_iter()
+                        .map(|pair| pair.subtokenize(subtokenizer))
+                        .collect();
+                }
+                pairs.extend(binary.into_iter().map(PairRecord::Binary));
+            }
+            PairFormat::Ranked => {
+                let ranked = rank_samples(samples, spec, opts).await?;
+                pairs.extend(ranked.into_iter().map(PairRecord::Ranked));
+            }
+            PairFormat::Triplet => {
+                let triplets = triplet_samples(samples, spec, opts).await?;
+                pairs.extend(triplets.into_iter().map(PairRecord::Triplet));
+            }
+            PairFormat::Chain => {
+                let chains = chain_samples(samples, spec, opts).await?;
+                pairs.extend(chains.into_iter().map(PairRecord::Chain));
+            }
+        }
+    }
+    if let Some(layout) = opts.code_layout {
+        pairs = pairs.into_iter().map(|pair| pair.lay_out(layout)).collect();
+    }
+    Ok(pairs)
+}
+
+/// The code and code tokens of a caller with the calls of a callee masked, and where the masks are,
+/// and its comment with the mentions of the callee handled as asked
+struct MaskedCaller {
+    code: String,
+    /// the code before masking
+    original: String,
+    code_tokens: Vec<String>,
+    comm: String,
+    comm_tokens: Vec<String>,
+    /// byte ranges of the masks in `code`
+    spans: Vec<[usize; 2]>,
+    /// indices of the masks in `code_tokens`
+    token_indices: Vec<usize>,
+    /// whether the given code tokens have another number of masks than the code
+    inconsistent_tokens: bool,
+}
+
+/// The calls of a callee in a caller and the mentions of the callee in the comments of the
+/// caller, and the tokens of the caller, as byte ranges in source order
+#[derive(Default)]
+struct CallSites {
+    calls: Vec<Range<usize>>,
+    mentions: Vec<Range<usize>>,
+    tokens: Vec<Range<usize>>,
+}
+
+/// The calls of `callee_name` in `code` as found in its syntax tree, the name in strings, in
+/// comments or in other identifiers is not a call
+fn call_sites(code: &str, callee_name: &str, spec: LanguageSpec) -> CallSites {
+    let mut parser = tree_sitter::Parser::new();
+    // the caller was parsed with the same grammar when its calls were found
+    let tree = match parser.set_language(spec.language) {
+        Ok(()) => parser.parse(code, None),
+        Err(_) => None,
+    };
+    let tree = match tree {
+        Some(tree) => tree,
+        None => return CallSites::default(),
+    };
+    let root = tree.root_node();
+    let re = regex::Regex::new(&format!(r"\b{}\b", regex::escape(callee_name))).unwrap();
+    let mentions = find_comments(root)
+        .into_iter()
+        .flat_map(|comment| {
+            re.find_iter(&code[comment.clone()])
+                .map(|m| comment.start + m.start()..comment.start + m.end())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    CallSites {
+        calls: find_call_sites(&spec, code, root, |name| name == callee_name).unwrap_or_default(),
+        mentions,
+        tokens: get_node_token_ranges(root),
+    }
+}
+
+/// Byte offsets of the code tokens in the code, each searched after the previous one, `None` for
+/// tokens which are not found
+fn token_offsets(code: &str, tokens: &[String]) -> Vec<Option<usize>> {
+    let mut end = 0;
+    tokens
+        .iter()
+        .map(|token| {
+            let offset = code[end..].find(token.as_str())? + end;
+            end = offset + token.len();
+            Some(offset)
+        })
+        .collect()
+}
+
+/// A caller as it is, for negative pairs
+fn unmasked_caller(caller: &JsonSample) -> MaskedCaller {
+    MaskedCaller {
+        code: caller.code.clone(),
+        original: caller.code.clone(),
+        code_tokens: caller.code_tokens.clone().unwrap_or_default(),
+        comm: caller.docstring.clone().unwrap_or_default(),
+        comm_tokens: caller.docstring_tokens.clone().unwrap_or_default(),
+        spans: Vec::new(),
+        token_indices: Vec::new(),
+        inconsistent_tokens: false,
+    }
+}
+
+fn mask_callee(
+    caller: &JsonSample,
+    callee_name: &str,
+    spec: LanguageSpec,
+    opts: PairOptions,
+) -> MaskedCaller {
+    let mentions = opts.comment_mentions;
+    let sites = call_sites(&caller.code, callee_name, spec);
+    // the calls, and the mentions in comments unless they are kept, with their replacements
+    let mut edits: Vec<(Range<usize>, &str, bool)> = sites
+        .calls
+        .into_iter()
+        .map(|call| (call, FUNC_CALL_ID_MASK, true))
+        .collect();
+    let mention_mask = match mentions {
+        CommentMentions::Keep => None,
+        CommentMentions::Mask => Some(FUNC_CALL_ID_MASK),
+        CommentMentions::Drop => Some(""),
+    };
+    if let Some(mask) = mention_mask {
+        edits.extend(
+            sites
+                .mentions
+                .into_iter()
+                .map(|mention| (mention, mask, false)),
+        );
+        edits.sort_by_key(|(range, _, _)| range.start);
+    }
+    let (mut code, mut spans, mut end) = (String::new(), Vec::new(), 0);
+    for (range, mask, is_call) in &edits {
+        code.push_str(&caller.code[end..range.start]);
+        if *is_call {
+            spans.push([code.len(), code.len() + mask.len()]);
+        }
+        code.push_str(mask);
+        end = range.end;
+    }
+    code.push_str(&caller.code[end..]);
+    let tokens = caller.code_tokens.clone().unwrap_or_default();
+    let (mut code_tokens, mut token_indices) = (Vec::new(), Vec::new());
+    for (token, offset) in tokens.iter().zip(token_offsets(&caller.code, &tokens)) {
+        let edit = offset.map(|offset| edits.iter().find(|(range, _, _)| range.start == offset));
+        match edit {
+            Some(Some((_, mask, true))) => {
+                token_indices.push(code_tokens.len());
+                code_tokens.push(mask.to_string());
+            }
+            Some(Some((_, mask, false))) if !mask.is_empty() => code_tokens.push(mask.to_string()),
+            Some(Some(_)) => {}
+            // tokens which differ from the code are only known by their text
+            None if token == callee_name => {
+                token_indices.push(code_tokens.len());
+                code_tokens.push(FUNC_CALL_ID_MASK.to_string());
+            }
+            _ => code_tokens.push(token.clone()),
+        }
+    }
+    let inconsistent_tokens = !tokens.is_empty() && token_indices.len() != spans.len();
+    if inconsistent_tokens && opts.repair_tokens {
+        code_tokens.clear();
+        token_indices.clear();
+        for token in &sites.tokens {
+            let call = edits.iter().find(|(range, _, is_call)| {
+                *is_call && range.start <= token.start && token.end <= range.end
+            });
+            match call {
+                // a call of a qualified name may be several tokens
+                Some((range, _, _)) if range.start == token.start => {
+                    token_indices.push(code_tokens.len());
+                    code_tokens.push(FUNC_CALL_ID_MASK.to_string());
+                }
+                Some(_) => {}
+                None => code_tokens.push(caller.code[token.clone()].to_string()),
+            }
+        }
+    }
+    let mut comm = caller.docstring.clone().unwrap_or_default();
+    let mut comm_tokens = caller.docstring_tokens.clone().unwrap_or_default();
+    if mentions != CommentMentions::Keep {
+        let name = regex::escape(callee_name);
+        let re = regex::Regex::new(&format!(r"\s*\b{}\b", name)).unwrap();
+        comm = re
+            .replace_all(&comm, |caps: &regex::Captures| match mentions {
+                // the whitespace before the mention is kept
+                CommentMentions::Mask => caps[0].replace(callee_name, FUNC_CALL_ID_MASK),
+                _ => String::new(),
+            })
+            .into_owned();
+        comm_tokens = comm_tokens
+            .into_iter()
+            .filter_map(|token| match (token == callee_name, mentions) {
+                (true, CommentMentions::Mask) => Some(FUNC_CALL_ID_MASK.to_string()),
+                (true, _) => None,
+                (false, _) => Some(token),
+            })
+            .collect();
+    }
+    MaskedCaller {
+        code,
+        original: caller.code.clone(),
+        code_tokens,
+        comm,
+        comm_tokens,
+        spans,
+        token_indices,
+        inconsistent_tokens,
+    }
+}
+
+/// The lines of a caller `context` lines around the `index`-th call of `callee_name`, with all
+/// calls of the callee masked, the code tokens on these lines and the span and token index of
+/// that call
+fn mask_call_site(
+    caller: &JsonSample,
+    callee_name: &str,
+    index: usize,
+    context: usize,
+    spec: LanguageSpec,
+    opts: PairOptions,
+) -> MaskedCaller {
+    let masked = mask_callee(caller, callee_name, spec, opts);
+    let [start, end] = masked.spans[index];
+    let line = masked.code[..start].matches('\n').count();
+    let lines: Vec<&str> = masked.code.split_inclusive('\n').collect();
+    let first = line.saturating_sub(context);
+    let last = (line + context + 1).min(lines.len());
+    let offset: usize = lines[..first].iter().map(|line| line.len()).sum();
+    // masks do not span lines, the lines of the original are those of the masked code
+    let original: Vec<&str> = masked.original.split_inclusive('\n').collect();
+    let code = lines[first..last].concat();
+    // tokens not found in the code are taken to be where the token before them is
+    let window = offset..offset + code.len();
+    let mut position = 0;
+    let mut code_tokens = Vec::new();
+    let mut window_indices = Vec::with_capacity(masked.code_tokens.len());
+    let offsets = token_offsets(&masked.code, &masked.code_tokens);
+    for (token, found) in masked.code_tokens.into_iter().zip(offsets) {
+        position = found.unwrap_or(position);
+        match window.contains(&position) {
+            true => {
+                window_indices.push(Some(code_tokens.len()));
+                code_tokens.push(token);
+            }
+            false => window_indices.push(None),
+        }
+    }
+    MaskedCaller {
+        code,
+        original: original[first..last].concat(),
+        code_tokens,
+        comm: masked.comm,
+        comm_tokens: masked.comm_tokens,
+        spans: vec![[start - offset, end - offset]],
+        token_indices: masked
+            .token_indices
+            .get(index)
+            .and_then(|index| window_indices[*index])
+            .into_iter()
+            .collect(),
+        inconsistent_tokens: masked.inconsistent_tokens,
+    }
+}
+
+/// A ranked sample per call of a group of samples from the same repo, with the callee among
+/// `opts.distractors` functions the caller does not call. The distractors of the calls of a
+/// caller rotate through these functions, the calls of callers with fewer are skipped.
+pub async fn rank_samples(
+    sample_group: Vec<JsonSample>,
+    spec: LanguageSpec,
+    opts: PairOptions,
+) -> Result<Vec<RankedCallSample>> {
+    let samples = group_calls(&sample_group, spec, opts)?
+        .into_par_iter()
+        .flat_map_iter(|calls| {
+            let num_uncalled = calls.non_callees().count();
+            if num_uncalled < opts.distractors {
+                NUM_FEW_UNCALLED.fetch_add(calls.callees.len(), Ordering::Relaxed);
+                return Vec::new();
+            }
+            let caller = calls.caller;
+            calls
+                .callees
+                .iter()
+                .enumerate()
+                .map(|(idx, callee)| {
+                    let start = (idx * opts.distractors).checked_rem(num_uncalled);
+                    let mut candidates: Vec<&JsonSample> = calls
+                        .non_callees()
+                        .cycle()
+                        .skip(start.unwrap_or(0))
+                        .take(opts.distractors)
+                        .collect();
+                    let label = match opts.stable_order {
+                        true => {
+                            let key = [caller.code.as_bytes(), callee.code.as_bytes()].concat();
+                            stable_hash(&key) as usize % (candidates.len() + 1)
+                        }
+                        false => rand::thread_rng().gen_range(0..=candidates.len()),
+                    };
+                    candidates.insert(label, callee);
+                    let masked = mask_callee(caller, &callee.func_name, spec, opts);
+                    let caller_metrics = complexity_of(&caller.code, spec, opts);
+                    let (code_lines, code_token_count, doc_token_count) = masked_lengths(&masked);
+                    RankedCallSample {
+                        caller_code: masked.code,
+                        caller_mask_spans: masked.spans,
+                        caller_comm: masked.comm,
+                        caller_code_original: match opts.keep_original {
+                            true => Some(masked.original),
+                            false => None,
+                        },
+                        candidate_codes: candidates.iter().map(|c| c.code.clone()).collect(),
+                        candidate_comms: candidates
+                            .iter()
+                            .map(|c| c.docstring.clone().unwrap_or_default())
+                            .collect(),
+                        label,
+                        language: spec.name.to_string(),
+                        caller_code_lines: code_lines,
+                        caller_code_token_count: code_token_count,
+                        caller_doc_token_count: doc_token_count,
+                        caller_ast: ast_of(&caller.code, spec, opts),
+                        candidate_asts: opts.with_ast.map(|_| {
+                            candidates
+                                .iter()
+                                .map(|c| ast_of(&c.code, spec, opts).unwrap_or_default())
+                                .collect()
+                        }),
+                        caller_complexity: caller_metrics.map(|m| m.cyclomatic),
+                        caller_nesting_depth: caller_metrics.map(|m| m.nesting_depth),
+                        caller_num_params: caller_metrics.map(|m| m.num_params),
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    Ok(samples)
+}
+
+/// A triplet per call of a group of samples from the same repo, with a function the caller does
+/// not call picked like the callee of the negative `binary` pair of the call. The calls of
+/// callers which call all other functions are skipped.
+pub async fn triplet_samples(
+    sample_group: Vec<JsonSample>,
+    spec: LanguageSpec,
+    opts: PairOptions,
+) -> Result<Vec<TripletCallSample>> {
+    let samples = group_calls(&sample_group, spec, opts)?
+        .into_par_iter()
+        .flat_map_iter(|calls| {
+            if calls.non_callees().next().is_none() {
+                NUM_FEW_UNCALLED.fetch_add(calls.callees.len(), Ordering::Relaxed);
+                return Vec::new();
+            }
+            let caller = calls.caller;
+            calls
+                .callees
+                .iter()
+                .zip(calls.non_callees().cycle())
+                .map(|(callee, non_callee)| {
+                    let masked = mask_callee(caller, &callee.func_name, spec, opts);
+                    let anchor_metrics = complexity_of(&caller.code, spec, opts);
+                    let (code_lines, code_token_count, doc_token_count) = masked_lengths(&masked);
+                    TripletCallSample {
+                        anchor_code: masked.code,
+                        anchor_mask_spans: masked.spans,
+                        anchor_comm: masked.comm,
+                        anchor_code_original: match opts.keep_original {
+                            true => Some(masked.original),
+                            false => None,
+                        },
+                        positive_code: callee.code.clone(),
+                        positive_comm: callee.docstring.clone().unwrap_or_default(),
+                        negative_code: non_callee.code.clone(),
+                        negative_comm: non_callee.docstring.clone().unwrap_or_default(),
+                        language: spec.name.to_string(),
+                        anchor_code_lines: code_lines,
+                        anchor_code_token_count: code_token_count,
+                        anchor_doc_token_count: doc_token_count,
+                        anchor_ast: ast_of(&caller.code, spec, opts),
+                        positive_ast: ast_of(&callee.code, spec, opts),
+                        negative_ast: ast_of(&non_callee.code, spec, opts),
+                        anchor_complexity: anchor_metrics.map(|m| m.cyclomatic),
+                        anchor_nesting_depth: anchor_metrics.map(|m| m.nesting_depth),
+                        anchor_num_params: anchor_metrics.map(|m| m.num_params),
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    Ok(samples)
+}
+
+/// A chain per call of a group of samples from the same repo whose callee calls other functions of
+/// the group, one per function the callee calls which is not the caller. The caller is masked
+/// where it calls the callee and the callee where it calls the second callee.
+pub async fn chain_samples(
+    sample_group: Vec<JsonSample>,
+    spec: LanguageSpec,
+    opts: PairOptions,
+) -> Result<Vec<ChainCallSample>> {
+    let calls = group_calls(&sample_group, spec, opts)?;
+    let callees_of: HashMap<&str, &[&JsonSample]> = calls
+        .iter()
+        .map(|calls| (calls.caller.func_name.as_str(), calls.callees.as_slice()))
+        .collect();
+    let samples = calls
+        .par_iter()
+        .flat_map_iter(|calls| {
+            let caller = calls.caller;
+            let mut chains = Vec::new();
+            for callee in &calls.callees {
+                let second_callees = match callees_of.get(callee.func_name.as_str()) {
+                    Some(second_callees) => *second_callees,
+                    None => continue,
+                };
+                for second_callee in second_callees {
+                    if second_callee.func_name == caller.func_name {
+                        continue;
+                    }
+                    let masked = mask_callee(caller, &callee.func_name, spec, opts);
+                    let masked_callee = mask_callee(callee, &second_callee.func_name, spec, opts);
+                    let (code_lines, code_token_count, doc_token_count) = masked_lengths(&masked);
+                    chains.push(ChainCallSample {
+                        caller_code: masked.code,
+                        caller_mask_spans: masked.spans,
+                        caller_comm: masked.comm,
+                        caller_code_original: match opts.keep_original {
+                            true => Some(masked.original),
+                            false => None,
+                        },
+                        callee_code: masked_callee.code,
+                        callee_mask_spans: masked_callee.spans,
+                        callee_comm: masked_callee.comm,
+                        callee_code_original: match opts.keep_original {
+                            true => Some(masked_callee.original),
+                            false => None,
+                        },
+                        second_callee_code: second_callee.code.clone(),
+                        second_callee_comm: second_callee.docstring.clone().unwrap_or_default(),
+                        language: spec.name.to_string(),
+                        caller_code_lines: code_lines,
+                        caller_code_token_count: code_token_count,
+                        caller_doc_token_count: doc_token_count,
+                    });
+                }
+            }
+            chains
+        })
+        .collect();
+    Ok(samples)
+}
+
+/// Caller and callee pairs of a group of samples from the same repo, the code of callers is
+/// masked with `FUNC_CALL_ID_MASK` where the callee is called. Pairs already in `seen` are
+/// skipped.
+pub async fn pair_samples(
+    sample_group: Vec<JsonSample>,
+    spec: LanguageSpec,
+    opts: PairOptions,
+    seen: &SeenPairs,
+) -> Result<Vec<CallJsonSample>> {
+    let samples = process_grouped_samples(&sample_group, spec, opts, seen).await?;
+    let samples: Vec<CallJsonSample> = samples
+        .into_par_iter()
+        .map(|(caller, callee, label, site, (fan_out, fan_in))| {
+            let masked = match (label, site, opts.call_site_context) {
+                (_, Some((callee_name, index)), Some(context)) => {
+                    mask_call_site(&caller, &callee_name, index, context, spec, opts)
+                }
+                (true, _, _) => mask_callee(&caller, &callee.func_name, spec, opts),
+                (false, _, _) => unmasked_caller(&caller),
+            };
+            // positive pairs and, with their caller masked around a call site, negative ones
+            if masked.inconsistent_tokens {
+                metrics::add_inconsistent_tokens();
+            }
+            let caller_metrics = complexity_of(&caller.code, spec, opts);
+            let callee_metrics = complexity_of(&callee.code, spec, opts);
+            let callee_code_tokens = callee.code_tokens.clone().unwrap_or_default();
+            let callee_comm = callee.docstring.clone().unwrap_or_default();
+            let callee_comm_tokens = callee.docstring_tokens.clone().unwrap_or_default();
+            let caller_lengths = masked_lengths(&masked);
+            let callee_lengths = lengths(
+                &callee.code,
+                &callee_code_tokens,
+                &callee_comm,
+                &callee_comm_tokens,
+            );
+            CallJsonSample {
+                caller_code: masked.code,
+                caller_comm: masked.comm,
+                callee_code: callee.code.clone(),
+                callee_comm,
+                label,
+                caller_code_tokens: masked.code_tokens,
+                caller_comm_tokens: masked.comm_tokens,
+                callee_code_tokens,
+                callee_comm_tokens,
+                language: spec.name.to_string(),
+                caller_mask_spans: masked.spans,
+                caller_mask_token_indices: masked.token_indices,
+                caller_code_lines: caller_lengths.0,
+                caller_code_token_count: caller_lengths.1,
+                caller_doc_token_count: caller_lengths.2,
+                callee_code_lines: callee_lengths.0,
+                callee_code_token_count: callee_lengths.1,
+                callee_doc_token_count: callee_lengths.2,
+                caller_fan_out: fan_out,
+                callee_fan_in: fan_in,
+                caller_code_original: match opts.keep_original {
+                    true => Some(masked.original),
+                    false => None,
+                },
+                caller_ast: ast_of(&caller.code, spec, opts),
+                callee_ast: ast_of(&callee.code, spec, opts),
+                caller_complexity: caller_metrics.map(|m| m.cyclomatic),
+                caller_nesting_depth: caller_metrics.map(|m| m.nesting_depth),
+                caller_num_params: caller_metrics.map(|m| m.num_params),
+                callee_complexity: callee_metrics.map(|m| m.cyclomatic),
+                callee_nesting_depth: callee_metrics.map(|m| m.nesting_depth),
+                callee_num_params: callee_metrics.map(|m| m.num_params),
+            }
+        })
+        .collect();
+    Ok(samples)
+}
+
+/// Lines of the code of a masked caller and the numbers of tokens of its code and comment
+fn masked_lengths(masked: &MaskedCaller) -> (usize, usize, usize) {
+    lengths(
+        &masked.code,
+        &masked.code_tokens,
+        &masked.comm,
+        &masked.comm_tokens,
+    )
+}
+
+/// The structural metrics of the function `code` if --complexity asks for them
+fn complexity_of(code: &str, spec: LanguageSpec, opts: PairOptions) -> Option<Complexity> {
+    match opts.complexity {
+        true => function_complexity(code, &spec),
+        false => None,
+    }
+}
+
+/// The syntax tree of the function `code` if --with-ast asks for it
+fn ast_of(code: &str, spec: LanguageSpec, opts: PairOptions) -> Option<String> {
+    opts.with_ast
+        .map(|format| function_ast(code, &spec, format))
+}
+
+/// Size of the buffer jsonl inputs are read through
+const READ_BUFFER_SIZE: usize = 1 << 20;
+/// Attempts at reading an input file whose reads fail with a transient error
+const READ_ATTEMPTS: u32 = 3;
+
+/// Whether a failed read may succeed when tried again, e.g. of a network file system
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Send a sample group to the workers. The channel only fails once the workers stopped, which
+/// no retry recovers from, so the run is aborted naming the group which was lost.
+fn send_group(tx: &Sender<Vec<JsonSample>>, group: Vec<JsonSample>) -> Result<()> {
+    debug!("sent {} samples", group.len());
+    let (num_samples, repo) = (group.len(), group[0].group().to_string());
+    metrics::timed_send(|| tx.blocking_send(group)).map_err(|_| {
+        Error::Channel(format!(
+            "pairing stopped before {} samples of {} were paired",
+            num_samples, repo
+        ))
+    })
+}
+
+/// Name of a function without the classes or modules qualifying it, e.g. `parse` of
+/// `Parser.parse`, which is the name its calls use
+pub(crate) fn unqualified_name(func_name: &str) -> &str {
+    func_name.rsplit('.').next().unwrap()
+}
+
+/// Parse the `JsonSample` lines of `reader`, mapped by `schema_map` if given, and send them in
+/// groups of consecutive samples of the same repo. Samples without a language are of `language`.
+/// The first `lines_sent` lines are skipped, which were sent by a previous attempt, and
+/// `lines_sent` is advanced as groups are sent.
+fn send_sample_groups(
+    reader: &mut dyn Read,
+    language: Option<&str>,
+    schema_map: Option<&SchemaMap>,
+    tx: &Sender<Vec<JsonSample>>,
+    bar: Option<&Bar>,
+    lines_sent: &mut usize,
+) -> Result<()> {
+    let num_skipped = *lines_sent;
+    let mut num_lines = 0;
+    let mut sample_group_identifier = String::new();
+    let mut cur_group_samples = Vec::new();
+    // lines are read into one buffer and parsed as bytes, which spares an allocation and a UTF-8
+    // check of each line of corpora of millions of lines
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, reader);
+    let mut line = Vec::new();
+    metrics::add_file(0);
+    loop {
+        // the samples of a group cut short by an interruption are not sent
+        if shutdown::requested() {
+            return Ok(());
+        }
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        num_lines += 1;
+        if num_lines <= num_skipped {
+            continue;
+        }
+        metrics::add_bytes(line.len() as u64);
+        if let Some(bar) = bar {
+            PROGRESS.blocking_lock().inc_and_draw(bar, 1);
+        }
+        let content = line.trim_ascii_end();
+        if content.is_empty() {
+            continue;
+        }
+        NUM_ROWS.fetch_add(1, Ordering::Relaxed);
+        let parsed = match schema_map {
+            Some(schema_map) => serde_json::from_slice(content)
+                .and_then(|row| serde_json::from_value(schema_map.apply(row))),
+            None => serde_json::from_slice::<JsonSample>(content),
+        };
+        let mut json_sample = match parsed {
+            Ok(json_sample) => json_sample,
+            Err(e) => {
+                debug!("invalid sample: {}", e);
+                NUM_INVALID.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        // the group read so far is sent once the limit is reached
+        let counted = SAMPLES_LEFT.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            left.checked_sub(1)
+        });
+        if counted.is_err() {
+            break;
+        }
+        if json_sample.is_partial() {
+            NUM_PARTIAL.fetch_add(1, Ordering::Relaxed);
+        }
+        json_sample.func_name = unqualified_name(&json_sample.func_name).to_string();
+        if json_sample.language.is_none() {
+            json_sample.language = language.map(str::to_string);
+        }
+        let group = json_sample.group();
+        if group != sample_group_identifier && !cur_group_samples.is_empty() {
+            send_group(tx, cur_group_samples)?;
+            // the lines before this one are in groups sent or are not samples
+            *lines_sent = num_lines - 1;
+            // reset
+            cur_group_samples = Vec::new();
+            sample_group_identifier = group.to_string();
+        }
+        cur_group_samples.push(json_sample);
+    }
+    if !cur_group_samples.is_empty() {
+        send_group(tx, cur_group_samples)?;
+    }
+    *lines_sent = num_lines;
+    Ok(())
+}
+
+/// Send the sample groups of the complete lines of a jsonl file after `offset`, returning the
+/// offset of the first line which is not complete yet
+fn send_new_lines(
+    path: &Path,
+    offset: u64,
+    schema_map: Option<&SchemaMap>,
+    tx: &Sender<Vec<JsonSample>>,
+) -> Result<u64> {
+    let mut file = fs::File::open(path)?;
+    // a file which shrank was rewritten and is read again
+    let offset = match file.metadata()?.len() < offset {
+        true => 0,
+        false => offset,
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let end = bytes
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |idx| idx + 1);
+    let language = language_of_path(path);
+    send_sample_groups(&mut &bytes[..end], language, schema_map, tx, None, &mut 0)?;
+    Ok(offset + end as u64)
+}
+
+/// Send the sample groups added to `paths` since `offsets`, returning the new offsets. Archives
+/// cannot be resumed, they are read once when they first change.
+fn read_changes(
+    paths: BTreeSet<PathBuf>,
+    mut offsets: HashMap<PathBuf, u64>,
+    schema_map: Option<SchemaMap>,
+    tx: Sender<Vec<JsonSample>>,
+) -> Result<HashMap<PathBuf, u64>> {
+    for path in paths.into_iter().filter(|path| path.is_file()) {
+        if is_archive(&path) {
+            if offsets.insert(path.clone(), 0).is_none() {
+                send_file_groups(&path, schema_map.as_ref(), &tx, None)?;
+            }
+            continue;
+        }
+        let offset = offsets.get(&path).copied().unwrap_or(0);
+        if offset == 0 {
+            NUM_FILES.fetch_add(1, Ordering::Relaxed);
+        }
+        match send_new_lines(&path, offset, schema_map.as_ref(), &tx) {
+            Ok(offset) => {
+                offsets.insert(path, offset);
+            }
+            Err(Error::Io(e)) => {
+                NUM_UNREADABLE.fetch_add(1, Ordering::Relaxed);
+                error!("cannot read {}: {}", path.display(), e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(offsets)
+}
+
+/// Send the sample groups of a file or archive. Reads failing with a transient error are tried
+/// again with exponential backoff from the first line not sent yet, a file which cannot be read
+/// is skipped.
+fn send_file_groups(
+    path: &Path,
+    schema_map: Option<&SchemaMap>,
+    tx: &Sender<Vec<JsonSample>>,
+    bar: Option<&Bar>,
+) -> Result<()> {
+    let language = language_of_path(path);
+    NUM_FILES.fetch_add(1, Ordering::Relaxed);
+    // lines sent of the file, or of each member of an archive
+    let mut lines_sent: HashMap<String, usize> = HashMap::new();
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let mut sent = Ok(());
+        let result = visit_input_file(path, |name, reader| {
+            let lines_sent = lines_sent.entry(name.to_string()).or_insert(0);
+            send_sample_groups(reader, language, schema_map, tx, bar, lines_sent).map_err(|e| {
+                match e {
+                    Error::Io(e) => e,
+                    e => {
+                        sent = Err(e);
+                        io::ErrorKind::BrokenPipe.into()
+                    }
+                }
+            })
+        });
+        sent?;
+        match result {
+            Ok(()) => {
+                if !shutdown::requested() {
+                    let path = path.to_string_lossy().into_owned();
+                    FILES_COMPLETED.lock().unwrap().push(path);
+                }
+                return Ok(());
+            }
+            Err(e) if is_transient(&e) && attempt < READ_ATTEMPTS => {
+                warn!(
+                    "cannot read {}, retrying in {}s: {}",
+                    path.display(),
+                    delay.as_secs(),
+                    e
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                NUM_UNREADABLE.fetch_add(1, Ordering::Relaxed);
+                error!("cannot read {}: {}", path.display(), e);
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn read_input_data(
+    data_dir: &str,
+    schema_map: Option<SchemaMap>,
+    partition: PartitionArgs,
+    stable_order: bool,
+    limit_files: Option<usize>,
+    tx: Sender<Vec<JsonSample>>,
+) -> Result<()> {
+    if data_dir == STDIN_PATH {
+        return tokio::task::spawn_blocking(move || {
+            let stdin = &mut std::io::stdin().lock();
+            send_sample_groups(stdin, None, schema_map.as_ref(), &tx, None, &mut 0)
+        })
+        .await?;
+    }
+    // URLs are streamed once, without counting their lines for the progress bar first
+    if is_url(data_dir) {
+        let url = PathBuf::from(data_dir);
+        return tokio::task::spawn_blocking(move || {
+            send_file_groups(&url, schema_map.as_ref(), &tx, None)
+        })
+        .await?;
+    }
+    // data_dir is a file, a directory or a glob pattern matching either
+    let roots = match is_glob(data_dir) {
+        true => expand_glob(data_dir)?,
+        false => vec![PathBuf::from(data_dir)],
+    };
+    let mut files: Vec<PathBuf> = roots
+        .iter()
+        // jsonl corpora are not source trees, so no directories are vendored
+        .flat_map(|root| {
+            let opts = WalkArgs {
+                include_vendored: true,
+                ..WalkArgs::default()
+            };
+            walk_source_files(root, &opts)
+        })
+        .filter(|path| partition.contains(path))
+        .collect();
+    if stable_order {
+        files.sort();
+    }
+    files.truncate(limit_files.unwrap_or(usize::MAX));
+
+    let files_bar = PROGRESS.lock().await.bar(files.len(), "Files");
+    let mut input_threads = Vec::new();
+    for (idx, entry) in files.into_iter().enumerate() {
+        if shutdown::requested() {
+            break;
+        }
+        let file_path = entry;
+        // info!("{}/{} {}", idx + 1, len, file_path.to_str().unwrap());
+        if file_path.is_file() {
+            let tx = tx.clone();
+            let file_path = file_path.clone();
+            let schema_map = schema_map.clone();
+            // archives are read with blocking decoders, so all files are read on blocking threads
+            let input_data_thread = tokio::task::spawn_blocking(move || {
+                // files queued before --limit-samples was reached are not read
+                if SAMPLES_LEFT.load(Ordering::Relaxed) == 0 {
+                    return Ok(());
+                }
+                let mut num_lines = 0;
+                let counted = visit_input_file(&file_path, |_, reader| {
+                    // a read error is returned rather than counted, a decoder can repeat it forever
+                    for line in BufReader::new(reader).lines() {
+                        line?;
+                        num_lines += 1;
+                    }
+                    Ok(())
+                });
+                if let Err(e) = counted {
+                    NUM_FILES.fetch_add(1, Ordering::Relaxed);
+                    NUM_UNREADABLE.fetch_add(1, Ordering::Relaxed);
+                    error!("cannot read {}: {}", file_path.display(), e);
+                    return Ok(());
+                }
+                let bar = PROGRESS.blocking_lock().bar(
+                    num_lines,
+                    format!("[IN] #{} {}", idx, file_path.to_str().unwrap()),
+                );
+                send_file_groups(&file_path, schema_map.as_ref(), &tx, Some(&bar))
+            });
+            match stable_order {
+                // the groups of a file are sent once those of the previous files are
+                true => input_data_thread.await??,
+                false => input_threads.push(input_data_thread),
+            }
+        }
+        PROGRESS.lock().await.inc_and_draw(&files_bar, 1);
+    }
+
+    for input_thread in input_threads {
+        input_thread.await??;
+    }
+    Ok(())
+}
+
+/// A sample of a group with the functions of the group it calls and those it reaches
+struct CallerCalls<'a> {
+    caller: &'a JsonSample,
+    callees: Vec<&'a JsonSample>,
+    /// names of the functions the caller reaches, which are not negatives
+    reachable: HashSet<&'a str>,
+    /// the functions of the group in name order, shared by all its callers
+    funcs: Arc<Vec<&'a JsonSample>>,
+    /// whether functions of other files than the caller's come first
+    cross_file: bool,
+    /// number of functions of the group calling each function, shared by all its callers
+    fan_in: Arc<HashMap<&'a str, usize>>,
+}
+
+impl<'a> CallerCalls<'a> {
+    /// The functions of the group the caller does not reach, in the order of the negative
+    /// strategy. They are only looked up as far as they are taken, rather than collected per
+    /// caller of large groups.
+    fn non_callees(&self) -> impl Iterator<Item = &'a JsonSample> + Clone + '_ {
+        let caller = self.caller;
+        let is_uncalled = move |func: &&'a JsonSample| {
+            func.func_name != caller.func_name && !self.reachable.contains(func.func_name.as_str())
+        };
+        let comes_first = move |func: &&'a JsonSample| !self.cross_file || func.path != caller.path;
+        let uncalled = self.funcs.iter().copied().filter(is_uncalled);
+        // the functions of other files stay in name order
+        uncalled
+            .clone()
+            .filter(comes_first)
+            .chain(uncalled.filter(move |func| !comes_first(func)))
+    }
+
+    /// The numbers of functions of the group the caller calls and which call `func`
+    fn fans(&self, func: &JsonSample) -> (usize, usize) {
+        let fan_in = self.fan_in.get(func.func_name.as_str());
+        (self.callees.len(), fan_in.copied().unwrap_or(0))
+    }
+}
+
+fn group_calls<'a>(
+    sample_group: &'a [JsonSample],
+    spec: LanguageSpec,
+    opts: PairOptions,
+) -> Result<Vec<CallerCalls<'a>>> {
+    let funcs = sample_group
+        .iter()
+        .map(|e| (e.func_name.as_str(), e))
+        .collect::<BTreeMap<&str, &JsonSample>>();
+    let index: Arc<Vec<&JsonSample>> = Arc::new(funcs.values().copied().collect());
+    // the call graph of the group, the other functions of the group each sample calls
+    let called: Vec<HashSet<&str>> = sample_group
+        .par_iter()
+        .map(|sample| {
+            let code = &sample.code;
+            let mut parser = tree_sitter::Parser::new();
+            parser.set_language(spec.language)?;
+            let root = parser.parse(code, None).ok_or_else(|| {
+                metrics::add_parse_failure();
+                Error::Parse {
+                    path: sample.func_name.clone(),
+                }
+            })?;
+            let called = find_called_functions(&spec, code, root.root_node(), |func_name| {
+                func_name != sample.func_name && funcs.contains_key(func_name)
+            })?;
+            // the names as borrowed from the group
+            Ok(called
+                .iter()
+                .filter_map(|func_name| funcs.get_key_value(func_name))
+                .map(|(func_name, _)| *func_name)
+                .collect())
+        })
+        .collect::<Result<_>>()?;
+    let mut fan_in: HashMap<&str, usize> = HashMap::new();
+    for func_name in called.iter().flatten() {
+        *fan_in.entry(func_name).or_default() += 1;
+    }
+    let fan_in = Arc::new(fan_in);
+    let calls_of: HashMap<&str, &HashSet<&str>> = sample_group
+        .iter()
+        .map(|sample| sample.func_name.as_str())
+        .zip(&called)
+        .collect();
+    let calls = sample_group
+        .par_iter()
+        .zip(&called)
+        .map(|(sample, callees)| {
+            // functions the caller reaches through other calls are not negatives either
+            let mut reachable: HashSet<&str> = callees.clone();
+            let mut frontier: Vec<&str> = reachable.iter().copied().collect();
+            let mut depth = 1;
+            while !frontier.is_empty() && depth < opts.exclusion_depth.unwrap_or(usize::MAX) {
+                frontier = frontier
+                    .iter()
+                    .filter_map(|func_name| calls_of.get(func_name))
+                    .flat_map(|callees| callees.iter().copied())
+                    .filter(|func_name| reachable.insert(func_name))
+                    .collect();
+                depth += 1;
+            }
+            let cross_file = opts.negative_strategy == NegativeStrategy::CrossFile;
+            // in name order, the order of a hash set changes from run to run
+            let mut callees: Vec<&JsonSample> =
+                callees.iter().map(|callee| funcs[callee]).collect();
+            callees.sort_by(|a, b| a.func_name.cmp(&b.func_name));
+            CallerCalls {
+                caller: sample,
+                callees,
+                reachable,
+                funcs: index.clone(),
+                cross_file: cross_file && sample.path.is_some(),
+                fan_in: fan_in.clone(),
+            }
+        })
+        .collect();
+    Ok(calls)
+}
+
+/// The caller and callee of a pair, its label, for pairs per call site the callee masked in the
+/// caller and the index of the call among its calls, and the fan-out of the caller and fan-in of
+/// the callee
+type Pair = (
+    JsonSample,
+    JsonSample,
+    bool,
+    Option<(String, usize)>,
+    (usize, usize),
+);
+
+async fn process_grouped_samples(
+    sample_group: &[JsonSample],
+    spec: LanguageSpec,
+    opts: PairOptions,
+    seen: &SeenPairs,
+) -> Result<Vec<Pair>> {
+    // a single split runs the callers in order, which decides which duplicate pairs are skipped
+    let min_len = match opts.stable_order {
+        true => usize::MAX,
+        false => 1,
+    };
+    let res: Vec<Vec<Pair>> = group_calls(sample_group, spec, opts)?
+        .into_par_iter()
+        .with_min_len(min_len)
+        .map(|calls| {
+            let sample = calls.caller;
+            let mut all_samples = Vec::new();
+            // generate a (caller, callee) pair, or one per call site
+            for callee in &calls.callees {
+                if !seen.is_new_pair(sample, callee) {
+                    continue;
+                }
+                let num_sites = match opts.call_site_context {
+                    Some(_) => call_sites(&sample.code, &callee.func_name, spec)
+                        .calls
+                        .len(),
+                    None => 0,
+                };
+                let fans = calls.fans(callee);
+                if num_sites == 0 {
+                    all_samples.push((sample.clone(), (*callee).clone(), true, None, fans));
+                }
+                for index in 0..num_sites {
+                    let site = Some((callee.func_name.clone(), index));
+                    all_samples.push((sample.clone(), (*callee).clone(), true, site, fans));
+                }
+            }
+            // generate (caller, non-callee) pairs with distinct non-callees, pairs per call site
+            // take the call sites of the positive pairs in turn
+            let sites: Vec<Option<(String, usize)>> =
+                all_samples.iter().map(|pair| pair.3.clone()).collect();
+            let num_negative = all_samples.len() * opts.negatives_per_positive;
+            let non_callees = calls
+                .non_callees()
+                .filter(|non_callee| seen.is_new_pair(sample, non_callee))
+                .take(num_negative);
+            for (idx, non_callee) in non_callees.enumerate() {
+                let site = sites[idx % sites.len()].clone();
+                let fans = calls.fans(non_callee);
+                all_samples.push((sample.clone(), non_callee.clone(), false, site, fans));
+            }
+            all_samples
+        })
+        .collect();
+
+    Ok(res.into_iter().flatten().collect::<Vec<Pair>>())
+}
@@ -0,0 +1,193 @@
+//! Pair the functions of repositories which port a library to several languages with their
+//! ports, as translation pairs. A function and a function of another language are ports when
+//! their names are the same up to case and underscores, e.g. `parse_header` and `parseHeader`,
+//! and their documentation is similar enough.
+
+use crate::analysis::{documentation, named_functions, LanguageSpec};
+use crate::detect::detect_language;
+use crate::error::{Error, Result};
+use crate::input::{decode_source, is_generated, walk_source_files, WalkArgs, MAX_FILE_SIZE};
+use crate::writer::OutputFileWriter;
+use crate::{node_text, required_arg, OutputArgs, ParallelSample};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use tree_sitter::Parser;
+
+/// Default of --min-similarity
+const MIN_SIMILARITY: f64 = 0.5;
+/// Names shorter than this, such as `get` or `run`, are too common to tell ports apart
+const MIN_NAME_LEN: usize = 4;
+
+/// Pair the functions of a repository with their ports in other languages
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParallelArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Directory of the repository, whose subtrees hold the ports
+    pub input: Option<String>,
+    /// Output file
+    pub out: Option<String>,
+    /// Lowest Jaccard similarity of the words of the documentation of two functions which are
+    /// paired, 0 pairs functions by their names alone [default: 0.5]
+    #[clap(long)]
+    pub min_similarity: Option<f64>,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub walk: WalkArgs,
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub output: OutputArgs,
+}
+
+/// A function of the repository
+struct Function {
+    language: &'static str,
+    path: String,
+    name: String,
+    /// lowercase words of its documentation
+    doc_words: HashSet<String>,
+    code: String,
+}
+
+/// `name` without case and underscores, the same for the names of a function in every language
+fn name_key(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Jaccard similarity of two sets of words, 0 if both are empty
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    match union {
+        0 => 0.0,
+        union => a.intersection(b).count() as f64 / union as f64,
+    }
+}
+
+/// The functions of a source file, none if its language is not detected or has no grammar
+fn file_functions(path: &Path, walk: &WalkArgs) -> Result<Vec<Function>> {
+    let max_file_size = match walk.max_file_size.unwrap_or(MAX_FILE_SIZE) {
+        0 => u64::MAX,
+        size => size,
+    };
+    if fs::metadata(path)?.len() > max_file_size {
+        return Ok(Vec::new());
+    }
+    let (code, encoding) = decode_source(fs::read(path)?);
+    if (encoding.is_some() && walk.skip_non_utf8) || (!walk.keep_generated && is_generated(&code)) {
+        return Ok(Vec::new());
+    }
+    let spec = match detect_language(Some(path), &code).and_then(LanguageSpec::by_name) {
+        Some(spec) => spec,
+        None => return Ok(Vec::new()),
+    };
+    let mut parser = Parser::new();
+    parser.set_language(spec.language)?;
+    let path = path.to_string_lossy().into_owned();
+    let tree = parser
+        .parse(&code, None)
+        .ok_or_else(|| Error::Parse { path: path.clone() })?;
+    Ok(named_functions(&spec, tree.root_node(), &code)
+        .into_iter()
+        .filter(|(name, _)| name.len() >= MIN_NAME_LEN)
+        .map(|(name, node)| Function {
+            language: spec.name,
+            path: path.clone(),
+            name: name.to_string(),
+            doc_words: words(&documentation(&spec, node, &code)),
+            code: node_text(node, &code).to_string(),
+        })
+        .collect())
+}
+
+/// For each name and pair of languages, the most similar pair of functions of the two languages
+/// with the name, if they are similar enough
+fn pair_ports(functions: &[Function], min_similarity: f64) -> Vec<ParallelSample> {
+    let mut by_name: BTreeMap<String, Vec<&Function>> = BTreeMap::new();
+    for function in functions {
+        by_name
+            .entry(name_key(&function.name))
+            .or_default()
+            .push(function);
+    }
+    let mut samples = Vec::new();
+    for ports in by_name.values() {
+        // the best pair by the languages it pairs, in alphabetical order
+        let mut best: BTreeMap<(&str, &str), (f64, &Function, &Function)> = BTreeMap::new();
+        for (i, a) in ports.iter().enumerate() {
+            for b in &ports[i + 1..] {
+                let (a, b) = match a.language.cmp(b.language) {
+                    Ordering::Equal => continue,
+                    Ordering::Less => (*a, *b),
+                    Ordering::Greater => (*b, *a),
+                };
+                let score = similarity(&a.doc_words, &b.doc_words);
+                if score < min_similarity {
+                    continue;
+                }
+                let pair = best
+                    .entry((a.language, b.language))
+                    .or_insert((score, a, b));
+                if score > pair.0 {
+                    *pair = (score, a, b);
+                }
+            }
+        }
+        samples.extend(best.into_values().map(|(score, a, b)| ParallelSample {
+            language_a: a.language.to_string(),
+            path_a: a.path.clone(),
+            code_a: a.code.clone(),
+            language_b: b.language.to_string(),
+            path_b: b.path.clone(),
+            code_b: b.code.clone(),
+            func_name: a.name.clone(),
+            doc_similarity: score,
+        }));
+    }
+    samples
+}
+
+pub fn run(args: ParallelArgs) -> Result<()> {
+    let input = required_arg(args.input, "<INPUT>");
+    let out = required_arg(args.out, "<OUT>");
+    let min_similarity = args.min_similarity.unwrap_or(MIN_SIMILARITY);
+    let walk = &args.walk;
+    let paths = walk_source_files(Path::new(&input), walk);
+    let functions: Vec<Function> = paths
+        .par_iter()
+        .map(|path| {
+            file_functions(path, walk).unwrap_or_else(|e| {
+                eprintln!("Skipping {}: {}", path.display(), e);
+                Vec::new()
+            })
+        })
+        .flatten()
+        .collect();
+    let samples = pair_ports(&functions, min_similarity);
+    let mut writer = OutputFileWriter::create(&out, &args.output.to_options())?;
+    writer.append(&samples)?;
+    writer.finish()?;
+    println!(
+        "Found {} pairs among {} functions of {} files",
+        samples.len(),
+        functions.len(),
+        paths.len()
+    );
+    Ok(())
+}
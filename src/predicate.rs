@@ -0,0 +1,368 @@
+//! A small predicate language over the fields of jsonl records, e.g.
+//! `label == true && len(callee_code_tokens) < 256`.
+//!
+//! Supported are `||`, `&&`, `!`, the comparisons `== != < <= > >=`, parentheses, string,
+//! number, boolean and `null` literals, field names (`a.b` for nested objects, `field0` for
+//! tuple records) and the functions `len(x)` and `contains(haystack, needle)`.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Field(Vec<String>),
+    Call(String, Vec<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(&'static str, Box<Expr>, Box<Expr>),
+}
+
+const OPERATORS: [&str; 9] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c == '(' || c == ')' || c == ',' {
+            tokens.push(match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                _ => Token::Comma,
+            });
+            rest = &rest[1..];
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| format!("unterminated string in `{}`", input))?;
+            tokens.push(Token::Literal(Value::String(rest[1..end + 1].to_string())));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_digit() || c == '-' {
+            // a sign only continues a number right after its exponent, as in `1e-5`
+            let bytes = rest.as_bytes();
+            let is_exponent = |idx: usize| matches!(bytes[idx], b'e' | b'E');
+            let mut end = 1;
+            while end < bytes.len()
+                && (bytes[end].is_ascii_digit()
+                    || bytes[end] == b'.'
+                    || is_exponent(end)
+                    || (matches!(bytes[end], b'-' | b'+') && is_exponent(end - 1)))
+            {
+                end += 1;
+            }
+            if end == 1 && c == '-' {
+                return Err(format!("expected a number after `-` in `{}`", input));
+            }
+            let number = serde_json::Number::from_str(&rest[..end])
+                .map_err(|_| format!("invalid number `{}`", &rest[..end]))?;
+            tokens.push(Token::Literal(Value::Number(number)));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(match &rest[..end] {
+                "true" => Token::Literal(Value::Bool(true)),
+                "false" => Token::Literal(Value::Bool(false)),
+                "null" => Token::Literal(Value::Null),
+                ident => Token::Ident(ident.to_string()),
+            });
+            rest = &rest[end..];
+        } else {
+            return Err(format!("unexpected character `{}` in `{}`", c, input));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, lowest precedence first
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(format!("expected {:?}, found {:?}", expected, token)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.next();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.not()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.next();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Op("!")) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let lhs = self.term()?;
+        match self.peek() {
+            Some(Token::Op(op)) if !matches!(*op, "&&" | "||" | "!") => {
+                let op = *op;
+                self.next();
+                Ok(Expr::Compare(op, Box::new(lhs), Box::new(self.term()?)))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                self.next();
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::RParen) {
+                    args.push(self.or()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.next();
+                        args.push(self.or()?);
+                    }
+                }
+                self.expect(Token::RParen)?;
+                let arity = match name.as_str() {
+                    "len" => 1,
+                    "contains" => 2,
+                    _ => return Err(format!("unknown function `{}`", name)),
+                };
+                if args.len() != arity {
+                    return Err(format!("`{}` takes {} argument(s)", name, arity));
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::Ident(name)) => Ok(Expr::Field(
+                name.split('.').map(|part| part.to_string()).collect(),
+            )),
+            token => Err(format!("unexpected {:?}", token)),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ExprParser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?} in `{}`", token, s)),
+        }
+    }
+}
+
+/// Field of a record, `field{i}` selects the i-th element of tuple records
+fn lookup<'a>(record: &'a Value, name: &str) -> Option<&'a Value> {
+    match record {
+        Value::Object(map) => map.get(name),
+        Value::Array(values) => values.get(name.strip_prefix("field")?.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+fn as_bool(value: &Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("expected a boolean, found {}", value))
+}
+
+impl Expr {
+    /// Value of the expression for a record, fields missing in the record are `null`
+    pub fn eval(&self, record: &Value) -> Result<Value, String> {
+        Ok(match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(path) => path
+                .iter()
+                .try_fold(record, |value, name| lookup(value, name))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Expr::Call(name, args) => {
+                let args: Vec<Value> = args
+                    .iter()
+                    .map(|arg| arg.eval(record))
+                    .collect::<Result<_, _>>()?;
+                match (name.as_str(), &args[..]) {
+                    ("len", [Value::String(s)]) => Value::from(s.chars().count()),
+                    ("len", [Value::Array(items)]) => Value::from(items.len()),
+                    ("len", [Value::Object(map)]) => Value::from(map.len()),
+                    ("contains", [Value::String(s), Value::String(needle)]) => {
+                        Value::Bool(s.contains(needle.as_str()))
+                    }
+                    ("contains", [Value::Array(items), needle]) => {
+                        Value::Bool(items.contains(needle))
+                    }
+                    _ => return Err(format!("invalid arguments to `{}`: {:?}", name, args)),
+                }
+            }
+            Expr::Not(expr) => Value::Bool(!as_bool(&expr.eval(record)?)?),
+            Expr::And(lhs, rhs) => {
+                Value::Bool(as_bool(&lhs.eval(record)?)? && as_bool(&rhs.eval(record)?)?)
+            }
+            Expr::Or(lhs, rhs) => {
+                Value::Bool(as_bool(&lhs.eval(record)?)? || as_bool(&rhs.eval(record)?)?)
+            }
+            Expr::Compare(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(record)?, rhs.eval(record)?);
+                let ordering = match (&lhs, &rhs) {
+                    (Value::Number(a), Value::Number(b)) => {
+                        a.as_f64().unwrap().partial_cmp(&b.as_f64().unwrap())
+                    }
+                    (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                    _ if matches!(*op, "==" | "!=") => None,
+                    _ => return Err(format!("cannot compare {} {} {}", lhs, op, rhs)),
+                };
+                Value::Bool(match *op {
+                    "==" => ordering.map_or(lhs == rhs, |o| o == Ordering::Equal),
+                    "!=" => ordering.map_or(lhs != rhs, |o| o != Ordering::Equal),
+                    "<" => ordering == Some(Ordering::Less),
+                    "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    ">" => ordering == Some(Ordering::Greater),
+                    _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                })
+            }
+        })
+    }
+
+    /// Whether a record satisfies the predicate
+    pub fn matches(&self, record: &Value) -> Result<bool, String> {
+        as_bool(&self.eval(record)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn eval(predicate: &str, record: &Value) -> Result<Value, String> {
+        predicate.parse::<Expr>()?.eval(record)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let record = json!({"a": true, "b": false, "c": false});
+        assert_eq!(eval("a || b && c", &record), Ok(json!(true)));
+        assert_eq!(eval("(a || b) && c", &record), Ok(json!(false)));
+    }
+
+    #[test]
+    fn not_applies_to_the_comparison_it_precedes() {
+        let record = json!({"label": true, "n": 3});
+        assert_eq!(eval("!label || n > 2", &record), Ok(json!(true)));
+        assert_eq!(eval("!(n > 2)", &record), Ok(json!(false)));
+    }
+
+    #[test]
+    fn not_equal_is_not_negation() {
+        let record = json!({"lang": "python"});
+        assert_eq!(eval("lang != 'go'", &record), Ok(json!(true)));
+        assert_eq!(eval("!(lang == 'go')", &record), Ok(json!(true)));
+        assert_eq!(eval("!(lang != 'go')", &record), Ok(json!(false)));
+    }
+
+    #[test]
+    fn and_or_short_circuit() {
+        let record = json!({"docstring": null});
+        assert_eq!(
+            eval("docstring != null && len(docstring) > 0", &record),
+            Ok(json!(false))
+        );
+        assert_eq!(
+            eval("docstring == null || len(docstring) > 0", &record),
+            Ok(json!(true))
+        );
+        assert!(eval("len(docstring) > 0", &record).is_err());
+    }
+
+    #[test]
+    fn number_literals() {
+        let record = json!({"score": 0.00001, "delta": -2});
+        assert_eq!(eval("score == 1e-5", &record), Ok(json!(true)));
+        assert_eq!(eval("score < 1E+2", &record), Ok(json!(true)));
+        assert_eq!(eval("delta == -2", &record), Ok(json!(true)));
+        assert_eq!(eval("delta < -1.5", &record), Ok(json!(true)));
+    }
+
+    #[test]
+    fn nested_and_tuple_fields() {
+        let record = json!({"meta": {"repo": "a/b"}});
+        assert_eq!(eval("meta.repo == 'a/b'", &record), Ok(json!(true)));
+        assert_eq!(eval("meta.missing == null", &record), Ok(json!(true)));
+        assert_eq!(eval("field1 == 2", &json!([1, 2])), Ok(json!(true)));
+    }
+
+    #[test]
+    fn error_messages() {
+        let parse = |s: &str| s.parse::<Expr>().unwrap_err();
+        assert_eq!(parse("n > -"), "expected a number after `-` in `n > -`");
+        assert_eq!(parse("n > 1.2.3"), "invalid number `1.2.3`");
+        assert_eq!(parse("name == 'x"), "unterminated string in `name == 'x`");
+        assert_eq!(parse("n # 1"), "unexpected character `#` in `n # 1`");
+        assert_eq!(parse("size(n) > 1"), "unknown function `size`");
+        assert_eq!(parse("len(a, b)"), "`len` takes 1 argument(s)");
+        assert_eq!(parse("(a"), "expected RParen, found None");
+        assert_eq!(
+            eval("n < 'x'", &json!({"n": 1})),
+            Err("cannot compare 1 < \"x\"".to_string())
+        );
+        assert_eq!(
+            eval("n && true", &json!({"n": 1})),
+            Err("expected a boolean, found 1".to_string())
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! `--preview`, the first samples of a run laid out for reading instead of written out, to check
+//! what a language or query extracts before a full run. Each field is printed on a line of its
+//! own with long values truncated, and the masks of callees are highlighted on terminals.
+
+use crate::samples::FUNC_CALL_ID_MASK;
+use crate::writer::Record;
+use serde_json::Value;
+use std::io::IsTerminal;
+
+/// Characters shown of a line of a value
+const PREVIEW_CHARS: usize = 120;
+/// Lines shown of a multi-line value
+const PREVIEW_LINES: usize = 12;
+
+/// Whether previews are colored, when stdout is a terminal and NO_COLOR is not set
+pub fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// `record` under the heading `title`, a field per line
+pub fn format_record(title: &str, record: &Record, color: bool) -> String {
+    let paint = |text: &str, style: &str| match color {
+        true => format!("\x1b[{}m{}\x1b[0m", style, text),
+        false => text.to_string(),
+    };
+    let mut text = format!("{}\n", paint(&format!("--- {} ---", title), "2"));
+    for (column, value) in record.columns.iter().zip(&record.values) {
+        let name = paint(column, "1");
+        match value {
+            Value::String(value) if value.contains('\n') => {
+                text.push_str(&format!("{}:\n", name));
+                let lines: Vec<&str> = value.lines().collect();
+                for line in lines.iter().take(PREVIEW_LINES) {
+                    let line = highlight_masks(&truncate(line), color);
+                    text.push_str(&format!("    {}\n", line));
+                }
+                if lines.len() > PREVIEW_LINES {
+                    let more = lines.len() - PREVIEW_LINES;
+                    text.push_str(&format!("    … {} more lines\n", more));
+                }
+            }
+            Value::String(value) => {
+                let value = highlight_masks(&truncate(value), color);
+                text.push_str(&format!("{}: {}\n", name, value));
+            }
+            Value::Array(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| match item {
+                        Value::String(item) => item.clone(),
+                        item => item.to_string(),
+                    })
+                    .collect();
+                text.push_str(&format!("{}: [{} items]", name, items.len()));
+                if !items.is_empty() {
+                    let joined = highlight_masks(&truncate(&items.join(" ")), color);
+                    text.push_str(&format!(" {}", joined));
+                }
+                text.push('\n');
+            }
+            value => text.push_str(&format!("{}: {}\n", name, truncate(&value.to_string()))),
+        }
+    }
+    text
+}
+
+/// The first `PREVIEW_CHARS` characters of `text`, and how many are left out
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => {
+            let more = text[end..].chars().count();
+            format!("{}… ({} more characters)", &text[..end], more)
+        }
+        None => text.to_string(),
+    }
+}
+
+fn highlight_masks(text: &str, color: bool) -> String {
+    match color {
+        true => text.replace(
+            FUNC_CALL_ID_MASK,
+            &format!("\x1b[1;33m{}\x1b[0m", FUNC_CALL_ID_MASK),
+        ),
+        false => text.to_string(),
+    }
+}
@@ -0,0 +1,146 @@
+//! `readme_api`, the sections of the README of a repository paired with the public functions they
+//! mention, as weakly supervised (natural language, code) retrieval pairs. The README of a source
+//! file is the nearest one in its directory or a parent directory up to the root of its git
+//! repository, and a section is the text under a heading of it. A section mentions a function
+//! when it has the name in its inline code or code blocks, or calls it in its text.
+
+use crate::analysis::{declaration, named_functions, LanguageSpec};
+use crate::error::Result;
+use crate::extract::{Extractor, FileCtx};
+use crate::{node_text, DataSample};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tree_sitter::Node;
+
+/// File names of READMEs, in the order they are looked up in a directory
+const README_NAMES: [&str; 4] = ["README.md", "README.rst", "README.txt", "README"];
+/// Names shorter than this are words of too many READMEs to be taken for mentions
+const MIN_NAME_LEN: usize = 3;
+
+lazy_static::lazy_static! {
+    static ref INLINE_CODE: Regex = Regex::new(r"`([^`]+)`").unwrap();
+    /// Sections of the READMEs read so far, by path
+    static ref README_SECTIONS: Mutex<HashMap<PathBuf, Arc<Vec<Section>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A section of a README and the text of its inline code and code blocks
+#[derive(Debug, Default)]
+struct Section {
+    text: String,
+    code: String,
+}
+
+/// The nearest README of the file at `path`, `None` for files which are not in the file system
+/// such as members of archives
+fn find_readme(path: &Path) -> Option<PathBuf> {
+    for dir in path.ancestors().skip(1) {
+        for name in README_NAMES {
+            let readme = dir.join(name);
+            if readme.is_file() {
+                return Some(readme);
+            }
+        }
+        // a README above the repository is of another project
+        if dir.join(".git").exists() {
+            return None;
+        }
+    }
+    None
+}
+
+/// The sections of a markdown README, each starting at a heading. Other READMEs are a section.
+fn sections(readme: &str) -> Vec<Section> {
+    let mut sections = vec![Section::default()];
+    let mut in_block = false;
+    for line in readme.lines() {
+        let trimmed = line.trim_start();
+        let is_fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if !in_block && !is_fence && trimmed.starts_with('#') {
+            sections.push(Section::default());
+        }
+        let section = sections.last_mut().unwrap();
+        if is_fence {
+            in_block = !in_block;
+        } else if in_block {
+            section.code.push_str(line);
+            section.code.push('\n');
+        } else {
+            for code in INLINE_CODE.captures_iter(line) {
+                section.code.push_str(&code[1]);
+                section.code.push('\n');
+            }
+        }
+        section.text.push_str(line);
+        section.text.push('\n');
+    }
+    sections
+        .into_iter()
+        .filter(|section| !section.text.trim().is_empty())
+        .collect()
+}
+
+/// The sections of the README of the file at `path`, each README is read once per process
+fn readme_sections(path: &Path) -> Result<Option<Arc<Vec<Section>>>> {
+    let readme = match find_readme(path) {
+        Some(readme) => readme,
+        None => return Ok(None),
+    };
+    let mut cache = README_SECTIONS.lock().unwrap();
+    if let Some(sections) = cache.get(&readme) {
+        return Ok(Some(sections.clone()));
+    }
+    let text = String::from_utf8_lossy(&fs::read(&readme)?).into_owned();
+    let sections = Arc::new(sections(&text));
+    cache.insert(readme, sections.clone());
+    Ok(Some(sections))
+}
+
+/// Whether a function is part of the API of its file: not private or internal by its modifiers
+/// or a leading underscore, and exported by its capital letter in Go
+fn is_public(spec: &LanguageSpec, node: Node, name: &str, code: &str) -> bool {
+    if name.starts_with('_') {
+        return false;
+    }
+    if spec.name == "go" {
+        return name.starts_with(char::is_uppercase);
+    }
+    !declaration(node, code)
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word == "private" || word == "internal")
+}
+
+/// `readme_api`: README sections with the names and code of the public functions they mention
+pub struct ReadmeApi;
+
+impl Extractor for ReadmeApi {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let sections = match readme_sections(Path::new(ctx.path))? {
+            Some(sections) => sections,
+            None => return Ok(Vec::new()),
+        };
+        let mut samples = Vec::new();
+        for (name, node) in named_functions(&ctx.spec, ctx.tree.root_node(), code) {
+            if name.len() < MIN_NAME_LEN || !is_public(&ctx.spec, node, name, code) {
+                continue;
+            }
+            let name_re = regex::escape(name);
+            let word = Regex::new(&format!(r"\b{}\b", name_re)).unwrap();
+            let call = Regex::new(&format!(r"\b{}\s*\(", name_re)).unwrap();
+            let mentions = sections
+                .iter()
+                .filter(|section| word.is_match(&section.code) || call.is_match(&section.text));
+            for section in mentions {
+                samples.push(DataSample::ReadmeApi(
+                    section.text.trim().to_string(),
+                    name.to_string(),
+                    ctx.lay_out(node_text(node, code)),
+                ));
+            }
+        }
+        Ok(samples)
+    }
+}
@@ -0,0 +1,238 @@
+//! Object store (`s3://bucket/prefix`, `gs://bucket/prefix`) inputs and outputs, which are
+//! staged in a local cache directory. Requires the `remote` feature; credentials are read from
+//! the usual `AWS_*` and `GOOGLE_*` environment variables.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Default directory remote inputs are downloaded to and outputs are staged in
+pub const REMOTE_CACHE: &str = ".sparser/remote";
+
+const SCHEMES: [&str; 2] = ["s3://", "gs://"];
+
+/// Whether a path is an object store URL
+pub fn is_remote(path: &str) -> bool {
+    SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+/// Local mirror of an object store URL below `cache_dir`, e.g. `s3://bucket/corpus` is staged
+/// in `cache_dir/s3/bucket/corpus`
+fn local_mirror(url: &str, cache_dir: &str) -> PathBuf {
+    let (scheme, path) = url.split_once("://").unwrap();
+    let mut dir = Path::new(cache_dir).join(scheme);
+    dir.extend(
+        path.split('/')
+            .filter(|part| !part.is_empty() && *part != "." && *part != ".."),
+    );
+    dir
+}
+
+/// The URL of the prefix containing `url`, and the last segment of `url`. An error if `url` has
+/// no segment below its bucket, such as `s3://bucket`.
+pub fn split_remote_path(url: &str) -> io::Result<(String, String)> {
+    let trimmed = url.trim_end_matches('/');
+    let (scheme, path) = trimmed.split_once("://").unwrap_or(("", trimmed));
+    match path.rsplit_once('/') {
+        Some((bucket, name)) if !bucket.is_empty() && !name.is_empty() => {
+            Ok((format!("{}://{}", scheme, bucket), name.to_string()))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} does not name an object below a bucket", url),
+        )),
+    }
+}
+
+/// Download the objects below `url` into the cache, returning the local path of `url` which is a
+/// file if `url` names a single object. Objects already in the cache are not downloaded again.
+pub fn download(url: &str, cache_dir: &str) -> io::Result<PathBuf> {
+    let local = local_mirror(url, cache_dir);
+    store::run(store::download(url, &local))?;
+    Ok(local)
+}
+
+/// An empty local directory the output for `url` is written into before `upload` copies it to
+/// the object store
+pub fn stage_output(url: &str, cache_dir: &str) -> io::Result<PathBuf> {
+    let local = local_mirror(url, cache_dir).with_extension("staged");
+    if local.exists() {
+        std::fs::remove_dir_all(&local)?;
+    }
+    std::fs::create_dir_all(&local)?;
+    Ok(local)
+}
+
+/// Upload every file below the local directory `dir` to the prefix `url`
+pub fn upload(dir: &Path, url: &str) -> io::Result<()> {
+    store::run(store::upload(dir, url))
+}
+
+#[cfg(feature = "remote")]
+mod store {
+    use futures::TryStreamExt;
+    use log::info;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::path::Path as ObjectPath;
+    use object_store::{ObjectMeta, ObjectStore, WriteMultipart};
+    use std::fs::{self, File};
+    use std::future::Future;
+    use std::io::{self, Read};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tokio::io::AsyncWriteExt;
+    use walkdir::WalkDir;
+
+    /// Size of the parts of multipart uploads
+    const PART_SIZE: usize = 8 << 20;
+    /// Parts of a file uploaded at once
+    const MAX_CONCURRENT_PARTS: usize = 4;
+
+    fn to_io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+        io::Error::other(e)
+    }
+
+    pub fn run<F: Future<Output = io::Result<()>>>(future: F) -> io::Result<()> {
+        tokio::runtime::Runtime::new()?.block_on(future)
+    }
+
+    /// The store of the bucket of `url` and the object path below the bucket
+    fn open(url: &str) -> io::Result<(Box<dyn ObjectStore>, ObjectPath)> {
+        let (scheme, path) = url.split_once("://").unwrap();
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        let bucket_url = format!("{}://{}", scheme, bucket);
+        let store: Box<dyn ObjectStore> = match scheme {
+            "s3" => Box::new(
+                AmazonS3Builder::from_env()
+                    .with_url(bucket_url)
+                    .build()
+                    .map_err(to_io_error)?,
+            ),
+            _ => Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(bucket_url)
+                    .build()
+                    .map_err(to_io_error)?,
+            ),
+        };
+        let prefix = ObjectPath::parse(prefix.trim_end_matches('/')).map_err(to_io_error)?;
+        Ok((store, prefix))
+    }
+
+    /// Modification time of the cached copy of `object`, which is the time the object was last
+    /// modified so that a changed object is downloaded again
+    fn cached_time(object: &ObjectMeta) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(object.last_modified.timestamp().max(0) as u64)
+    }
+
+    /// Whether `path` is a copy of `object` downloaded before
+    fn is_cached(path: &Path, object: &ObjectMeta) -> bool {
+        match fs::metadata(path).and_then(|m| Ok((m.len(), m.modified()?))) {
+            Ok((len, modified)) => len == object.size as u64 && modified == cached_time(object),
+            Err(_) => false,
+        }
+    }
+
+    pub async fn download(url: &str, local: &Path) -> io::Result<()> {
+        let (store, prefix) = open(url)?;
+        // a single object is listed under its own name
+        let objects: Vec<_> = store
+            .list(Some(&prefix))
+            .try_collect()
+            .await
+            .map_err(to_io_error)?;
+        let objects = match objects.is_empty() {
+            true => vec![store.head(&prefix).await.map_err(to_io_error)?],
+            false => objects,
+        };
+        for object in objects {
+            let path = match object.location.prefix_match(&prefix) {
+                Some(parts) => {
+                    parts.fold(local.to_path_buf(), |path, part| path.join(part.as_ref()))
+                }
+                None => local.to_path_buf(),
+            };
+            if is_cached(&path, &object) {
+                continue;
+            }
+            info!("Downloading {}", object.location);
+            fs::create_dir_all(path.parent().unwrap())?;
+            // streamed to a temporary file, so an interrupted download is not taken as cached
+            let mut part_name = path.file_name().unwrap().to_os_string();
+            part_name.push(".part");
+            let part_path = path.with_file_name(part_name);
+            let mut file = tokio::fs::File::create(&part_path).await?;
+            let mut chunks = store
+                .get(&object.location)
+                .await
+                .map_err(to_io_error)?
+                .into_stream();
+            while let Some(chunk) = chunks.try_next().await.map_err(to_io_error)? {
+                file.write_all(&chunk).await?;
+            }
+            let file = file.into_std().await;
+            file.sync_all()?;
+            file.set_modified(cached_time(&object))?;
+            fs::rename(&part_path, &path)?;
+        }
+        Ok(())
+    }
+
+    pub async fn upload(dir: &Path, url: &str) -> io::Result<()> {
+        let (store, prefix) = open(url)?;
+        for entry in WalkDir::new(dir) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(dir).unwrap();
+            let location = relative.iter().fold(prefix.clone(), |location, part| {
+                location.child(part.to_string_lossy().as_ref())
+            });
+            info!("Uploading {}", location);
+            let mut file = File::open(entry.path())?;
+            let upload = store.put_multipart(&location).await.map_err(to_io_error)?;
+            let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+            let mut buf = vec![0; PART_SIZE];
+            loop {
+                let read = match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) => {
+                        writer.abort().await.map_err(to_io_error)?;
+                        return Err(e);
+                    }
+                };
+                writer
+                    .wait_for_capacity(MAX_CONCURRENT_PARTS)
+                    .await
+                    .map_err(to_io_error)?;
+                writer.write(&buf[..read]);
+            }
+            writer.finish().await.map_err(to_io_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+mod store {
+    use std::future::Future;
+    use std::io;
+    use std::path::Path;
+
+    pub fn run<F: Future<Output = io::Result<()>>>(_: F) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "object store paths require sparser to be built with the `remote` feature",
+        ))
+    }
+
+    pub async fn download(_: &str, _: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub async fn upload(_: &Path, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+//! Sample types read and written by the subcommands. `JsonSample` is a function of a
+//! CodeSearchNet style corpus, `CallJsonSample` a caller-callee pair built from them by
+//! `pair-calls`, and `DataSample` a sample produced by the `extract` tasks.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Placeholder replacing the name of the callee in the caller code of `func_call` samples
+pub const FUNC_CALL_ID_MASK: &str = "<masked_func_id>";
+
+/// A function of a CodeSearchNet style jsonl corpus. Only `func_name` and `code` are required,
+/// rows missing the other fields are read as partially filled samples.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub struct JsonSample {
+    pub func_name: String,
+    /// repository the function was taken from, samples are paired within a repository
+    #[serde(default)]
+    pub repo: Option<String>,
+    /// file the function was taken from, which groups samples of corpora without repositories
+    #[serde(default)]
+    pub path: Option<String>,
+    /// source of the function including its comment
+    #[serde(default)]
+    pub original_string: Option<String>,
+    /// source of the function without its comment
+    pub code: String,
+    #[serde(default)]
+    pub code_tokens: Option<Vec<String>>,
+    #[serde(default)]
+    pub docstring: Option<String>,
+    #[serde(default)]
+    pub docstring_tokens: Option<Vec<String>>,
+    /// language of the function, e.g. `python`, used by runs over several languages
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+impl JsonSample {
+    /// The repository, or the file if there is none, whose samples are paired with each other
+    pub fn group(&self) -> &str {
+        self.repo
+            .as_deref()
+            .or(self.path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Whether any of the optional fields was missing
+    pub fn is_partial(&self) -> bool {
+        (self.repo.is_none() && self.path.is_none())
+            || self.original_string.is_none()
+            || self.code_tokens.is_none()
+            || self.docstring.is_none()
+            || self.docstring_tokens.is_none()
+    }
+}
+
+/// Lines of `code` and the numbers of tokens of `code` and of `comm`, those of their token lists
+/// or their whitespace separated words if a list is empty
+pub fn lengths(
+    code: &str,
+    code_tokens: &[String],
+    comm: &str,
+    comm_tokens: &[String],
+) -> (usize, usize, usize) {
+    let token_count = |text: &str, tokens: &[String]| match tokens.is_empty() {
+        true => text.split_whitespace().count(),
+        false => tokens.len(),
+    };
+    (
+        code.lines().count(),
+        token_count(code, code_tokens),
+        token_count(comm, comm_tokens),
+    )
+}
+
+/// Fields of `JsonSample` which a `SchemaMap` can map
+const JSON_SAMPLE_FIELDS: [&str; 9] = [
+    "func_name",
+    "repo",
+    "path",
+    "original_string",
+    "code",
+    "code_tokens",
+    "docstring",
+    "docstring_tokens",
+    "language",
+];
+
+/// Mapping of other jsonl corpora onto `JsonSample`, written as
+/// `code=content, func_name=name, repo=repository.full_name`. Sources are dotted paths into
+/// nested objects, fields without a mapping are read from the field of the same name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SchemaMap {
+    /// sample field and source path of each mapping
+    fields: Vec<(String, String)>,
+}
+
+impl FromStr for SchemaMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::new();
+        for mapping in s.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            let (field, source) = mapping
+                .split_once('=')
+                .ok_or_else(|| format!("expected field=source, got {}", mapping))?;
+            let (field, source) = (field.trim(), source.trim());
+            if !JSON_SAMPLE_FIELDS.contains(&field) {
+                return Err(format!(
+                    "unknown sample field {}, expected one of {}",
+                    field,
+                    JSON_SAMPLE_FIELDS.join(", ")
+                ));
+            }
+            if source.is_empty() || source.split('.').any(str::is_empty) {
+                return Err(format!("invalid source path in {}", mapping));
+            }
+            fields.push((field.to_string(), source.to_string()));
+        }
+        Ok(SchemaMap { fields })
+    }
+}
+
+impl TryFrom<String> for SchemaMap {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for SchemaMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mappings: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(field, source)| format!("{}={}", field, source))
+            .collect();
+        f.write_str(&mappings.join(", "))
+    }
+}
+
+impl From<SchemaMap> for String {
+    fn from(schema: SchemaMap) -> Self {
+        schema.to_string()
+    }
+}
+
+impl SchemaMap {
+    /// Rename the fields of a row to those of `JsonSample`. Mapped fields whose source is missing
+    /// are left out, so that they read as missing optional fields.
+    pub fn apply(&self, mut row: Value) -> Value {
+        let values: Vec<Option<Value>> = self
+            .fields
+            .iter()
+            .map(|(_, source)| row.pointer(&json_pointer(source)).cloned())
+            .collect();
+        if let Value::Object(map) = &mut row {
+            for ((field, _), value) in self.fields.iter().zip(values) {
+                match value {
+                    Some(value) => map.insert(field.clone(), value),
+                    None => map.remove(field),
+                };
+            }
+        }
+        row
+    }
+}
+
+/// JSON pointer of a dotted path such as `repository.full_name` or `files.0.content`
+fn json_pointer(path: &str) -> String {
+    path.split('.')
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// A caller-callee pair data sample
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub struct CallJsonSample {
+    pub caller_code: String,
+    pub caller_comm: String,
+    pub callee_code: String,
+    pub callee_comm: String,
+    /// whether the caller calls the callee, `false` for negative samples
+    pub label: bool,
+    pub caller_code_tokens: Vec<String>,
+    pub caller_comm_tokens: Vec<String>,
+    pub callee_code_tokens: Vec<String>,
+    pub callee_comm_tokens: Vec<String>,
+    /// language of the caller and callee
+    #[serde(default)]
+    pub language: String,
+    /// byte ranges of the masked calls of the callee in `caller_code`
+    #[serde(default)]
+    pub caller_mask_spans: Vec<[usize; 2]>,
+    /// indices of the masked calls of the callee in `caller_code_tokens`
+    #[serde(default)]
+    pub caller_mask_token_indices: Vec<usize>,
+    /// lines of `caller_code` before `--code-layout` lays it out
+    #[serde(default)]
+    pub caller_code_lines: usize,
+    /// tokens of `caller_code` and of `caller_comm`, counted in the token lists of the input or as
+    /// whitespace separated words if it has none
+    #[serde(default)]
+    pub caller_code_token_count: usize,
+    #[serde(default)]
+    pub caller_doc_token_count: usize,
+    /// lines of `callee_code` before `--code-layout` lays it out
+    #[serde(default)]
+    pub callee_code_lines: usize,
+    /// tokens of `callee_code` and of `callee_comm`, counted in the token lists of the input or as
+    /// whitespace separated words if it has none
+    #[serde(default)]
+    pub callee_code_token_count: usize,
+    #[serde(default)]
+    pub callee_doc_token_count: usize,
+    /// number of functions of the repo the caller calls, and which call the callee
+    #[serde(default)]
+    pub caller_fan_out: usize,
+    #[serde(default)]
+    pub callee_fan_in: usize,
+    /// `caller_code` before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_code_original: Option<String>,
+    /// syntax tree of the caller before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_ast: Option<String>,
+    /// syntax tree of the callee, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callee_ast: Option<String>,
+    /// cyclomatic complexity of the caller, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_complexity: Option<usize>,
+    /// how deeply blocks nest in the body of the caller, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_nesting_depth: Option<usize>,
+    /// parameters of the caller, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_num_params: Option<usize>,
+    /// cyclomatic complexity of the callee, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callee_complexity: Option<usize>,
+    /// how deeply blocks nest in the body of the callee, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callee_nesting_depth: Option<usize>,
+    /// parameters of the callee, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callee_num_params: Option<usize>,
+}
+
+/// A caller with the callee of one of its calls among distractor functions it does not call, as
+/// re-ranking models are evaluated
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct RankedCallSample {
+    pub caller_code: String,
+    /// byte ranges of the masked calls of the callee in `caller_code`
+    pub caller_mask_spans: Vec<[usize; 2]>,
+    pub caller_comm: String,
+    /// `caller_code` before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_code_original: Option<String>,
+    /// the callee and the distractors in random order
+    pub candidate_codes: Vec<String>,
+    pub candidate_comms: Vec<String>,
+    /// index of the callee among the candidates
+    pub label: usize,
+    pub language: String,
+    /// lines of `caller_code` before `--code-layout` lays it out
+    #[serde(default)]
+    pub caller_code_lines: usize,
+    /// tokens of `caller_code` and of `caller_comm`, counted in the token lists of the input or as
+    /// whitespace separated words if it has none
+    #[serde(default)]
+    pub caller_code_token_count: usize,
+    #[serde(default)]
+    pub caller_doc_token_count: usize,
+    /// syntax tree of the caller before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_ast: Option<String>,
+    /// syntax trees of the candidates, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidate_asts: Option<Vec<String>>,
+    /// cyclomatic complexity of the caller, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_complexity: Option<usize>,
+    /// how deeply blocks nest in the body of the caller, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_nesting_depth: Option<usize>,
+    /// parameters of the caller, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_num_params: Option<usize>,
+}
+
+/// A caller with the callee of one of its calls and a function it does not call, as contrastive
+/// losses take them
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct TripletCallSample {
+    pub anchor_code: String,
+    /// byte ranges of the masked calls of the positive callee in `anchor_code`
+    pub anchor_mask_spans: Vec<[usize; 2]>,
+    pub anchor_comm: String,
+    /// `anchor_code` before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_code_original: Option<String>,
+    pub positive_code: String,
+    pub positive_comm: String,
+    pub negative_code: String,
+    pub negative_comm: String,
+    pub language: String,
+    /// lines of `anchor_code` before `--code-layout` lays it out
+    #[serde(default)]
+    pub anchor_code_lines: usize,
+    /// tokens of `anchor_code` and of `anchor_comm`, counted in the token lists of the input or as
+    /// whitespace separated words if it has none
+    #[serde(default)]
+    pub anchor_code_token_count: usize,
+    #[serde(default)]
+    pub anchor_doc_token_count: usize,
+    /// syntax trees of the anchor before masking, the positive and the negative, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_ast: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positive_ast: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative_ast: Option<String>,
+    /// cyclomatic complexity of the anchor, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_complexity: Option<usize>,
+    /// how deeply blocks nest in the body of the anchor, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_nesting_depth: Option<usize>,
+    /// parameters of the anchor, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_num_params: Option<usize>,
+}
+
+/// A caller, one of its callees and a function the callee calls in turn, for multi-hop retrieval
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ChainCallSample {
+    pub caller_code: String,
+    /// byte ranges of the masked calls of the callee in `caller_code`
+    pub caller_mask_spans: Vec<[usize; 2]>,
+    pub caller_comm: String,
+    /// `caller_code` before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_code_original: Option<String>,
+    pub callee_code: String,
+    /// byte ranges of the masked calls of the second callee in `callee_code`
+    pub callee_mask_spans: Vec<[usize; 2]>,
+    pub callee_comm: String,
+    /// `callee_code` before masking, if asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callee_code_original: Option<String>,
+    /// the function the callee calls
+    pub second_callee_code: String,
+    pub second_callee_comm: String,
+    pub language: String,
+    /// lines of `caller_code` before `--code-layout` lays it out
+    #[serde(default)]
+    pub caller_code_lines: usize,
+    /// tokens of `caller_code` and of `caller_comm`, counted in the token lists of the input or
+    /// as whitespace separated words if it has none
+    #[serde(default)]
+    pub caller_code_token_count: usize,
+    #[serde(default)]
+    pub caller_doc_token_count: usize,
+}
+
+/// A function and its port to another language, the languages in alphabetical order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ParallelSample {
+    pub language_a: String,
+    pub path_a: String,
+    pub code_a: String,
+    pub language_b: String,
+    pub path_b: String,
+    pub code_b: String,
+    /// name of the function in `language_a`
+    pub func_name: String,
+    /// Jaccard similarity of the words of the documentation of the functions
+    pub doc_similarity: f64,
+}
+
+/// A sample produced by an extraction task
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+pub enum DataSample {
+    /// caller src, caller tokens, callee src, callee tokens and, with `--include-recursive`,
+    /// whether the callee is the caller
+    FuncCall(String, Vec<String>, String, Vec<String>, Option<bool>),
+    /// caller src, caller comment, callee src, callee comment, whether the caller calls the
+    /// callee and, with `--include-recursive`, whether the callee is the caller
+    FuncCallComm(String, String, String, String, bool, Option<bool>),
+    /// function src and function comment
+    FuncComm(String, String),
+    /// file src without its header comment and the header comment
+    FileComm(String, String),
+    /// class declaration with the signatures and documentation of its methods, and class
+    /// documentation
+    ClassComm(String, String),
+    /// section of a README, name of a public function it mentions and function src
+    ReadmeApi(String, String, String),
+    /// comment of a function before a commit, function src after it, whether the comment is stale
+    StaleComment(String, String, bool),
+    /// function name and its versions, oldest first
+    FuncHistory(String, Vec<FuncVersion>),
+    /// fields of a task defined by a task file, in the order of the file
+    Fields(Vec<(String, String)>),
+}
+
+/// A version of a function in the history of its file
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+pub struct FuncVersion {
+    /// hash of the commit making the version
+    pub commit: String,
+    pub author: String,
+    /// author date of the commit in ISO 8601
+    pub date: String,
+    /// subject of the commit message
+    pub message: String,
+    pub code: String,
+}
+
+/// Record written for a `DataSample::FuncCall`
+#[derive(Serialize)]
+pub(crate) struct FuncCallRecord<'a> {
+    pub caller_code: &'a str,
+    pub caller_code_tokens: &'a [String],
+    pub callee_code: &'a str,
+    pub callee_code_tokens: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursive: Option<bool>,
+}
+
+/// Column names of the tuple records written for each `DataSample` variant
+pub(crate) const FUNC_CALL_COMM_COLUMNS: [&str; 5] = [
+    "caller_code",
+    "caller_comm",
+    "callee_code",
+    "callee_comm",
+    "label",
+];
+pub(crate) const FUNC_COMM_COLUMNS: [&str; 2] = ["code", "comment"];
+pub(crate) const FILE_COMM_COLUMNS: [&str; 2] = ["code", "summary"];
+pub(crate) const README_API_COLUMNS: [&str; 3] = ["readme_section", "func_name", "code"];
+pub(crate) const STALE_COMMENT_COLUMNS: [&str; 3] = ["old_comment", "new_code", "is_stale"];
+pub(crate) const FUNC_HISTORY_COLUMNS: [&str; 6] = [
+    "func_name",
+    "code_versions",
+    "commits",
+    "authors",
+    "dates",
+    "messages",
+];
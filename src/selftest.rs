@@ -0,0 +1,397 @@
+//! `sparser selftest`, every task run against small fixtures embedded in the binary, to check that
+//! a build and its grammars extract what they should before a long run. The fixtures are a chain
+//! of functions of a repo in each language of `pair-calls`, each calling the next one and a
+//! library function, and a Solidity contract of such functions for `extract`. The checks count the
+//! samples of each task and look at their shape, e.g. that the masks of callers are where the
+//! spans say they are.
+
+use crate::analysis::LanguageSpec;
+use crate::benchmark::{function_code, function_sample};
+use crate::error::{Error, Result};
+use crate::extract::{ExtractorRegistry, FileCtx};
+use crate::pair_calls::{
+    chain_samples, pair_samples, rank_samples, triplet_samples, PairOptions, SeenPairs,
+    TargetLanguage,
+};
+use crate::samples::{DataSample, FUNC_CALL_ID_MASK};
+use crate::JsonSample;
+use tokio::runtime::Runtime;
+use tree_sitter::Parser;
+
+/// Functions of the fixture of each language
+const NUM_FUNCTIONS: usize = 4;
+/// Function every function of the fixtures calls, which is not one of them
+const LIBRARY_FUNCTION: &str = "helper";
+
+/// The `extract` fixture, `step0` calls `step1` which calls `step2`
+const CONTRACT: &str = r#"// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.0;
+
+/// @title A computation in steps
+contract Selftest {
+    /// @notice First step of the computation
+    /// @param x the input
+    function step0(uint256 x) public pure returns (uint256) {
+        x = x * 2 + 1;
+        return step1(x);
+    }
+
+    /// @notice Second step of the computation
+    /// @param x the input
+    function step1(uint256 x) public pure returns (uint256) {
+        x = x * 3 + 1;
+        return step2(x);
+    }
+
+    /// @notice Last step of the computation
+    /// @param x the input
+    function step2(uint256 x) public pure returns (uint256) {
+        return x * 4 + 1;
+    }
+}
+"#;
+
+/// Check that every task extracts what it should from small fixtures of each language
+#[derive(clap::Args, Debug)]
+pub struct SelftestArgs {
+    /// Only check this language: solidity, python, javascript, java, go, php or ruby
+    /// [default: all]
+    #[clap(short = 'l', long)]
+    pub lang: Option<String>,
+}
+
+/// A summary of the samples of a check, or what is wrong with them
+type Outcome = std::result::Result<String, String>;
+
+/// A check of a pair-calls output format on the fixture of a language
+type PairCheck = fn(&Runtime, Vec<JsonSample>, LanguageSpec) -> Outcome;
+
+/// An error of the check with `message` unless `cond`
+fn ensure(cond: bool, message: impl FnOnce() -> String) -> std::result::Result<(), String> {
+    match cond {
+        true => Ok(()),
+        false => Err(message()),
+    }
+}
+
+/// Whether `spans` are non-empty and each covers a mask in `code`
+fn masks_at_spans(code: &str, spans: &[[usize; 2]]) -> bool {
+    !spans.is_empty()
+        && spans
+            .iter()
+            .all(|[start, end]| code.get(*start..*end) == Some(FUNC_CALL_ID_MASK))
+}
+
+/// The fixture of `lang`, `step{i}` calls `step{i + 1}` and the library function
+fn pair_fixture(lang: TargetLanguage) -> Result<Vec<JsonSample>> {
+    let mut parser = Parser::new();
+    parser.set_language(lang.spec().language)?;
+    let name = |idx: usize| match idx < NUM_FUNCTIONS {
+        true => format!("step{}", idx),
+        false => LIBRARY_FUNCTION.to_string(),
+    };
+    (0..NUM_FUNCTIONS)
+        .map(|idx| {
+            let code = function_code(lang, &name(idx), idx, &name(idx + 1), LIBRARY_FUNCTION);
+            function_sample(&mut parser, lang, "selftest/repo", &name(idx), idx, code)
+        })
+        .collect()
+}
+
+/// Every caller but the last has a callee, the first reaches all other functions and has no
+/// function left for a negative pair
+fn check_binary(runtime: &Runtime, fixture: Vec<JsonSample>, spec: LanguageSpec) -> Outcome {
+    let pairs = runtime
+        .block_on(pair_samples(
+            fixture,
+            spec,
+            PairOptions::default(),
+            &SeenPairs::default(),
+        ))
+        .map_err(|e| e.to_string())?;
+    let (positive, negative): (Vec<_>, Vec<_>) = pairs.iter().partition(|pair| pair.label);
+    ensure(positive.len() == NUM_FUNCTIONS - 1, || {
+        format!(
+            "{} positive pairs, expected {}",
+            positive.len(),
+            NUM_FUNCTIONS - 1
+        )
+    })?;
+    ensure(negative.len() == NUM_FUNCTIONS - 2, || {
+        format!(
+            "{} negative pairs, expected {}",
+            negative.len(),
+            NUM_FUNCTIONS - 2
+        )
+    })?;
+    for pair in &positive {
+        ensure(
+            masks_at_spans(&pair.caller_code, &pair.caller_mask_spans),
+            || {
+                format!(
+                    "positive pair without masks at its spans: {}",
+                    pair.caller_code
+                )
+            },
+        )?;
+        ensure(
+            !pair.caller_code_tokens.is_empty() && !pair.callee_code_tokens.is_empty(),
+            || "positive pair without code tokens".to_string(),
+        )?;
+        ensure(!pair.callee_comm.is_empty(), || {
+            "positive pair without the comment of the callee".to_string()
+        })?;
+    }
+    for pair in &negative {
+        ensure(
+            pair.caller_mask_spans.is_empty() && !pair.caller_code.contains(FUNC_CALL_ID_MASK),
+            || format!("negative pair with masks: {}", pair.caller_code),
+        )?;
+    }
+    Ok(format!(
+        "{} positive and {} negative pairs",
+        positive.len(),
+        negative.len()
+    ))
+}
+
+/// A row per call of the callers which do not reach every other function
+fn check_ranked(runtime: &Runtime, fixture: Vec<JsonSample>, spec: LanguageSpec) -> Outcome {
+    let opts = PairOptions {
+        distractors: 1,
+        ..Default::default()
+    };
+    let codes: Vec<String> = fixture.iter().map(|sample| sample.code.clone()).collect();
+    let rows = runtime
+        .block_on(rank_samples(fixture, spec, opts))
+        .map_err(|e| e.to_string())?;
+    ensure(rows.len() == NUM_FUNCTIONS - 2, || {
+        format!("{} rows, expected {}", rows.len(), NUM_FUNCTIONS - 2)
+    })?;
+    for row in &rows {
+        ensure(
+            masks_at_spans(&row.caller_code, &row.caller_mask_spans),
+            || format!("row without masks at its spans: {}", row.caller_code),
+        )?;
+        ensure(
+            row.candidate_codes.len() == opts.distractors + 1
+                && row.candidate_comms.len() == opts.distractors + 1,
+            || format!("{} candidates, expected 2", row.candidate_codes.len()),
+        )?;
+        ensure(row.label < row.candidate_codes.len(), || {
+            format!("label {} out of the candidates", row.label)
+        })?;
+        ensure(
+            row.candidate_codes.iter().all(|code| codes.contains(code)),
+            || "candidate which is not a function of the fixture".to_string(),
+        )?;
+    }
+    Ok(format!("{} rows of 2 candidates", rows.len()))
+}
+
+/// A triplet per call of the callers which do not reach every other function
+fn check_triplet(runtime: &Runtime, fixture: Vec<JsonSample>, spec: LanguageSpec) -> Outcome {
+    let rows = runtime
+        .block_on(triplet_samples(fixture, spec, PairOptions::default()))
+        .map_err(|e| e.to_string())?;
+    ensure(rows.len() == NUM_FUNCTIONS - 2, || {
+        format!("{} triplets, expected {}", rows.len(), NUM_FUNCTIONS - 2)
+    })?;
+    for row in &rows {
+        ensure(
+            masks_at_spans(&row.anchor_code, &row.anchor_mask_spans),
+            || format!("anchor without masks at its spans: {}", row.anchor_code),
+        )?;
+        ensure(row.positive_code != row.negative_code, || {
+            "triplet whose positive is its negative".to_string()
+        })?;
+    }
+    Ok(format!("{} triplets", rows.len()))
+}
+
+/// A chain per call of a callee which calls the next function in turn
+fn check_chain(runtime: &Runtime, fixture: Vec<JsonSample>, spec: LanguageSpec) -> Outcome {
+    let rows = runtime
+        .block_on(chain_samples(fixture, spec, PairOptions::default()))
+        .map_err(|e| e.to_string())?;
+    ensure(rows.len() == NUM_FUNCTIONS - 2, || {
+        format!("{} chains, expected {}", rows.len(), NUM_FUNCTIONS - 2)
+    })?;
+    for row in &rows {
+        ensure(
+            masks_at_spans(&row.caller_code, &row.caller_mask_spans)
+                && masks_at_spans(&row.callee_code, &row.callee_mask_spans),
+            || format!("chain without masks at its spans: {}", row.caller_code),
+        )?;
+    }
+    Ok(format!("{} chains", rows.len()))
+}
+
+/// Number of samples `task` extracts from `CONTRACT`, `None` for tasks registered by other
+/// crates whose samples are only checked for their shape
+fn expected_samples(task: &str) -> Option<usize> {
+    match task {
+        // the calls of step0 and step1
+        "func_call" => Some(2),
+        // a positive and a negative sample per call
+        "func_call_comm" => Some(4),
+        // the commented functions
+        "func_comm" => Some(3),
+        // the contract without its title
+        "file_comm" => Some(1),
+        // the contract with its functions
+        "class_comm" => Some(1),
+        // the contract has no history
+        "stale_comment" | "func_history" => Some(0),
+        _ => None,
+    }
+}
+
+/// What is wrong with the shape of `sample`, if anything
+fn sample_shape(sample: &DataSample) -> std::result::Result<(), String> {
+    match sample {
+        DataSample::FuncCall(caller, caller_tokens, callee, callee_tokens, _) => ensure(
+            !caller_tokens.is_empty() && !callee_tokens.is_empty() && caller != callee,
+            || format!("caller and callee without tokens: {}", caller),
+        ),
+        DataSample::FuncCallComm(caller, caller_comm, _, callee_comm, label, _) => {
+            ensure(!caller_comm.is_empty() && !callee_comm.is_empty(), || {
+                format!("sample without comments: {}", caller)
+            })?;
+            ensure(!label || caller.contains(FUNC_CALL_ID_MASK), || {
+                format!("positive sample without a mask: {}", caller)
+            })
+        }
+        DataSample::FuncComm(src, comment) => {
+            ensure(src.contains("function") && !comment.is_empty(), || {
+                format!("function without its source or comment: {}", src)
+            })
+        }
+        DataSample::ClassComm(skeleton, doc) => ensure(
+            skeleton.starts_with("contract") && skeleton.contains("@notice") && !doc.is_empty(),
+            || {
+                format!(
+                    "contract without its functions or documentation: {}",
+                    skeleton
+                )
+            },
+        ),
+        DataSample::ReadmeApi(section, name, src) => ensure(
+            section.contains(name.as_str()) && src.contains(name.as_str()),
+            || format!("function {} not in its README section", name),
+        ),
+        DataSample::StaleComment(comment, src, _) => {
+            ensure(!comment.is_empty() && src.contains("function"), || {
+                format!("function without its source or old comment: {}", src)
+            })
+        }
+        DataSample::FuncHistory(name, versions) => ensure(
+            versions.len() > 1
+                && versions
+                    .iter()
+                    .all(|version| version.code.contains(name.as_str())),
+            || format!("function {} without its versions", name),
+        ),
+        DataSample::FileComm(src, summary) => ensure(
+            src.contains("contract") && summary.contains("@title") && !src.contains(summary.trim()),
+            || format!("file without its source or header comment: {}", summary),
+        ),
+        DataSample::Fields(fields) => {
+            ensure(!fields.is_empty(), || "sample without fields".to_string())
+        }
+    }
+}
+
+fn check_extract(registry: &ExtractorRegistry, task: &str) -> Outcome {
+    let spec = LanguageSpec::solidity();
+    let mut parser = Parser::new();
+    parser
+        .set_language(spec.language)
+        .map_err(|e| e.to_string())?;
+    let tree = parser
+        .parse(CONTRACT, None)
+        .ok_or_else(|| "cannot parse the contract".to_string())?;
+    let ctx = FileCtx {
+        path: "Selftest.sol",
+        tree: &tree,
+        spec,
+        layout: None,
+        include_recursive: false,
+        max_file_lines: None,
+        subtokenizer: None,
+        seed: None,
+    };
+    let samples = match registry.get(task) {
+        Some(extractor) => extractor
+            .extract(CONTRACT, &ctx)
+            .map_err(|e| e.to_string())?,
+        None => return Err(format!("no task {}", task)),
+    };
+    if let Some(expected) = expected_samples(task) {
+        ensure(samples.len() == expected, || {
+            format!("{} samples, expected {}", samples.len(), expected)
+        })?;
+    }
+    samples.iter().try_for_each(sample_shape)?;
+    Ok(format!("{} samples", samples.len()))
+}
+
+pub fn run(args: SelftestArgs) -> Result<()> {
+    let lang = args.lang.as_deref();
+    let langs: Vec<TargetLanguage> = match lang {
+        None => TargetLanguage::ALL.to_vec(),
+        Some("solidity") => Vec::new(),
+        Some(lang) => match lang.parse() {
+            Ok(lang) => vec![lang],
+            Err(e) => clap::Error::raw(clap::ErrorKind::InvalidValue, format!("{}\n", e)).exit(),
+        },
+    };
+    let mut outcomes: Vec<(&str, String, Outcome)> = Vec::new();
+    if matches!(lang, None | Some("solidity")) {
+        let registry = ExtractorRegistry::default();
+        for task in registry.names() {
+            let outcome = check_extract(&registry, task);
+            outcomes.push(("solidity", format!("extract {}", task), outcome));
+        }
+    }
+    let runtime = Runtime::new()?;
+    for lang in langs {
+        let spec = lang.spec();
+        let checks: [(&str, PairCheck); 4] = [
+            ("binary", check_binary),
+            ("ranked", check_ranked),
+            ("triplet", check_triplet),
+            ("chain", check_chain),
+        ];
+        for (format, check) in checks {
+            let outcome = match pair_fixture(lang) {
+                Ok(fixture) => check(&runtime, fixture, spec),
+                Err(e) => Err(e.to_string()),
+            };
+            outcomes.push((lang.name(), format!("pair-calls {}", format), outcome));
+        }
+    }
+    for (lang, task, outcome) in &outcomes {
+        let (status, detail) = match outcome {
+            Ok(summary) => ("ok", summary),
+            Err(message) => ("FAIL", message),
+        };
+        println!("{:<4} {:<10} {:<26} {}", status, lang, task, detail);
+    }
+    let failed = outcomes
+        .iter()
+        .filter(|(_, _, outcome)| outcome.is_err())
+        .count();
+    println!(
+        "{} of {} checks passed",
+        outcomes.len() - failed,
+        outcomes.len()
+    );
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::Selftest {
+            failed,
+            total: outcomes.len(),
+        }),
+    }
+}
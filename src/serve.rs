@@ -0,0 +1,234 @@
+//! `sparser serve`, the analyses as an HTTP service for callers which cannot spawn a process per
+//! file. Requires the `serve` feature. Requests and responses are JSON except for sources:
+//!
+//! - `POST /extract?lang=solidity` with a source file as body returns the `functions` and
+//!   `comments` of commented functions by name, and the `calls` as `[caller, callee]` pairs
+//! - `POST /pair-calls?lang=python` with an array of `JsonSample`s of one repo returns the
+//!   `CallJsonSample`s of the group
+//! - `GET /metrics` returns the counters of `crate::metrics` in the Prometheus text format
+//! - `GET /health` returns `ok`
+//!
+//! Errors are returned as `{"error": message}`, with status 400 for errors of the request and 413
+//! for bodies larger than 64 MiB.
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8080;
+
+/// Serve the analyses over HTTP
+#[derive(clap::Args, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServeArgs {
+    /// Read options from a toml or yaml file, command line options take precedence
+    #[clap(short = 'c', long)]
+    #[serde(skip)]
+    pub config: Option<String>,
+    /// Address to listen on [default: 127.0.0.1]
+    #[clap(long)]
+    pub host: Option<String>,
+    /// Port to listen on [default: 8080]
+    #[clap(short = 'p', long)]
+    pub port: Option<u16>,
+}
+
+/// Serve until the process is stopped
+pub fn run(args: ServeArgs) -> Result<()> {
+    let host = args.host.as_deref().unwrap_or(DEFAULT_HOST);
+    let addr: SocketAddr = format!("{}:{}", host, args.port.unwrap_or(DEFAULT_PORT))
+        .parse()
+        .unwrap_or_else(|e| {
+            let message = format!("invalid address {}: {}\n", host, e);
+            clap::Error::raw(clap::ErrorKind::InvalidValue, message).exit()
+        });
+    crate::metrics::start();
+    tokio::runtime::Runtime::new()?.block_on(http::serve(addr))
+}
+
+#[cfg(feature = "serve")]
+mod http {
+    use crate::analysis::{find_function_calls, find_function_comments, LanguageSpec};
+    use crate::error::{Error, Result};
+    use crate::metrics;
+    use crate::pair_calls::{
+        pair_samples, unqualified_name, PairOptions, SeenPairs, TargetLanguage,
+    };
+    use crate::JsonSample;
+    use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Method, Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use log::{debug, info};
+    use serde_json::{json, Value};
+    use std::collections::BTreeSet;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use tokio::net::TcpListener;
+
+    /// Largest request body accepted, so that a request cannot exhaust the memory of the service
+    const MAX_BODY_SIZE: usize = 64 << 20;
+
+    pub async fn serve(addr: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Listening on http://{}", listener.local_addr()?);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(async move {
+                let connection = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service_fn(handle));
+                if let Err(e) = connection.await {
+                    debug!("connection error: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle(
+        request: Request<Incoming>,
+    ) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+        let lang = request.uri().query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "lang")
+                .map(|(_, lang)| lang.into_owned())
+        });
+        let route = (request.method().clone(), request.uri().path().to_string());
+        let response = match route {
+            (Method::GET, path) if path == "/health" => {
+                return Ok(Response::new(Full::new(Bytes::from("ok"))))
+            }
+            (Method::GET, path) if path == "/metrics" => {
+                return Ok(Response::new(Full::new(Bytes::from(metrics::prometheus()))))
+            }
+            (Method::POST, path) if path == "/extract" => match body(request).await {
+                Ok(body) => extract(lang.as_deref().unwrap_or("solidity"), body).await,
+                Err(response) => return Ok(response),
+            },
+            (Method::POST, path) if path == "/pair-calls" => match body(request).await {
+                Ok(body) => pair_calls(lang.as_deref().unwrap_or_default(), body).await,
+                Err(response) => return Ok(response),
+            },
+            (_, path) => {
+                let message = json!({ "error": format!("no such endpoint: {}", path) });
+                return Ok(json_response(StatusCode::NOT_FOUND, &message));
+            }
+        };
+        Ok(match response {
+            Ok(value) => json_response(StatusCode::OK, &value),
+            Err(e) => {
+                let status = match e.is_recoverable() {
+                    true => StatusCode::BAD_REQUEST,
+                    false => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                json_response(status, &json!({ "error": e.to_string() }))
+            }
+        })
+    }
+
+    /// The body of a request, or the error response if it cannot be read or is larger than
+    /// `MAX_BODY_SIZE`. Bodies with a larger Content-Length are refused before they are read.
+    async fn body(request: Request<Incoming>) -> std::result::Result<Bytes, Response<Full<Bytes>>> {
+        let too_large = || {
+            let message = format!("body larger than {} bytes", MAX_BODY_SIZE);
+            json_response(StatusCode::PAYLOAD_TOO_LARGE, &json!({ "error": message }))
+        };
+        let content_length = request
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+        if content_length.is_some_and(|len| len > MAX_BODY_SIZE) {
+            return Err(too_large());
+        }
+        match Limited::new(request.into_body(), MAX_BODY_SIZE)
+            .collect()
+            .await
+        {
+            Ok(body) => Ok(body.to_bytes()),
+            Err(e) if e.is::<LengthLimitError>() => Err(too_large()),
+            Err(e) => Err(json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({ "error": e.to_string() }),
+            )),
+        }
+    }
+
+    fn json_response(status: StatusCode, value: &Value) -> Response<Full<Bytes>> {
+        let mut response = Response::new(Full::new(Bytes::from(value.to_string())));
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("application/json"),
+        );
+        response
+    }
+
+    /// An error of the request, reported with status 400
+    fn bad_request(message: String) -> Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+    }
+
+    async fn extract(lang: &str, body: Bytes) -> Result<Value> {
+        let spec = LanguageSpec::by_name(lang)
+            .ok_or_else(|| bad_request(format!("Unknown language: {}", lang)))?;
+        metrics::add_file(body.len() as u64);
+        let src = String::from_utf8_lossy(&body).into_owned();
+        // parsing is CPU bound
+        tokio::task::spawn_blocking(move || {
+            let mut parser = tree_sitter::Parser::new();
+            parser.set_language(spec.language)?;
+            let tree = parser.parse(&src, None).ok_or_else(|| {
+                metrics::add_parse_failure();
+                Error::Parse {
+                    path: "request body".to_string(),
+                }
+            })?;
+            let (functions, comments) = find_function_comments(&spec, &src, tree.root_node())?;
+            let calls: BTreeSet<(&str, &str)> =
+                find_function_calls(&spec, &src, tree.root_node(), |_| true)?
+                    .into_iter()
+                    .collect();
+            Ok(json!({ "functions": functions, "comments": comments, "calls": calls }))
+        })
+        .await?
+    }
+
+    async fn pair_calls(lang: &str, body: Bytes) -> Result<Value> {
+        let language = TargetLanguage::from_str(lang).map_err(bad_request)?;
+        let mut samples: Vec<JsonSample> = serde_json::from_slice(&body)?;
+        for sample in &mut samples {
+            sample.func_name = unqualified_name(&sample.func_name).to_string();
+        }
+        // pairing is CPU bound
+        let runtime = tokio::runtime::Handle::current();
+        let pairs = tokio::task::spawn_blocking(move || {
+            runtime.block_on(pair_samples(
+                samples,
+                language.spec(),
+                PairOptions::default(),
+                &SeenPairs::default(),
+            ))
+        })
+        .await??;
+        metrics::add_samples(pairs.len());
+        Ok(serde_json::to_value(pairs)?)
+    }
+}
+
+#[cfg(not(feature = "serve"))]
+mod http {
+    use crate::error::Result;
+    use std::io;
+    use std::net::SocketAddr;
+
+    pub async fn serve(_: SocketAddr) -> Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "serve requires sparser to be built with the `serve` feature",
+        )
+        .into())
+    }
+}
@@ -0,0 +1,204 @@
+//! Shuffle of jsonl lines which do not fit in memory. Lines are scattered over temporary shard
+//! files by a random draw, then each shard is shuffled in memory and the shards are read back one
+//! after the other. Both steps are uniform, so the result is a uniform shuffle of all lines while
+//! only one shard is held in memory at a time. Shards which turn out larger than `SHARD_BYTES`
+//! are scattered again when they are read.
+
+use log::debug;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes of input per shard, a shard is held in memory when it is shuffled
+const SHARD_BYTES: u64 = 256 << 20;
+/// Upper bound of the number of open shard files, well below the default limit of 1024 open
+/// files so that the inputs and outputs of the run stay open too. The shards of inputs of more
+/// than 64 GiB are scattered again.
+const MAX_SHARDS: u64 = 256;
+
+static NUM_SHUFFLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary directory of the shards, removed when dropped
+struct ShardDir(PathBuf);
+
+impl Drop for ShardDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            debug!("cannot remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Lines being scattered over the shards of a shuffle
+pub struct ExternalShuffle {
+    dir: ShardDir,
+    shards: Vec<BufWriter<File>>,
+    /// lines of each shard
+    shard_lens: Vec<usize>,
+    rng: StdRng,
+    len: usize,
+}
+
+impl ExternalShuffle {
+    /// Start a shuffle of about `num_bytes` of lines with shards in a new directory under
+    /// `tmp_dir`, or the system temporary directory if `None`
+    pub fn new(tmp_dir: Option<&str>, num_bytes: u64, rng: StdRng) -> io::Result<Self> {
+        let parent = tmp_dir.map_or_else(std::env::temp_dir, PathBuf::from);
+        let dir = parent.join(format!(
+            "sparser-shuffle-{}-{}",
+            std::process::id(),
+            NUM_SHUFFLES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let dir = ShardDir(dir);
+        let num_shards = num_shards(num_bytes);
+        let shards = (0..num_shards)
+            .map(|idx| File::create(shard_path(&dir.0, idx)).map(BufWriter::new))
+            .collect::<io::Result<_>>()?;
+        debug!(
+            "shuffling through {} shards in {}",
+            num_shards,
+            dir.0.display()
+        );
+        Ok(ExternalShuffle {
+            dir,
+            shards,
+            shard_lens: vec![0; num_shards],
+            rng,
+            len: 0,
+        })
+    }
+
+    /// Add a line, which must not contain a newline
+    pub fn push(&mut self, line: &str) -> io::Result<()> {
+        let shard = self.rng.gen_range(0..self.shards.len());
+        writeln!(self.shards[shard], "{}", line)?;
+        self.shard_lens[shard] += 1;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Number of lines added
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The lines in random order, the shards are removed once all lines have been read
+    pub fn finish(self) -> io::Result<ShuffledLines> {
+        let num_shards = self.shards.len();
+        for mut shard in self.shards {
+            shard.flush()?;
+        }
+        let dir = self.dir;
+        let pending = (0..num_shards)
+            .map(|idx| shard_path(&dir.0, idx))
+            .zip(self.shard_lens)
+            .rev()
+            .collect();
+        Ok(ShuffledLines {
+            dir,
+            pending,
+            num_created: num_shards,
+            lines: Vec::new().into_iter(),
+            rng: self.rng,
+        })
+    }
+}
+
+/// Number of shards of `num_bytes` of lines
+fn num_shards(num_bytes: u64) -> usize {
+    (num_bytes / SHARD_BYTES + 1).min(MAX_SHARDS) as usize
+}
+
+fn shard_path(dir: &Path, idx: usize) -> PathBuf {
+    dir.join(format!("shard-{:04}.jsonl", idx))
+}
+
+/// Iterator over the shuffled lines, shard by shard
+pub struct ShuffledLines {
+    dir: ShardDir,
+    /// shards not read yet and their number of lines, the next one last
+    pending: Vec<(PathBuf, usize)>,
+    /// shard files created so far, which numbers the next one
+    num_created: usize,
+    lines: std::vec::IntoIter<String>,
+    rng: StdRng,
+}
+
+impl ShuffledLines {
+    /// Read and shuffle the next shard, or scatter it over smaller shards read in its place if
+    /// it does not fit in memory, removing its file
+    fn load_shard(&mut self) -> io::Result<()> {
+        let (path, num_lines) = self.pending.pop().unwrap();
+        let size = fs::metadata(&path)?.len();
+        // a single line is read whole, however large
+        if size > SHARD_BYTES && num_lines > 1 {
+            self.scatter(&path, size)?;
+        } else {
+            let mut lines = BufReader::new(File::open(&path)?)
+                .lines()
+                .collect::<io::Result<Vec<String>>>()?;
+            lines.shuffle(&mut self.rng);
+            self.lines = lines.into_iter();
+        }
+        fs::remove_file(&path)
+    }
+
+    /// Scatter the lines of the shard at `path` of `size` bytes over new shards, which are read
+    /// next in its place
+    fn scatter(&mut self, path: &Path, size: u64) -> io::Result<()> {
+        let paths: Vec<PathBuf> = (self.num_created..)
+            .take(num_shards(size))
+            .map(|idx| shard_path(&self.dir.0, idx))
+            .collect();
+        self.num_created += paths.len();
+        debug!(
+            "scattering {} of {} bytes over {} shards",
+            path.display(),
+            size,
+            paths.len()
+        );
+        let mut shards = paths
+            .iter()
+            .map(|path| File::create(path).map(BufWriter::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut shard_lens = vec![0; shards.len()];
+        for line in BufReader::new(File::open(path)?).lines() {
+            let shard = self.rng.gen_range(0..shards.len());
+            writeln!(shards[shard], "{}", line?)?;
+            shard_lens[shard] += 1;
+        }
+        for mut shard in shards {
+            shard.flush()?;
+        }
+        self.pending.extend(paths.into_iter().zip(shard_lens).rev());
+        Ok(())
+    }
+}
+
+impl Iterator for ShuffledLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.next() {
+                return Some(Ok(line));
+            }
+            if self.pending.is_empty() {
+                return None;
+            }
+            if let Err(e) = self.load_shard() {
+                self.pending.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Graceful shutdown on SIGINT and SIGTERM. The first signal asks the run to stop taking new
+//! inputs and to write out the samples of the inputs it read, a second one exits at once.
+//! Interrupted runs write a manifest of the input files which were completed next to their
+//! output and exit with 128 plus the number of the signal, like a shell reports them.
+
+use crate::error::{Error, Result};
+use crate::{write_metadata_file, OutputOptions};
+use serde::Serialize;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Once;
+use tokio::sync::Notify;
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+/// Number of the first signal received, 0 if none was
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+static LISTEN: Once = Once::new();
+
+lazy_static::lazy_static! {
+    static ref REQUESTED: Notify = Notify::new();
+}
+
+/// Handle SIGINT and SIGTERM from now on, on a thread of its own so that runs without a tokio
+/// runtime are stopped alike
+pub fn listen() {
+    LISTEN.call_once(|| {
+        std::thread::spawn(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("cannot start the signal handler");
+            runtime.block_on(handle_signals())
+        });
+    });
+}
+
+async fn handle_signals() {
+    #[cfg(unix)]
+    let mut terminate =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).ok();
+    loop {
+        #[cfg(unix)]
+        let signal = tokio::select! {
+            _ = tokio::signal::ctrl_c() => SIGINT,
+            Some(_) = async { terminate.as_mut()?.recv().await } => SIGTERM,
+        };
+        #[cfg(not(unix))]
+        let signal = match tokio::signal::ctrl_c().await {
+            Ok(()) => SIGINT,
+            Err(_) => return,
+        };
+        if SIGNAL.swap(signal, Ordering::SeqCst) != 0 {
+            eprintln!("\nInterrupted again, exiting without writing the output");
+            std::process::exit(128 + signal);
+        }
+        eprintln!(
+            "\nInterrupted, writing the output of the inputs read so far. Interrupt again to exit \
+             at once."
+        );
+        REQUESTED.notify_waiters();
+    }
+}
+
+/// Whether a signal asked the run to stop
+pub fn requested() -> bool {
+    SIGNAL.load(Ordering::SeqCst) != 0
+}
+
+/// Wait until a signal asks the run to stop
+pub async fn wait() {
+    loop {
+        let notified = REQUESTED.notified();
+        if requested() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// An `Interrupted` error if a signal stopped the run
+pub fn check() -> Result<()> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        signal => Err(Error::Interrupted {
+            name: match signal {
+                SIGTERM => "SIGTERM",
+                _ => "SIGINT",
+            },
+            number: signal,
+        }),
+    }
+}
+
+/// What an interrupted run got through
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    signal: i32,
+    /// input files whose samples are all in the output
+    completed_files: &'a [String],
+}
+
+/// Write the manifest of an interrupted run as JSON to `path`
+pub fn write_manifest(
+    path: &str,
+    completed_files: &[String],
+    opts: &OutputOptions,
+) -> std::io::Result<()> {
+    let manifest = Manifest {
+        signal: SIGNAL.load(Ordering::SeqCst),
+        completed_files,
+    };
+    write_metadata_file(path, &serde_json::to_vec_pretty(&manifest)?, opts)
+}
@@ -0,0 +1,384 @@
+//! Assignment of samples to the train/val/test splits
+
+use crate::predicate::Expr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How samples are assigned to splits
+#[derive(Debug, Clone, Default)]
+pub struct SplitOptions {
+    /// keys whose combination defines the strata, numeric keys are bucketed into quartiles
+    pub stratify: Vec<Expr>,
+    /// samples with the same key are always put into the same split
+    pub group_by: Option<Expr>,
+    /// write this many cross-validation folds instead of a single split
+    pub kfold: Option<usize>,
+    /// write one partition per group key with all samples of the group in test and all other
+    /// samples in train, `*` holds out every group in turn
+    pub leave_out: Vec<String>,
+    /// assign samples by a stable hash of their group key, or their content without a key
+    pub hash: bool,
+}
+
+impl SplitOptions {
+    /// Whether anything but the plain 8:1:1 split in sample order is requested
+    pub fn is_custom(&self) -> bool {
+        self.needs_records() || self.kfold.is_some()
+    }
+
+    /// Whether the split depends on the content of the samples
+    fn needs_records(&self) -> bool {
+        !self.stratify.is_empty()
+            || self.group_by.is_some()
+            || !self.leave_out.is_empty()
+            || self.hash
+    }
+}
+
+/// Split options shared by the subcommands which write train/val/test splits
+#[derive(clap::Args, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitStrategyArgs {
+    /// Split each stratum 8:1:1 so all splits share its distribution, e.g. `label` or
+    /// `len(caller_code)`; numeric keys are bucketed into quartiles. May be repeated
+    #[clap(long)]
+    pub stratify: Vec<String>,
+    /// Keep samples with the same key in the same split to avoid leakage, e.g. `repo`
+    #[clap(long)]
+    pub group_by: Option<String>,
+    /// Write K cross-validation folds into fold0/ ... fold{K-1}/ with disjoint test splits
+    #[clap(long, value_name = "K", conflicts_with = "stratify")]
+    pub kfold: Option<usize>,
+    /// Write a directory per held-out group (by --group-by, default `repo`) with its samples in
+    /// test and all others in train, `*` holds out every group. May be repeated
+    #[clap(long, value_name = "GROUP", conflicts_with_all = &["stratify", "kfold"])]
+    pub leave_out: Vec<String>,
+    /// Assign each sample to a split by a stable hash of its --group-by key (or its content),
+    /// so samples keep their split when the corpus grows
+    #[clap(long, conflicts_with_all = &["stratify", "kfold", "leave-out"])]
+    pub hash_split: bool,
+}
+
+/// Parse an expression given on the command line, exiting with a usage error if it is invalid
+pub fn parse_expr_arg(expr: &str, flag: &str) -> Expr {
+    expr.parse().unwrap_or_else(|e| {
+        clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            format!("invalid {} `{}`: {}\n", flag, expr, e),
+        )
+        .exit()
+    })
+}
+
+impl SplitStrategyArgs {
+    pub fn to_options(&self) -> SplitOptions {
+        SplitOptions {
+            stratify: self
+                .stratify
+                .iter()
+                .map(|key| parse_expr_arg(key, "--stratify"))
+                .collect(),
+            group_by: self
+                .group_by
+                .as_ref()
+                .map(|key| parse_expr_arg(key, "--group-by")),
+            kfold: self.kfold.map(|k| match k {
+                0 | 1 => clap::Error::raw(
+                    clap::ErrorKind::InvalidValue,
+                    "--kfold needs at least 2 folds\n",
+                )
+                .exit(),
+                k => k,
+            }),
+            leave_out: self.leave_out.clone(),
+            hash: self.hash_split,
+        }
+    }
+}
+
+/// Train, val and test sample indices written into the sub directory `name`, or directly into
+/// the output directory
+#[derive(Debug, Clone, Default)]
+pub struct Partition {
+    pub name: Option<String>,
+    pub splits: [Vec<usize>; 3],
+}
+
+/// Quartile bucket of each value if all non-null values are numbers
+fn quartile_buckets(values: &[Value]) -> Option<Vec<String>> {
+    let mut numbers = values
+        .iter()
+        .filter(|v| !v.is_null())
+        .map(|v| v.as_f64())
+        .collect::<Option<Vec<f64>>>()?;
+    numbers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let quartiles: Vec<f64> = [1, 2, 3]
+        .iter()
+        .filter_map(|q| numbers.get(numbers.len() * q / 4).copied())
+        .collect();
+    Some(
+        values
+            .iter()
+            .map(|v| match v.as_f64() {
+                Some(n) => format!("q{}", quartiles.iter().filter(|q| n > **q).count()),
+                None => "null".to_string(),
+            })
+            .collect(),
+    )
+}
+
+/// Value of `key` for each record. Records it cannot be evaluated on, such as `len(docstring)`
+/// of records without a docstring, have a `null` key and are reported.
+fn key_values(records: &[Value], key: &Expr) -> Vec<Value> {
+    let (mut num_failed, mut first_error) = (0, None);
+    let values = records
+        .iter()
+        .map(|record| {
+            key.eval(record).unwrap_or_else(|e| {
+                num_failed += 1;
+                first_error.get_or_insert(e);
+                Value::Null
+            })
+        })
+        .collect();
+    if let Some(e) = first_error {
+        eprintln!(
+            "{} records have a null key as it could not be evaluated on them, the first: {}",
+            num_failed, e
+        );
+    }
+    values
+}
+
+/// Stratum of each record
+fn strata(records: &[Value], keys: &[Expr]) -> Vec<Vec<String>> {
+    let mut strata = vec![Vec::new(); records.len()];
+    for key in keys {
+        let values = key_values(records, key);
+        let labels = quartile_buckets(&values)
+            .unwrap_or_else(|| values.iter().map(|v| v.to_string()).collect());
+        for (stratum, label) in strata.iter_mut().zip(labels) {
+            stratum.push(label);
+        }
+    }
+    strata
+}
+
+/// Indices of the samples grouped by `key` in order of first appearance, each sample is its
+/// own group without a key
+fn groups(num_samples: usize, records: &[Value], key: &Option<Expr>) -> Vec<Vec<usize>> {
+    let key = match key {
+        Some(key) => key,
+        None => return (0..num_samples).map(|idx| vec![idx]).collect(),
+    };
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of: HashMap<String, usize> = HashMap::new();
+    for (idx, key) in group_keys(records, key).into_iter().enumerate() {
+        let group = *group_of.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(idx);
+    }
+    groups
+}
+
+/// Split groups in order so that the number of samples of each part follows `ratio`,
+/// never splitting a group
+fn split_groups(groups: &[&Vec<usize>], ratio: &[usize]) -> Vec<Vec<usize>> {
+    let total: usize = groups.iter().map(|g| g.len()).sum();
+    let ratio_sum: usize = ratio.iter().sum();
+    let mut parts = vec![Vec::new(); ratio.len()];
+    let (mut part, mut cumulative_ratio, mut count) = (0, ratio[0], 0);
+    for group in groups {
+        // move on once the current part is full, or when adding the group would overshoot its
+        // end by more than leaving it short
+        while part + 1 < ratio.len() {
+            let end = (total * cumulative_ratio).div_ceil(ratio_sum);
+            let overshoot = (count + group.len()).saturating_sub(end);
+            if count < end && overshoot <= end - count {
+                break;
+            }
+            part += 1;
+            cumulative_ratio += ratio[part];
+        }
+        parts[part].extend(group.iter());
+        count += group.len();
+    }
+    parts
+}
+
+/// The split of the sample at `idx` of `total` samples in the plain 8:1:1 split in sample order,
+/// 0 for train, 1 for val and 2 for test, for writers which stream the samples
+pub fn plain_split_of(idx: usize, total: usize) -> usize {
+    match idx {
+        idx if idx < (total * 8).div_ceil(10) => 0,
+        idx if idx < (total * 9).div_ceil(10) => 1,
+        _ => 2,
+    }
+}
+
+/// Split groups 8:1:1 into train, val and test within each stratum
+fn split_stratified(groups: &[Vec<usize>], strata: &[Vec<String>]) -> [Vec<usize>; 3] {
+    let mut by_stratum: BTreeMap<&[String], Vec<&Vec<usize>>> = BTreeMap::new();
+    for group in groups {
+        let stratum = strata.get(group[0]).map_or(&[][..], |s| &s[..]);
+        by_stratum.entry(stratum).or_default().push(group);
+    }
+    let mut splits: [Vec<usize>; 3] = Default::default();
+    for stratum_groups in by_stratum.values() {
+        for (split, part) in splits
+            .iter_mut()
+            .zip(split_groups(stratum_groups, &[8, 1, 1]))
+        {
+            split.extend(part);
+        }
+    }
+    splits
+}
+
+/// `k` folds of groups with balanced sample counts, each test split is one fold and the other
+/// folds are split 8:1 into train and val
+fn split_kfold(groups: &[Vec<usize>], k: usize) -> Vec<Partition> {
+    let mut order: Vec<usize> = (0..groups.len()).collect();
+    order.sort_by_key(|&g| std::cmp::Reverse(groups[g].len()));
+    let mut fold_of = vec![0; groups.len()];
+    let mut fold_sizes = vec![0; k];
+    for g in order {
+        let fold = (0..k).min_by_key(|&f| fold_sizes[f]).unwrap();
+        fold_of[g] = fold;
+        fold_sizes[fold] += groups[g].len();
+    }
+    (0..k)
+        .map(|fold| {
+            let (mut test, mut rest) = (Vec::new(), Vec::new());
+            for (group, group_fold) in groups.iter().zip(&fold_of) {
+                match *group_fold == fold {
+                    true => test.extend(group),
+                    false => rest.push(group),
+                }
+            }
+            let mut parts = split_groups(&rest, &[8, 1]).into_iter();
+            Partition {
+                name: Some(format!("fold{}", fold)),
+                splits: [parts.next().unwrap(), parts.next().unwrap(), test],
+            }
+        })
+        .collect()
+}
+
+/// Group key of each record, strings are used without quotes and records the key cannot be
+/// evaluated on are in the `null` group
+fn group_keys(records: &[Value], key: &Expr) -> Vec<String> {
+    key_values(records, key)
+        .into_iter()
+        .map(|value| match value {
+            Value::String(s) => s,
+            other => other.to_string(),
+        })
+        .collect()
+}
+
+/// Directory name for a group key
+fn partition_name(key: &str) -> String {
+    key.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || "._-".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// A partition per held-out group with the samples of the group in test and all others in train
+fn split_leave_out(records: &[Value], key: &Expr, leave_out: &[String]) -> Vec<Partition> {
+    let keys = group_keys(records, key);
+    let mut held_out: Vec<&str> = Vec::new();
+    for name in leave_out {
+        match name.as_str() {
+            "*" => held_out.extend(keys.iter().map(|k| k.as_str())),
+            name => held_out.push(name),
+        }
+    }
+    let mut seen = HashSet::new();
+    held_out.retain(|name| seen.insert(*name));
+    held_out
+        .into_iter()
+        .map(|name| {
+            let (test, train): (Vec<usize>, Vec<usize>) =
+                (0..keys.len()).partition(|&idx| keys[idx] == name);
+            if test.is_empty() {
+                eprintln!("No samples of {} to leave out", name);
+            }
+            Partition {
+                name: Some(partition_name(name)),
+                splits: [train, Vec::new(), test],
+            }
+        })
+        .collect()
+}
+
+/// 64-bit FNV-1a hash with the murmur3 finalizer to spread similar keys, which unlike the std
+/// hashers is stable across releases and platforms
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Split 8:1:1 by the hash of each sample's key modulo 10
+fn split_hash(records: &[Value], key: &Option<Expr>) -> [Vec<usize>; 3] {
+    let keys = match key {
+        Some(key) => group_keys(records, key),
+        None => records.iter().map(|record| record.to_string()).collect(),
+    };
+    let mut splits: [Vec<usize>; 3] = Default::default();
+    for (idx, key) in keys.iter().enumerate() {
+        let split = match stable_hash(key.as_bytes()) % 10 {
+            0..=7 => 0,
+            8 => 1,
+            _ => 2,
+        };
+        splits[split].push(idx);
+    }
+    splits
+}
+
+/// Assign samples to the train, val and test splits of one or more partitions. `to_record`
+/// is only called when the split depends on the content of the samples.
+pub fn assign_splits<T, F>(samples: &[T], to_record: F, opts: &SplitOptions) -> Vec<Partition>
+where
+    F: Fn(&T) -> Value,
+{
+    let records: Vec<Value> = match opts.needs_records() {
+        true => samples.iter().map(to_record).collect(),
+        false => Vec::new(),
+    };
+    if !opts.leave_out.is_empty() {
+        let repo = Expr::Field(vec!["repo".to_string()]);
+        return split_leave_out(
+            &records,
+            opts.group_by.as_ref().unwrap_or(&repo),
+            &opts.leave_out,
+        );
+    }
+    if opts.hash {
+        return vec![Partition {
+            name: None,
+            splits: split_hash(&records, &opts.group_by),
+        }];
+    }
+    let groups = groups(samples.len(), &records, &opts.group_by);
+    match opts.kfold {
+        Some(k) => split_kfold(&groups, k),
+        None => vec![Partition {
+            name: None,
+            splits: split_stratified(&groups, &strata(&records, &opts.stratify)),
+        }],
+    }
+}
@@ -0,0 +1,117 @@
+//! Sub-tokenization of the identifiers of token lists, `--subtokenize`. Identifiers are split at
+//! the boundaries of the styles asked for, `parseHttpResponse` into `parse`, `http` and
+//! `response` for camel case and `parse_http_response` alike for snake case, and their sub-tokens
+//! are lowercased. Other tokens such as literals, operators and masks are left as they are.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A style of identifiers whose words are split into sub-tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentifierStyle {
+    /// words starting with a capital letter, `parseHttpResponse` or `HTTPResponse`
+    Camel,
+    /// words separated by underscores, `parse_http_response` or `MAX_SIZE`
+    Snake,
+}
+
+impl FromStr for IdentifierStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "camel" => Ok(IdentifierStyle::Camel),
+            "snake" => Ok(IdentifierStyle::Snake),
+            _ => Err(format!("Unknown identifier style: {}", s)),
+        }
+    }
+}
+
+/// Splits the identifiers of token lists at the boundaries of some styles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subtokenizer {
+    camel: bool,
+    snake: bool,
+}
+
+impl Subtokenizer {
+    /// The subtokenizer splitting identifiers of `styles`, `None` if there are none
+    pub fn new(styles: &[IdentifierStyle]) -> Option<Self> {
+        match styles.is_empty() {
+            true => None,
+            false => Some(Subtokenizer {
+                camel: styles.contains(&IdentifierStyle::Camel),
+                snake: styles.contains(&IdentifierStyle::Snake),
+            }),
+        }
+    }
+
+    /// The sub-tokens of `token`, itself if it is not an identifier
+    pub fn split(self, token: &str) -> Vec<String> {
+        let is_identifier = token.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && token.chars().any(char::is_alphabetic)
+            && !token.starts_with(|c: char| c.is_ascii_digit());
+        if !is_identifier {
+            return vec![token.to_string()];
+        }
+        let words: Vec<&str> = match self.snake {
+            true => token.split('_').filter(|word| !word.is_empty()).collect(),
+            false => vec![token],
+        };
+        let mut subtokens = Vec::new();
+        for word in words {
+            match self.camel {
+                true => subtokens.extend(camel_words(word)),
+                false => subtokens.push(word),
+            }
+        }
+        subtokens.iter().map(|word| word.to_lowercase()).collect()
+    }
+
+    /// `tokens` with their identifiers split
+    pub fn apply(self, tokens: &[String]) -> Vec<String> {
+        tokens.iter().flat_map(|token| self.split(token)).collect()
+    }
+
+    /// `tokens` with their identifiers split, and `indices` of tokens moved to the first
+    /// sub-token of each
+    pub fn apply_indexed(self, tokens: &[String], indices: &[usize]) -> (Vec<String>, Vec<usize>) {
+        let mut subtokens = Vec::new();
+        let mut starts = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            starts.push(subtokens.len());
+            subtokens.extend(self.split(token));
+        }
+        let indices = indices
+            .iter()
+            .filter_map(|index| starts.get(*index).copied())
+            .collect();
+        (subtokens, indices)
+    }
+}
+
+/// The words of a camel case identifier. A word starts at a capital letter after a small letter
+/// or digit, and at the last capital letter of a run followed by a small letter, so that
+/// `HTTPResponse` is `HTTP` and `Response`.
+fn camel_words(identifier: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = identifier.char_indices().collect();
+    let mut words = Vec::new();
+    let mut start = 0;
+    for i in 1..chars.len() {
+        let (prev, (offset, c)) = (chars[i - 1].1, chars[i]);
+        let next_is_lower = chars
+            .get(i + 1)
+            .is_some_and(|(_, next)| next.is_lowercase());
+        let is_boundary = c.is_uppercase()
+            && (prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower));
+        if is_boundary {
+            words.push(&identifier[start..offset]);
+            start = offset;
+        }
+    }
+    words.push(&identifier[start..]);
+    words
+}
@@ -0,0 +1,250 @@
+//! Extraction tasks defined in a TOML file instead of Rust, selected with `--task-file`. A task
+//! file holds a tree-sitter query per language and the capture each output field is taken from:
+//!
+//! ```toml
+//! name = "events"
+//!
+//! [queries]
+//! solidity = "(event_definition name: (identifier) @name) @event"
+//!
+//! [fields]
+//! name = "name"
+//! code = { capture = "event", post = [{ mask = "name" }] }
+//! comment = { capture = "doc", post = ["strip-comment-markers", "trim"] }
+//! ```
+//!
+//! Every match of the query of the language of a file is a sample with the fields in the order
+//! of the task file. A capture of several nodes yields their texts joined by newlines, a capture
+//! without a node an empty string. The text of a capture is cleaned by the post-processors of
+//! its field, which are applied in order:
+//!
+//! - `trim` removes leading and trailing whitespace
+//! - `strip-comment-markers` removes `//`, `///`, `/*`, `*/`, leading `*` and `#` of each line
+//! - `collapse-ws` replaces runs of whitespace by a single space
+//! - `normalize-indent` removes the indentation the capture had in its file and indents its lines
+//!   by levels of four spaces
+//! - `lowercase`
+//! - `{ mask = "capture" }` replaces the words equal to the text of another capture of the match
+//!   by the function call mask, e.g. the name of a called function
+
+use crate::analysis::LanguageSpec;
+use crate::error::{Error, Result};
+use crate::extract::{Extractor, FileCtx};
+use crate::layout::CodeLayout;
+use crate::{node_text, DataSample, FUNC_CALL_ID_MASK};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fs;
+use tree_sitter::{Query, QueryCursor, QueryMatch};
+
+/// A task file as written
+#[derive(Debug, Deserialize)]
+struct TaskFile {
+    name: String,
+    /// query by language name
+    queries: BTreeMap<String, String>,
+    /// capture name or `FieldSpec` by output field
+    fields: toml::value::Table,
+}
+
+/// The capture of an output field, and the post-processors of its text
+#[derive(Debug, Deserialize)]
+struct FieldSpec {
+    capture: String,
+    #[serde(default)]
+    post: Vec<PostProcessor>,
+}
+
+/// A post-processor as written, its name or a table of its argument
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PostSpec {
+    Name(String),
+    Mask { mask: String },
+}
+
+/// A cleaning step of the text of a capture
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "PostSpec")]
+enum PostProcessor {
+    Trim,
+    StripCommentMarkers,
+    CollapseWs,
+    NormalizeIndent,
+    Lowercase,
+    /// capture whose text is masked
+    Mask(String),
+}
+
+impl TryFrom<PostSpec> for PostProcessor {
+    type Error = String;
+
+    fn try_from(spec: PostSpec) -> std::result::Result<Self, Self::Error> {
+        match spec {
+            PostSpec::Name(name) => match name.as_str() {
+                "trim" => Ok(PostProcessor::Trim),
+                "strip-comment-markers" => Ok(PostProcessor::StripCommentMarkers),
+                "collapse-ws" => Ok(PostProcessor::CollapseWs),
+                "normalize-indent" => Ok(PostProcessor::NormalizeIndent),
+                "lowercase" => Ok(PostProcessor::Lowercase),
+                _ => Err(format!("unknown post-processor {}", name)),
+            },
+            PostSpec::Mask { mask } => Ok(PostProcessor::Mask(mask)),
+        }
+    }
+}
+
+impl PostProcessor {
+    /// Apply to `text`, `capture_text` is the text of a capture of the same match
+    fn apply(&self, text: String, capture_text: &dyn Fn(&str) -> String) -> String {
+        match self {
+            PostProcessor::Trim => text.trim().to_string(),
+            PostProcessor::StripCommentMarkers => text
+                .lines()
+                .map(|line| {
+                    let line = line.trim();
+                    let line = line.strip_suffix("*/").unwrap_or(line);
+                    let line = ["/**", "/*", "///", "//", "*", "#"]
+                        .iter()
+                        .find_map(|marker| line.strip_prefix(marker))
+                        .unwrap_or(line);
+                    line.trim()
+                })
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            PostProcessor::CollapseWs => CodeLayout::Collapsed.apply(&text),
+            PostProcessor::NormalizeIndent => CodeLayout::NormalizedIndent.apply(&text),
+            PostProcessor::Lowercase => text.to_lowercase(),
+            PostProcessor::Mask(capture) => {
+                let masked = capture_text(capture);
+                if masked.is_empty() {
+                    return text;
+                }
+                let re = Regex::new(&format!(r"\b{}\b", regex::escape(&masked))).unwrap();
+                re.replace_all(&text, FUNC_CALL_ID_MASK).to_string()
+            }
+        }
+    }
+
+    /// The capture the post-processor reads, besides the capture of its field
+    fn capture(&self) -> Option<&str> {
+        match self {
+            PostProcessor::Mask(capture) => Some(capture),
+            _ => None,
+        }
+    }
+}
+
+/// An output field of a task
+struct Field {
+    name: String,
+    capture: String,
+    post: Vec<PostProcessor>,
+}
+
+/// An extraction task defined by a task file
+pub struct QueryTask {
+    pub name: String,
+    fields: Vec<Field>,
+    /// query by language name
+    queries: HashMap<&'static str, Query>,
+}
+
+impl QueryTask {
+    /// Load and check a task file, the queries are compiled up front so that mistakes are
+    /// reported before any file is extracted
+    pub fn load(path: &str) -> Result<Self> {
+        let invalid =
+            |message: String| Error::Task(format!("invalid task file {}: {}", path, message));
+        let content = fs::read_to_string(path)?;
+        let task: TaskFile = toml::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        let mut fields = Vec::new();
+        for (name, spec) in task.fields {
+            let FieldSpec { capture, post } = match spec {
+                toml::Value::String(capture) => FieldSpec {
+                    capture,
+                    post: Vec::new(),
+                },
+                spec => spec
+                    .try_into()
+                    .map_err(|e| invalid(format!("field {}: {}", name, e)))?,
+            };
+            fields.push(Field {
+                name,
+                capture,
+                post,
+            });
+        }
+        if fields.is_empty() {
+            return Err(invalid("no fields".to_string()));
+        }
+        let mut queries = HashMap::new();
+        for (lang, source) in &task.queries {
+            let spec = LanguageSpec::by_name(lang)
+                .ok_or_else(|| invalid(format!("unknown language {}", lang)))?;
+            let query = Query::new(spec.language, source)
+                .map_err(|e| invalid(format!("query of {}: {}", lang, e)))?;
+            let captures = fields.iter().flat_map(|field| {
+                let post_captures = field.post.iter().filter_map(|post| post.capture());
+                std::iter::once(field.capture.as_str()).chain(post_captures)
+            });
+            for capture in captures {
+                if !query.capture_names().iter().any(|name| name == capture) {
+                    let message = format!("query of {} has no capture @{}", lang, capture);
+                    return Err(invalid(message));
+                }
+            }
+            queries.insert(spec.name, query);
+        }
+        Ok(QueryTask {
+            name: task.name,
+            fields,
+            queries,
+        })
+    }
+}
+
+impl Extractor for QueryTask {
+    fn extract(&self, code: &str, ctx: &FileCtx) -> Result<Vec<DataSample>> {
+        let query = match self.queries.get(ctx.spec.name) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, ctx.tree.root_node(), |_| code.as_bytes());
+        let samples = matches
+            .map(|m| {
+                let capture_text = |capture: &str| capture_text(query, &m, capture, code);
+                let fields = self.fields.iter().map(|field| {
+                    let text = field
+                        .post
+                        .iter()
+                        .fold(capture_text(&field.capture), |text, post| {
+                            post.apply(text, &capture_text)
+                        });
+                    (field.name.clone(), text)
+                });
+                DataSample::Fields(fields.collect())
+            })
+            .collect();
+        Ok(samples)
+    }
+}
+
+/// The texts of the nodes of a capture of a match joined by newlines, the capture was checked to
+/// exist when the task was loaded
+fn capture_text(query: &Query, m: &QueryMatch, capture: &str, code: &str) -> String {
+    let idx = query
+        .capture_names()
+        .iter()
+        .position(|name| name == capture)
+        .unwrap() as u32;
+    m.captures
+        .iter()
+        .filter(|c| c.index == idx)
+        .map(|c| node_text(c.node, code))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
@@ -0,0 +1,95 @@
+//! Vocabulary statistics of the splits of a dataset, `--vocab`. The tokens of a record are those
+//! of its token lists, or the whitespace separated words of its text fields if it has none, and
+//! their sub-tokens are the lowercase words of their camel and snake case identifiers. The
+//! frequencies of both are written as a `vocab.tsv` per split, with the columns `kind`, `token`
+//! and `count`, most frequent first.
+
+use crate::subtoken::{IdentifierStyle, Subtokenizer};
+use crate::write_metadata_file;
+use crate::OutputOptions;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+
+/// Suffix of the fields of records holding token lists
+const TOKENS_SUFFIX: &str = "_tokens";
+
+/// Token and sub-token frequencies of some records
+pub(crate) struct Vocab {
+    subtokenizer: Subtokenizer,
+    tokens: HashMap<String, usize>,
+    subtokens: HashMap<String, usize>,
+}
+
+impl Vocab {
+    pub(crate) fn new() -> Self {
+        Vocab {
+            subtokenizer: Subtokenizer::new(&[IdentifierStyle::Camel, IdentifierStyle::Snake])
+                .unwrap(),
+            tokens: HashMap::new(),
+            subtokens: HashMap::new(),
+        }
+    }
+
+    fn add_token(&mut self, token: &str) {
+        *self.tokens.entry(token.to_string()).or_default() += 1;
+        for subtoken in self.subtokenizer.split(token) {
+            *self.subtokens.entry(subtoken).or_default() += 1;
+        }
+    }
+
+    /// Count the tokens of a json record
+    pub(crate) fn add(&mut self, record: &Value) {
+        let fields = match record {
+            Value::Object(fields) => fields,
+            _ => return,
+        };
+        let token_lists: Vec<&Vec<Value>> = fields
+            .iter()
+            .filter(|(name, _)| name.ends_with(TOKENS_SUFFIX))
+            .filter_map(|(_, value)| value.as_array())
+            .collect();
+        if token_lists.is_empty() {
+            for text in fields.values().filter_map(Value::as_str) {
+                text.split_whitespace()
+                    .for_each(|word| self.add_token(word));
+            }
+            return;
+        }
+        for token in token_lists.into_iter().flatten().filter_map(Value::as_str) {
+            self.add_token(token);
+        }
+    }
+
+    /// Write the frequency tables as a tsv file at `path`
+    pub(crate) fn write(&self, path: &str, opts: &OutputOptions) -> io::Result<()> {
+        let mut tsv = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_writer(Vec::new());
+        tsv.write_record(["kind", "token", "count"])?;
+        for (kind, counts) in [("token", &self.tokens), ("subtoken", &self.subtokens)] {
+            let mut counts: Vec<(&String, &usize)> = counts.iter().collect();
+            counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+            for (token, count) in counts {
+                tsv.write_record([kind, token.as_str(), &count.to_string()])?;
+            }
+        }
+        let content = tsv
+            .into_inner()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        write_metadata_file(path, &content, opts)
+    }
+}
+
+/// Path of the vocabulary of the split written to `split_path`, `vocab.tsv` in the directory of
+/// the split in the HuggingFace layout and `<split>.vocab.tsv` next to it otherwise
+pub(crate) fn vocab_path(split: &str, split_path: &str, opts: &OutputOptions) -> String {
+    let dir = match split_path.rfind('/') {
+        Some(end) => &split_path[..end],
+        None => ".",
+    };
+    match opts.hf_layout {
+        true => format!("{}/vocab.tsv", dir),
+        false => format!("{}/{}.vocab.tsv", dir, split),
+    }
+}
@@ -0,0 +1,937 @@
+//! Output formats of generated datasets, and the writer which encodes records into sharded
+//! output files. Each format is a `DatasetWriter`, so adding a format only touches this module.
+
+use crate::{
+    check_fields, check_overwrite, existing_outputs, project_fields, shard_path, split_record,
+    tmp_path, OutputOptions,
+};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+/// File format of generated datasets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Jsonl,
+    /// Gzip compressed jsonl file
+    #[serde(rename = "jsonl.gz")]
+    JsonlGz,
+    Csv,
+    Tsv,
+    /// Arrow IPC (Feather v2) file
+    #[cfg(feature = "arrow")]
+    Arrow,
+    /// Parquet file
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// Stream of MessagePack maps, or arrays for tuple records
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+    /// TFRecord file of `tf.train.Example` protos
+    #[cfg(feature = "tfrecord")]
+    TfRecord,
+    /// SQLite database with a `samples` table
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "jsonl.gz" => Ok(OutputFormat::JsonlGz),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            #[cfg(feature = "arrow")]
+            "arrow" => Ok(OutputFormat::Arrow),
+            #[cfg(not(feature = "arrow"))]
+            "arrow" => Err("arrow output requires the `arrow` feature".to_string()),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("parquet output requires the `parquet` feature".to_string()),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Ok(OutputFormat::MsgPack),
+            #[cfg(not(feature = "msgpack"))]
+            "msgpack" => Err("msgpack output requires the `msgpack` feature".to_string()),
+            #[cfg(feature = "tfrecord")]
+            "tfrecord" => Ok(OutputFormat::TfRecord),
+            #[cfg(not(feature = "tfrecord"))]
+            "tfrecord" => Err("tfrecord output requires the `tfrecord` feature".to_string()),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => Err("sqlite output requires the `sqlite` feature".to_string()),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::JsonlGz => "jsonl.gz",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            #[cfg(feature = "arrow")]
+            OutputFormat::Arrow => "arrow",
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => "parquet",
+            #[cfg(feature = "msgpack")]
+            OutputFormat::MsgPack => "msgpack",
+            #[cfg(feature = "tfrecord")]
+            OutputFormat::TfRecord => "tfrecord",
+            #[cfg(feature = "sqlite")]
+            OutputFormat::Sqlite => "sqlite",
+        }
+    }
+
+    /// Whether records can be appended to a finished file of the format
+    pub fn is_appendable(&self) -> bool {
+        match self {
+            // gzip members can be concatenated
+            OutputFormat::Jsonl | OutputFormat::JsonlGz => true,
+            #[cfg(feature = "msgpack")]
+            OutputFormat::MsgPack => true,
+            #[cfg(feature = "tfrecord")]
+            OutputFormat::TfRecord => true,
+            _ => false,
+        }
+    }
+}
+
+/// Output path which writes to stdout instead of a file
+pub const STDOUT_PATH: &str = "-";
+
+/// Destination of an output file
+pub enum OutputSink {
+    File(File),
+    Stdout(io::Stdout),
+}
+
+impl Write for OutputSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputSink::File(file) => file.write(buf),
+            OutputSink::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputSink::File(file) => file.flush(),
+            OutputSink::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+/// A record of a dataset as field names and values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub columns: Vec<String>,
+    pub values: Vec<Value>,
+    /// whether the record was serialized from a struct or map rather than a sequence such as a
+    /// tuple, sequences are written as arrays by the json based formats
+    pub is_object: bool,
+}
+
+impl Record {
+    /// The record of a sample, `columns` names the fields of samples serialized as sequences
+    pub fn new<T: Serialize>(sample: &T, columns: Option<&[String]>) -> serde_json::Result<Self> {
+        let value = serde_json::to_value(sample)?;
+        let is_object = value.is_object();
+        let (mut record_columns, values) = split_record(value);
+        if let (false, Some(columns)) = (is_object, columns) {
+            record_columns = columns.to_vec();
+        }
+        Ok(Record {
+            columns: record_columns,
+            values,
+            is_object,
+        })
+    }
+
+    /// Keep only the given fields, in the given order
+    pub fn project(self, fields: &Option<Vec<String>>) -> io::Result<Self> {
+        let (columns, values) = project_fields(self.columns, self.values, fields)?;
+        Ok(Record {
+            columns,
+            values,
+            is_object: self.is_object,
+        })
+    }
+
+    /// The record as a json object keyed by field name
+    pub fn to_object(&self) -> serde_json::Map<String, Value> {
+        self.columns
+            .iter()
+            .cloned()
+            .zip(self.values.iter().cloned())
+            .collect()
+    }
+
+    /// The record as it is written by the json based formats
+    fn to_json(&self) -> Value {
+        match self.is_object {
+            true => Value::Object(self.to_object()),
+            false => Value::Array(self.values.clone()),
+        }
+    }
+}
+
+/// Encoder of the records of one output file
+pub trait DatasetWriter: Send {
+    fn write_record(&mut self, record: &Record) -> io::Result<()>;
+
+    /// Write any buffered records and trailers, returning the underlying output
+    fn finish(self: Box<Self>) -> io::Result<BufWriter<OutputSink>>;
+}
+
+/// The writer of an output format
+pub fn dataset_writer(format: OutputFormat, out: BufWriter<OutputSink>) -> Box<dyn DatasetWriter> {
+    match format {
+        OutputFormat::Jsonl => Box::new(JsonlWriter { out }),
+        OutputFormat::JsonlGz => Box::new(JsonlGzWriter {
+            out: GzEncoder::new(out, flate2::Compression::default()),
+        }),
+        OutputFormat::Csv => Box::new(CsvWriter::new(out, b',')),
+        OutputFormat::Tsv => Box::new(CsvWriter::new(out, b'\t')),
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => Box::new(arrow_ipc::ArrowWriter::new(out, false)),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => Box::new(arrow_ipc::ArrowWriter::new(out, true)),
+        #[cfg(feature = "msgpack")]
+        OutputFormat::MsgPack => Box::new(MsgPackWriter { out }),
+        #[cfg(feature = "tfrecord")]
+        OutputFormat::TfRecord => Box::new(tfrecord::TfRecordWriter { out }),
+        #[cfg(feature = "sqlite")]
+        OutputFormat::Sqlite => Box::new(sqlite::SqliteWriter::new(out)),
+    }
+}
+
+struct JsonlWriter {
+    out: BufWriter<OutputSink>,
+}
+
+impl DatasetWriter for JsonlWriter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &record.to_json())?;
+        self.out.write_all(b"\n")
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<BufWriter<OutputSink>> {
+        Ok(self.out)
+    }
+}
+
+struct JsonlGzWriter {
+    out: GzEncoder<BufWriter<OutputSink>>,
+}
+
+impl DatasetWriter for JsonlGzWriter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &record.to_json())?;
+        self.out.write_all(b"\n")
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<BufWriter<OutputSink>> {
+        self.out.finish()
+    }
+}
+
+/// Render a json value as a csv cell, nested values are kept as json text
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Csv or tsv file with a header of the field names of the first record
+struct CsvWriter {
+    out: csv::Writer<BufWriter<OutputSink>>,
+    has_header: bool,
+}
+
+impl CsvWriter {
+    fn new(out: BufWriter<OutputSink>, delimiter: u8) -> Self {
+        CsvWriter {
+            out: csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(out),
+            has_header: false,
+        }
+    }
+}
+
+impl DatasetWriter for CsvWriter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        if !self.has_header {
+            self.out.write_record(&record.columns)?;
+            self.has_header = true;
+        }
+        self.out.write_record(record.values.iter().map(csv_cell))?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<BufWriter<OutputSink>> {
+        self.out.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+/// Stream of MessagePack maps, or arrays for records serialized from sequences
+#[cfg(feature = "msgpack")]
+struct MsgPackWriter {
+    out: BufWriter<OutputSink>,
+}
+
+#[cfg(feature = "msgpack")]
+impl DatasetWriter for MsgPackWriter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        match record.is_object {
+            true => rmp_serde::encode::write_named(&mut self.out, &record.to_object()),
+            false => rmp_serde::encode::write(&mut self.out, &record.values),
+        }
+        .map_err(io::Error::other)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<BufWriter<OutputSink>> {
+        Ok(self.out)
+    }
+}
+
+/// Writes records into temporary files which are renamed into place by `finish`.
+/// When `shard_size` is set, a new shard is started every `shard_size` records.
+/// Records are streamed to stdout without sharding if the path is `-`.
+pub struct OutputFileWriter {
+    path: String,
+    opts: OutputOptions,
+    /// records are appended to the file at `path` rather than written into a temporary file
+    append: bool,
+    writer: Option<Box<dyn DatasetWriter>>,
+    records_in_shard: usize,
+    tmp_files: Vec<String>,
+    /// field names of records serialized as sequences (e.g. tuples)
+    columns: Option<Vec<String>>,
+}
+
+impl OutputFileWriter {
+    pub fn create(path: &str, opts: &OutputOptions) -> io::Result<Self> {
+        let mut opts = opts.clone();
+        match path {
+            STDOUT_PATH => opts.shard_size = None,
+            _ => check_overwrite(&[path.to_string()], &opts)?,
+        }
+        let mut writer = OutputFileWriter {
+            path: path.to_string(),
+            opts,
+            append: false,
+            writer: None,
+            records_in_shard: 0,
+            tmp_files: Vec::new(),
+            columns: None,
+        };
+        writer.open_next()?;
+        Ok(writer)
+    }
+
+    /// Append records to an existing file of an appendable format, without sharding
+    pub fn open_append(path: &str, opts: &OutputOptions) -> io::Result<Self> {
+        let mut opts = opts.clone();
+        opts.shard_size = None;
+        let mut writer = OutputFileWriter {
+            path: path.to_string(),
+            opts,
+            append: true,
+            writer: None,
+            records_in_shard: 0,
+            tmp_files: Vec::new(),
+            columns: None,
+        };
+        writer.open_next()?;
+        Ok(writer)
+    }
+
+    /// Use the given column names for records serialized as sequences (e.g. tuples)
+    pub fn set_columns(&mut self, columns: &[&str]) {
+        self.columns = Some(columns.iter().map(|c| c.to_string()).collect());
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        self.close_current()?;
+        let sink = match self.path.as_str() {
+            STDOUT_PATH => OutputSink::Stdout(io::stdout()),
+            path if self.append => {
+                OutputSink::File(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            _ => {
+                let tmp = match self.opts.shard_size {
+                    Some(_) => tmp_path(&format!("{}-{:05}", self.path, self.tmp_files.len())),
+                    None => tmp_path(&self.path),
+                };
+                let file = File::create(&tmp)?;
+                self.tmp_files.push(tmp);
+                OutputSink::File(file)
+            }
+        };
+        self.writer = Some(dataset_writer(self.opts.format, BufWriter::new(sink)));
+        self.records_in_shard = 0;
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let sink = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+            if let (OutputSink::File(file), true) = (sink, self.opts.fsync) {
+                file.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn append_record(&mut self, record: Record) -> io::Result<()> {
+        if let Some(shard_size) = self.opts.shard_size {
+            if self.records_in_shard >= shard_size {
+                self.open_next()?;
+            }
+        }
+        // the records of an output all have the same fields, which are checked before the first
+        // is written
+        if self.records_in_shard == 0 {
+            check_fields(&record.columns, &self.opts.fields)
+                .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+        }
+        let record = record.project(&self.opts.fields)?;
+        self.writer.as_mut().unwrap().write_record(&record)?;
+        self.records_in_shard += 1;
+        Ok(())
+    }
+
+    pub fn append_one<T: Serialize>(&mut self, sample: &T) -> io::Result<()> {
+        let record = Record::new(sample, self.columns.as_deref())?;
+        self.append_record(record)
+    }
+
+    pub fn append<T: Serialize>(&mut self, samples: &[T]) -> io::Result<()> {
+        for sample in samples {
+            self.append_one(sample)?;
+        }
+        Ok(())
+    }
+
+    /// Flush and rename all temporary files to their final names, returning the written paths.
+    /// Shards of an earlier output which are not replaced, as it had more shards, are removed.
+    pub fn finish(mut self) -> io::Result<Vec<String>> {
+        self.close_current()?;
+        if self.path == STDOUT_PATH || self.append {
+            return Ok(vec![self.path.clone()]);
+        }
+        let replaced = existing_outputs(&self.path, &self.opts);
+        let tmp_files = std::mem::take(&mut self.tmp_files);
+        let num_shards = tmp_files.len();
+        let mut paths = Vec::new();
+        for (idx, tmp) in tmp_files.iter().enumerate() {
+            let path = match self.opts.shard_size {
+                Some(_) => shard_path(&self.path, idx, num_shards),
+                None => self.path.clone(),
+            };
+            fs::rename(tmp, &path)?;
+            paths.push(path);
+        }
+        for stale in replaced.iter().filter(|path| !paths.contains(path)) {
+            fs::remove_file(stale)?;
+        }
+        Ok(paths)
+    }
+}
+
+/// The temporary files of a writer dropped before `finish`, such as after an error, are removed
+impl Drop for OutputFileWriter {
+    fn drop(&mut self) {
+        for tmp in &self.tmp_files {
+            let _ = fs::remove_file(tmp);
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+mod arrow_ipc {
+    use super::{DatasetWriter, OutputSink, Record};
+    use arrow::array::{
+        ArrayRef, BooleanArray, Float64Array, Int64Array, ListBuilder, StringArray, StringBuilder,
+    };
+    use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+    use arrow::error::ArrowError;
+    use arrow::ipc::writer::FileWriter;
+    use arrow::record_batch::RecordBatch;
+    use serde_json::Value;
+    use std::io::{BufWriter, Error, Write};
+    use std::sync::Arc;
+
+    fn to_io_error(e: ArrowError) -> Error {
+        Error::other(e)
+    }
+
+    /// Column type taken from the first non-null value, nested values other than string
+    /// lists are stored as json text
+    fn infer_type(rows: &[Vec<Value>], col: usize) -> DataType {
+        for row in rows {
+            return match &row[col] {
+                Value::Null => continue,
+                Value::Bool(_) => DataType::Boolean,
+                Value::Number(n) if n.is_i64() => DataType::Int64,
+                Value::Number(_) => DataType::Float64,
+                Value::Array(items) if items.iter().all(|v| v.is_string()) => {
+                    DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+                }
+                _ => DataType::Utf8,
+            };
+        }
+        DataType::Utf8
+    }
+
+    fn build_column(rows: &[Vec<Value>], col: usize, data_type: &DataType) -> ArrayRef {
+        match data_type {
+            DataType::Boolean => Arc::new(
+                rows.iter()
+                    .map(|r| r[col].as_bool())
+                    .collect::<BooleanArray>(),
+            ),
+            DataType::Int64 => {
+                Arc::new(rows.iter().map(|r| r[col].as_i64()).collect::<Int64Array>())
+            }
+            DataType::Float64 => Arc::new(
+                rows.iter()
+                    .map(|r| r[col].as_f64())
+                    .collect::<Float64Array>(),
+            ),
+            DataType::List(_) => {
+                let mut builder = ListBuilder::new(StringBuilder::new());
+                for row in rows {
+                    match &row[col] {
+                        Value::Array(items) => {
+                            for item in items {
+                                builder.values().append_value(item.as_str().unwrap_or(""));
+                            }
+                            builder.append(true);
+                        }
+                        _ => builder.append(false),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            _ => Arc::new(
+                rows.iter()
+                    .map(|r| match &r[col] {
+                        Value::Null => None,
+                        Value::String(s) => Some(s.clone()),
+                        other => Some(other.to_string()),
+                    })
+                    .collect::<StringArray>(),
+            ),
+        }
+    }
+
+    /// Schema inferred from the rows, and the rows as a single record batch if there are any
+    fn record_batch(
+        columns: &[String],
+        rows: &[Vec<Value>],
+    ) -> std::io::Result<(SchemaRef, Option<RecordBatch>)> {
+        let types: Vec<DataType> = (0..columns.len()).map(|i| infer_type(rows, i)).collect();
+        let fields: Vec<Field> = columns
+            .iter()
+            .zip(&types)
+            .map(|(name, data_type)| Field::new(name, data_type.clone(), true))
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        if rows.is_empty() {
+            return Ok((schema, None));
+        }
+        let arrays = types
+            .iter()
+            .enumerate()
+            .map(|(i, data_type)| build_column(rows, i, data_type))
+            .collect();
+        let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(to_io_error)?;
+        Ok((schema, Some(batch)))
+    }
+
+    /// Write all rows as a single record batch of an Arrow IPC file
+    pub fn write_ipc<W: Write>(
+        writer: W,
+        columns: &[String],
+        rows: &[Vec<Value>],
+    ) -> std::io::Result<()> {
+        let (schema, batch) = record_batch(columns, rows)?;
+        let mut ipc_writer = FileWriter::try_new(writer, &schema).map_err(to_io_error)?;
+        if let Some(batch) = batch {
+            ipc_writer.write(&batch).map_err(to_io_error)?;
+        }
+        ipc_writer.finish().map_err(to_io_error)
+    }
+
+    /// Write all rows as a single row group of a Parquet file
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<W: Write + Send>(
+        writer: W,
+        columns: &[String],
+        rows: &[Vec<Value>],
+    ) -> std::io::Result<()> {
+        let (schema, batch) = record_batch(columns, rows)?;
+        let mut parquet_writer =
+            parquet::arrow::ArrowWriter::try_new(writer, schema, None).map_err(Error::other)?;
+        if let Some(batch) = batch {
+            parquet_writer.write(&batch).map_err(Error::other)?;
+        }
+        parquet_writer.close().map_err(Error::other)?;
+        Ok(())
+    }
+
+    /// Buffers the records of a shard, which is written as a single batch when finished
+    pub struct ArrowWriter {
+        out: BufWriter<OutputSink>,
+        parquet: bool,
+        /// field names of the first record, used as the schema
+        columns: Option<Vec<String>>,
+        rows: Vec<Vec<Value>>,
+    }
+
+    impl ArrowWriter {
+        pub fn new(out: BufWriter<OutputSink>, parquet: bool) -> Self {
+            ArrowWriter {
+                out,
+                parquet,
+                columns: None,
+                rows: Vec::new(),
+            }
+        }
+    }
+
+    impl DatasetWriter for ArrowWriter {
+        fn write_record(&mut self, record: &Record) -> std::io::Result<()> {
+            self.columns.get_or_insert_with(|| record.columns.clone());
+            self.rows.push(record.values.clone());
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> std::io::Result<BufWriter<OutputSink>> {
+            let columns = self.columns.take().unwrap_or_default();
+            match self.parquet {
+                #[cfg(feature = "parquet")]
+                true => write_parquet(&mut self.out, &columns, &self.rows)?,
+                _ => write_ipc(&mut self.out, &columns, &self.rows)?,
+            }
+            Ok(self.out)
+        }
+    }
+}
+
+#[cfg(feature = "tfrecord")]
+mod tfrecord {
+    use super::{DatasetWriter, OutputSink, Record};
+    use serde_json::Value;
+    use std::io::{self, BufWriter, Write};
+
+    pub struct TfRecordWriter {
+        pub out: BufWriter<OutputSink>,
+    }
+
+    impl DatasetWriter for TfRecordWriter {
+        fn write_record(&mut self, record: &Record) -> io::Result<()> {
+            let example = encode_example(&record.columns, &record.values)?;
+            let mut framed = Vec::with_capacity(example.len() + 16);
+            write_record(&mut framed, &example);
+            self.out.write_all(&framed)
+        }
+
+        fn finish(self: Box<Self>) -> io::Result<BufWriter<OutputSink>> {
+            Ok(self.out)
+        }
+    }
+
+    fn crc32c(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = match crc & 1 {
+                    1 => (crc >> 1) ^ 0x82f6_3b78,
+                    _ => crc >> 1,
+                };
+            }
+        }
+        !crc
+    }
+
+    fn masked_crc32c(data: &[u8]) -> u32 {
+        let crc = crc32c(data);
+        crc.rotate_right(15).wrapping_add(0xa282_ead8)
+    }
+
+    fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    /// Append a length-delimited protobuf field
+    fn put_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        put_varint(buf, field << 3 | 2);
+        put_varint(buf, bytes.len() as u64);
+        buf.extend_from_slice(bytes);
+    }
+
+    fn value_bytes(value: &Value) -> Vec<u8> {
+        match value {
+            Value::String(s) => s.as_bytes().to_vec(),
+            Value::Null => vec![],
+            other => other.to_string().into_bytes(),
+        }
+    }
+
+    /// Encode a json value as a `tf.train.Feature`: strings become `bytes_list`, booleans and
+    /// integers `int64_list`, floats `float_list`, anything else is stored as json bytes. An
+    /// error for integers above `i64::MAX`, which an `int64_list` cannot hold.
+    fn encode_feature(value: &Value) -> io::Result<Vec<u8>> {
+        let items: Vec<&Value> = match value {
+            Value::Array(items) => items.iter().collect(),
+            other => vec![other],
+        };
+        let mut list = Vec::new();
+        let mut feature = Vec::new();
+        if items
+            .iter()
+            .all(|v| v.is_boolean() || v.is_i64() || v.is_u64())
+            && !items.is_empty()
+        {
+            let mut packed = Vec::new();
+            for item in &items {
+                let int = match item {
+                    Value::Bool(b) => *b as i64,
+                    other => other.as_i64().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} does not fit the int64 of a TFRecord feature", other),
+                        )
+                    })?,
+                };
+                put_varint(&mut packed, int as u64);
+            }
+            put_bytes_field(&mut list, 1, &packed);
+            put_bytes_field(&mut feature, 3, &list);
+        } else if items.iter().all(|v| v.is_number()) && !items.is_empty() {
+            let mut packed = Vec::new();
+            for item in &items {
+                packed.extend_from_slice(&(item.as_f64().unwrap() as f32).to_le_bytes());
+            }
+            put_bytes_field(&mut list, 1, &packed);
+            put_bytes_field(&mut feature, 2, &list);
+        } else {
+            for item in &items {
+                put_bytes_field(&mut list, 1, &value_bytes(item));
+            }
+            put_bytes_field(&mut feature, 1, &list);
+        }
+        Ok(feature)
+    }
+
+    /// Encode a record as a serialized `tf.train.Example`
+    fn encode_example(columns: &[String], values: &[Value]) -> io::Result<Vec<u8>> {
+        let mut features = Vec::new();
+        for (column, value) in columns.iter().zip(values) {
+            let feature = encode_feature(value)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", column, e)))?;
+            let mut entry = Vec::new();
+            put_bytes_field(&mut entry, 1, column.as_bytes());
+            put_bytes_field(&mut entry, 2, &feature);
+            put_bytes_field(&mut features, 1, &entry);
+        }
+        let mut example = Vec::new();
+        put_bytes_field(&mut example, 1, &features);
+        Ok(example)
+    }
+
+    /// Frame a record as `length, masked crc of length, data, masked crc of data`
+    fn write_record(buf: &mut Vec<u8>, data: &[u8]) {
+        let len = (data.len() as u64).to_le_bytes();
+        buf.extend_from_slice(&len);
+        buf.extend_from_slice(&masked_crc32c(&len).to_le_bytes());
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(&masked_crc32c(data).to_le_bytes());
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serde_json::json;
+
+        #[test]
+        fn crc32c_check_value() {
+            assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        }
+
+        #[test]
+        fn int_record_framed() {
+            let example = encode_example(&["a".to_string()], &[json!(1)]).unwrap();
+            let mut framed = Vec::new();
+            write_record(&mut framed, &example);
+            #[rustfmt::skip]
+            let expected = [
+                // length and its masked crc
+                0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc5, 0xe5, 0x69, 0x3f,
+                // Example { features { feature { key: "a" value { int64_list { value: 1 } } } } }
+                0x0a, 0x0c, 0x0a, 0x0a, 0x0a, 0x01, 0x61, 0x12, 0x05, 0x1a, 0x03, 0x0a, 0x01, 0x01,
+                // masked crc of the example
+                0x39, 0xe8, 0x78, 0x50,
+            ];
+            assert_eq!(framed, expected);
+        }
+
+        #[test]
+        fn string_and_float_features() {
+            assert_eq!(
+                encode_feature(&json!("x")).unwrap(),
+                [0x0a, 0x03, 0x0a, 0x01, b'x']
+            );
+            assert_eq!(
+                encode_feature(&json!([0.5])).unwrap(),
+                [0x12, 0x06, 0x0a, 0x04, 0x00, 0x00, 0x00, 0x3f]
+            );
+        }
+
+        #[test]
+        fn int_above_i64_rejected() {
+            let e = encode_example(&["a".to_string()], &[json!(u64::MAX)]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().starts_with("a: 18446744073709551615"));
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{DatasetWriter, OutputSink, Record};
+    use rusqlite::types::Value as SqlValue;
+    use rusqlite::Connection;
+    use serde_json::Value;
+    use std::fs::{self, File};
+    use std::io::{self, BufWriter};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_DB_ID: AtomicUsize = AtomicUsize::new(0);
+
+    fn sql_type(value: &Value) -> &'static str {
+        match value {
+            Value::Bool(_) => "INTEGER",
+            Value::Number(n) if n.is_i64() => "INTEGER",
+            Value::Number(_) => "REAL",
+            _ => "TEXT",
+        }
+    }
+
+    /// Column value, nested values are stored as json text
+    fn sql_value(value: &Value) -> SqlValue {
+        match value {
+            Value::Null => SqlValue::Null,
+            Value::Bool(b) => SqlValue::Integer(*b as i64),
+            Value::Number(n) => match n.as_i64() {
+                Some(int) => SqlValue::Integer(int),
+                None => SqlValue::Real(n.as_f64().unwrap()),
+            },
+            Value::String(s) => SqlValue::Text(s.clone()),
+            other => SqlValue::Text(other.to_string()),
+        }
+    }
+
+    fn quote_identifier(name: &str) -> String {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+
+    /// SQLite database with a `samples` table, which is built in a temporary database and copied
+    /// into the output when finished
+    pub struct SqliteWriter {
+        out: BufWriter<OutputSink>,
+        path: PathBuf,
+        conn: Option<Connection>,
+        insert: Option<String>,
+    }
+
+    impl SqliteWriter {
+        pub fn new(out: BufWriter<OutputSink>) -> Self {
+            let id = NEXT_DB_ID.fetch_add(1, Ordering::Relaxed);
+            let name = format!("sparser-{}-{}.sqlite", std::process::id(), id);
+            SqliteWriter {
+                out,
+                path: std::env::temp_dir().join(name),
+                conn: None,
+                insert: None,
+            }
+        }
+
+        fn create_table(&mut self, record: &Record) -> rusqlite::Result<()> {
+            let _ = fs::remove_file(&self.path);
+            let conn = Connection::open(&self.path)?;
+            let columns: Vec<String> = record
+                .columns
+                .iter()
+                .zip(&record.values)
+                .map(|(column, value)| format!("{} {}", quote_identifier(column), sql_type(value)))
+                .collect();
+            conn.execute_batch(&format!(
+                "PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF; \
+                 CREATE TABLE samples ({}); BEGIN;",
+                columns.join(", ")
+            ))?;
+            let placeholders: Vec<String> = (1..=record.columns.len())
+                .map(|i| format!("?{}", i))
+                .collect();
+            self.insert = Some(format!(
+                "INSERT INTO samples VALUES ({})",
+                placeholders.join(", ")
+            ));
+            self.conn = Some(conn);
+            Ok(())
+        }
+    }
+
+    impl DatasetWriter for SqliteWriter {
+        fn write_record(&mut self, record: &Record) -> io::Result<()> {
+            if self.conn.is_none() {
+                self.create_table(record).map_err(io::Error::other)?;
+            }
+            let conn = self.conn.as_ref().unwrap();
+            let mut statement = conn
+                .prepare_cached(self.insert.as_ref().unwrap())
+                .map_err(io::Error::other)?;
+            statement
+                .execute(rusqlite::params_from_iter(
+                    record.values.iter().map(sql_value),
+                ))
+                .map_err(io::Error::other)?;
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> io::Result<BufWriter<OutputSink>> {
+            // a shard without records is written as an empty file
+            if let Some(conn) = self.conn.take() {
+                conn.execute_batch("COMMIT;").map_err(io::Error::other)?;
+                conn.close().map_err(|(_, e)| io::Error::other(e))?;
+                io::copy(&mut File::open(&self.path)?, &mut self.out)?;
+                fs::remove_file(&self.path)?;
+            }
+            Ok(self.out)
+        }
+    }
+}